@@ -0,0 +1,2639 @@
+//! # A Simple Public-Key Implementation
+//!
+//! IMPORTANT - This should not be used for anything important!
+//! The goal of this program is to teach you the basics of public-key cryptography
+//! but it has not been audited or protected against other attacks.  Additionally,
+//! it is trivially crackable due to the relatively small number of possible
+//! keys and does not use industrial-strength randomization.  When using cryptography for
+//! any professional purpose, follow this cardinal rule - "don't roll your own crypto!"  
+//!
+//! ## Overview
+//!
+//! Although most people think of public-key cryptography as simply encrypting
+//! communications, it can also be used to prove that a particular identity
+//! wrote something.  As long as the private key is not shared with anybody,
+//! then only the person who owns the private key will able to reliably sign messages
+//! with it.  This is done in a three-step process, detailed below.
+//!
+//! ### Key Generation
+//!
+//! Two keys, a public key and a private key, are produced.  These keys are
+//! mathematically linked in a way that we will see in the algorithm.  As their names
+//! imply, the public key should be publicly shared and the private key must
+//! be kept private.
+//!
+//! ### Signing
+//!
+//! Using the message as input, you "sign" it with your private key.  This will
+//! produce a number that can prove that you wrote the message.
+//!
+//! ### Verifying
+//!
+//! Using the message and the produced signature, anybody can prove that you
+//! generated that signature, without knowing the private key, using only
+//! the public key.
+//!
+//! ### Example and Walkthrough
+//!
+//! ```text
+//! $ cargo run generate
+//! Private key: 902962279, 278653459
+//! Public key: 902962279, 291642999
+//! 
+//! $ cargo run sign meow 902962279 278653459
+//! Signature: 124665060
+//! 
+//! $ cargo run verify meow 124665060 902962279 291642999
+//! Signature verified!
+//! 
+//! $ cargo run verify meow 111111 902962279 291642999
+//! SIGNATURE INVALID!
+//! Reason: the signature is of the hash 98485657, but the message's hash is 209457542
+//! ```
+//!
+//! We can see above that a person, let's call her Alice, has generated a
+//! keypair - the private key (902962279, 278653459) which she keeps secret,
+//! and a public key (902962279, 291642999) which she publicizes widely to
+//! anyone who wants to communicate with her.
+//!
+//! Alice would like to prove to the world that she wrote the message "meow".
+//! Perhaps she insists that she is a cat, or this is a game like
+//! rock-paper-scissors.  She can publish "meow" with the signature "124665060"
+//! and anyone can look up her public key and prove that she (or at least
+//! someone with her private key) wrote it.
+//!
+//! There are many other uses for signatures in blockchain technology,
+//! many of which will be covered later in the course.
+//!
+//! ## RSA Algorithm
+//!
+//! This is a relatively straightforward implementation of the paper
+//! ["A Method for Obtaining Digital Signatures and Public-Key Cryptosystems"
+//! by Rivest, Shamir and Adleman]([https://people.csail.mit.edu/rivest/Rsapaper.pdf]).
+//!
+//! If some of the terminology seems strange, don't worry, we will go through
+//! it step by step in the functions.
+//!
+//! 1. Choose two different prime numbers, p and q.
+//! 2. Compute n = p * q.
+//! 3. Compute the Carmichael's totient function (lcm(p - 1, q - 1) to get t.
+//! 4. Choose an integer, e, where 1 < e < t and e is coprime to t.
+//! 5. Compute the modular multiplicative inverse of e mod t to get d.
+//! 6. The public key is (n, e).  The private key is (n, d).
+//!
+//! To sign a message, we will take a hash of the message and raise it
+//! to the power of d modulo n.  To verify a message, we will take the
+//! signature and raise it to the power of e modulo n to get a value r.
+//! If r == h modulo n, then the signatures match and it is valid; otherwise
+//! invalid.
+//!
+//! ## Using this as a library
+//!
+//! Everything above is available from this crate, so that other programs
+//! (and integration tests) can generate keys, sign and verify without going
+//! through the command line - e.g.
+//!
+//! ```
+//! use public_key_solution::{generate_key_pair, sign_message, verify_signature};
+//!
+//! let keys = generate_key_pair(&mut rand::thread_rng());
+//! let sig = sign_message("meow".to_string(), keys.modulus, keys.private_exp);
+//! assert!(verify_signature("meow".to_string(), sig, keys.modulus, keys.public_exp));
+//! ```
+
+// External crates that we use for a mathematical functions dealing
+// with large integers - quite common in cryptography.
+
+extern crate num_bigint;
+extern crate num_traits;
+
+use num::integer::Integer;
+use num_bigint::{BigInt, BigUint};
+use num_traits::cast::{FromPrimitive, ToPrimitive};
+use num_traits::{One, Zero};
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+use rand::prelude::*;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+// Our two keys can not be higher than this value
+// This makes cracking the code relatively simple, but frees us
+// from having to use BigUint everywhere for multiplication!
+pub const MAX_KEY_VAL: u32 = 65536;
+
+// The smallest value either of our two primes can take.  2 is left out, so
+// that both primes are odd.
+pub const MIN_KEY_VAL: u32 = 3;
+
+// A modulus with a prime factor smaller than this is considered weak,
+// since it can be factored almost instantly by trial division.
+pub const SMALL_FACTOR_BOUND: u32 = 1000;
+
+// The witnesses tried by the Miller-Rabin test, in order.  Each one that a
+// composite number gets past makes it less likely to really be composite.
+// The first five are enough to get every 32-bit number right, and all
+// twelve are enough for every 64-bit number.
+pub const MILLER_RABIN_WITNESSES: [u32; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+
+// Below this, the first nine Miller-Rabin witnesses (2 to 23) are enough
+// to get every number right.
+pub const MR_DETERMINISTIC_BOUND: u64 = 3_825_123_056_546_413_051;
+
+// How many Miller-Rabin witnesses to use when generating keys, unless
+// asked for something else.
+pub const DEFAULT_PRIME_ROUNDS: usize = 5;
+
+// The smallest and largest modulus sizes (in bits) keyspace-attack will
+// generate and crack.  Every modulus must fit in a u32.
+pub const MIN_ATTACK_BITS: u32 = 8;
+pub const MAX_ATTACK_BITS: u32 = 32;
+
+// When counting how many primes there are of a given size, we use a sieve
+// to count them exactly up to this many bits, and estimate them above it.
+pub const SIEVE_BITS: u32 = 24;
+
+// An RSA key pair.  The modulus is shared between the public and private
+// keys, so the private key is (modulus, private_exp) and the public key is
+// (modulus, public_exp).  Messages are signed with the private key and
+// verified with the public key.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyPair {
+    pub modulus: u32,
+    pub public_exp: u32,
+    pub private_exp: u32,
+}
+
+// The sign and verify commands only ever have one half of a key pair, so
+// the program itself does not use these, but they make a KeyPair much
+// easier to use from other code.
+impl KeyPair {
+    // The public key, as (modulus, public exponent)
+    pub fn public_key(&self) -> (u32, u32) {
+        (self.modulus, self.public_exp)
+    }
+
+    // Sign a message with the private key
+    pub fn sign(&self, msg: String) -> Signature {
+        Signature(sign_message(msg, self.modulus, self.private_exp))
+    }
+
+    // Check a signature of a message with the public key
+    pub fn verify(&self, msg: String, sig: Signature) -> bool {
+        sig.verify(msg, self.public_key())
+    }
+}
+
+// Everything worked out while generating a key pair, not just the key pair
+// itself - the two primes and the totient are normally thrown away, but
+// seeing them all together shows how the key was put together.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyComponents {
+    pub p: u32,
+    pub q: u32,
+    pub modulus: u32,
+    pub totient: u32,
+    pub private_exp: u32,
+    pub public_exp: u32,
+}
+
+impl KeyComponents {
+    // Just the key pair
+    pub fn key_pair(&self) -> KeyPair {
+        KeyPair { modulus: self.modulus, public_exp: self.public_exp, private_exp: self.private_exp }
+    }
+}
+
+// A signature of a message.  This is just a number, but wrapping it up in
+// its own type means that it cannot be mixed up with the other numbers
+// (moduli, exponents and hashes) which are passed around.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Signature(pub u32);
+
+impl Signature {
+    // Check that this is a signature of `msg` for the public key
+    // `pub_key`, which is (modulus, public exponent)
+    pub fn verify(&self, msg: String, pub_key: (u32, u32)) -> bool {
+        verify_signature(msg, self.0, pub_key.0, pub_key.1)
+    }
+}
+
+// ****************************************************************
+// Helper functions
+// ****************************************************************
+
+
+/// Check primality of a given unsigned integer
+/// This is a pretty straightforward implementation of the `6k +/- 1` trial
+/// division primality test described
+/// [here](https://en.wikipedia.org/wiki/Primality_test#Simple_methods).
+
+pub fn is_prime(n: u32) -> bool {
+    if n <= 3 {
+        return n > 1;
+    } else if n % 2 == 0 || n % 3 == 0 {
+        return false;
+    }
+
+    let mut i = 5;
+
+    while i * i <= n {
+        if n % i == 0 || n % (i + 2) == 0 {
+            return false;
+        }
+        i += 6;
+    }
+                
+    true
+        
+}
+
+/// Check whether n is a probable prime, using the first `rounds` of the
+/// Miller-Rabin witnesses.  Write n - 1 as d * 2^s with d odd; for a
+/// prime n, every witness a has a^d = 1 or a^(d * 2^r) = -1 (mod n) for
+/// some r < s.  A composite number will fail this for most witnesses.
+/// See [here](https://en.wikipedia.org/wiki/Miller%E2%80%93Rabin_primality_test).
+pub fn miller_rabin(n: u32, rounds: usize) -> bool {
+    match small_prime_check(n as u64) {
+        Some(result) => result,
+        None => miller_rabin_witnesses(n as u64,
+                                       MILLER_RABIN_WITNESSES.iter().take(rounds).map(|a| *a as u64)),
+    }
+}
+
+/// Check whether a 64-bit n is prime with Miller-Rabin.  Below
+/// MR_DETERMINISTIC_BOUND, the first nine witnesses are known to give the
+/// right answer for every n, so the answer is certain and `rounds` is
+/// ignored.  Above it, `rounds` random witnesses are tried instead - each
+/// one that a composite number gets past has at most a 1 in 4 chance of
+/// doing so, so the answer is only wrong with probability 4^-rounds.
+pub fn is_prime_mr(n: u64, rounds: u32) -> bool {
+    if let Some(result) = small_prime_check(n) {
+        return result;
+    }
+    if n < MR_DETERMINISTIC_BOUND {
+        miller_rabin_witnesses(n, MILLER_RABIN_WITNESSES.iter().take(9).map(|a| *a as u64))
+    } else {
+        let mut rng = rand::thread_rng();
+        miller_rabin_witnesses(n, (0..rounds).map(|_| rng.gen_range(2..n - 1)))
+    }
+}
+
+/// Settle whether n is prime by trial division by the witnesses themselves,
+/// if that is enough to tell - i.e. n is below 2, is one of the witnesses or
+/// is a multiple of one.  Otherwise returns None, and n is odd and bigger
+/// than every witness, as miller_rabin_witnesses() needs.
+pub fn small_prime_check(n: u64) -> Option<bool> {
+    if n < 2 {
+        return Some(false);
+    }
+    for &a in MILLER_RABIN_WITNESSES.iter() {
+        if n == a as u64 {
+            return Some(true);
+        }
+        if n.is_multiple_of(a as u64) {
+            return Some(false);
+        }
+    }
+    None
+}
+
+/// The Miller-Rabin test itself, trying each of `witnesses` in turn and
+/// returning false as soon as one shows that n is composite.
+pub fn miller_rabin_witnesses(n: u64, witnesses: impl Iterator<Item = u64>) -> bool {
+    let mut d = n - 1;
+    let mut s = 0;
+    while d.is_multiple_of(2) {
+        d /= 2;
+        s += 1;
+    }
+
+    // Multiply as u128s, since two numbers below n can overflow a u64
+    let mul_mod = |x: u64, y: u64| (x as u128 * y as u128 % n as u128) as u64;
+    let pow_mod = |mut base: u64, mut exp: u64| {
+        let mut result = 1;
+        while exp > 0 {
+            if exp % 2 == 1 {
+                result = mul_mod(result, base);
+            }
+            base = mul_mod(base, base);
+            exp /= 2;
+        }
+        result
+    };
+
+    'witness: for a in witnesses {
+        let mut x = pow_mod(a, d);
+        if x == 1 || x == n - 1 {
+            continue;
+        }
+        for _ in 1..s {
+            x = mul_mod(x, x);
+            if x == n - 1 {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+/// This function will return a random prime p, with min <= p < max.
+/// It does this by randomly generating an integer and testing if it's
+/// prime, using `rounds` rounds of Miller-Rabin.  There are definitely
+/// more efficient algorithms for this, but this is meant to be as simple
+/// as possible.
+/// 2 is never returned, even if it is in the range: with p = 2, p - 1 = 1
+/// adds nothing to the totient and the key is degenerate.
+/// Returns an error if the range is empty or has no primes in it, since
+/// then it would loop forever.
+pub fn get_random_prime(rng: &mut impl Rng, min: u32, max: u32, rounds: usize) -> Result<u32, String> {
+
+    if min >= max {
+        return Err(format!("the range {}..{} is empty", min, max));
+    }
+    // Leave 2 out of the range
+    let low = min.max(3);
+    if low >= max {
+        return Err(format!("there are no primes other than 2 from {} up to {}", min, max));
+    }
+    if !(low..max).any(is_prime) {
+        return Err(format!("there are no primes from {} up to {}", min, max));
+    }
+
+    // Generate a random unsigned integer in the range.
+    let mut p: u32; 
+
+    // Keep generating random numbers and putting them in `p` until
+    // the generated number is found to be prime.
+    // Note that Rust does not have a do...while equivalent, so this
+    // break statement may seem strange if you are coming from a different
+    // language.
+    loop {
+        
+        // Like the old gen_range(low, max), the upper bound is exclusive
+        p = rng.gen_range(low..max);
+
+        if miller_rabin(p, rounds) {
+            break;
+        }
+
+    }
+
+    // Return the last generated number, which should be prime
+    Ok(p)
+
+}
+
+// The same as get_random_prime(), but with `threads` threads all looking for
+// a prime at once.  Each thread keeps trying random numbers with its own
+// random number generator; the first one to find a prime sends it back
+// down the channel, and then all of the others are told to stop.
+// For the tiny primes in this program, starting the threads takes longer
+// than finding a prime - this only pays off when each candidate is slow to
+// test.
+
+pub fn get_random_prime_parallel(threads: usize, rounds: usize) -> u32 {
+    let stop = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel();
+
+    let workers: Vec<_> = (0..threads.max(1)).map(|_| {
+        let stop = Arc::clone(&stop);
+        let sender = sender.clone();
+        thread::spawn(move || {
+            let mut rng = rand::thread_rng();
+            while !stop.load(Ordering::Relaxed) {
+                let p = rng.gen_range(MIN_KEY_VAL..MAX_KEY_VAL);
+                if miller_rabin(p, rounds) {
+                    // Only the first prime is received - it does not
+                    // matter if the others can not be sent
+                    let _ = sender.send(p);
+                    break;
+                }
+            }
+        })
+    }).collect();
+
+    let p = receiver.recv().unwrap();
+    stop.store(true, Ordering::Relaxed);
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    p
+}
+
+// Look for a small prime factor of the modulus n by trial division, returning
+// the first one found (if any).  A good modulus is the product of two large
+// primes, so it should never have a small factor.
+
+pub fn find_small_factor(n: u32) -> Option<u32> {
+    (2..SMALL_FACTOR_BOUND).find(|f| *f < n && is_prime(*f) && n.is_multiple_of(*f))
+}
+
+// Factor a modulus n = p * q by trial division, trying every odd number up
+// to the square root of n.  Returns (p, q) with p <= q, or None if n is not
+// the product of two numbers (other than 1 and n).
+// This takes up to sqrt(n) = 2^(bits / 2) divisions, so every 2 extra bits
+// of modulus doubles the time it takes.  This is nothing for the tiny keys
+// in this program, but for a 2048-bit modulus it would be 2^1024 divisions!
+
+pub fn factor_modulus(n: u32) -> Option<(u32, u32)> {
+    factor_modulus_counting(n).0
+}
+
+// The same as factor_modulus(), but also return how many trial divisions
+// it took - to show how much work cracking a key is.
+
+pub fn factor_modulus_counting(n: u32) -> (Option<(u32, u32)>, u64) {
+    let mut divisions = 1;
+    if n > 2 && n.is_multiple_of(2) {
+        return (Some((2, n / 2)), divisions);
+    }
+    let mut f: u32 = 3;
+    while (f as u64) * (f as u64) <= n as u64 {
+        divisions += 1;
+        if n.is_multiple_of(f) {
+            return (Some((f, n / f)), divisions);
+        }
+        f += 2;
+    }
+    (None, divisions)
+}
+
+// Crack an RSA key, given only its public half (modulus and public
+// exponent): factor the modulus, then work out the other exponent exactly
+// like generate_key_pair() did.  Returns the full key pair.
+// This assumes the key was made with Carmichael's totient (the default).
+
+pub fn crack_key(modulus: u32, public_exp: u32) -> Option<KeyPair> {
+    let (p, q) = factor_modulus(modulus)?;
+    let private_exp = mmi(public_exp, carmichael_totient(p, q))?;
+    Some(KeyPair { modulus, public_exp, private_exp })
+}
+
+// Check whether a modulus n looks weak, returning a warning explaining why
+// if it does.  This catches moduli with small factors, as well as moduli
+// which are themselves prime (and so are not the product of two primes at all).
+// Passing this check does not make a modulus secure - every modulus in this
+// program is small enough to be factored quickly!
+
+pub fn check_modulus(n: u32) -> Option<String> {
+    if is_prime_mr(n as u64, DEFAULT_PRIME_ROUNDS as u32) {
+        Some(format!("Warning: modulus {} is prime, not a product of two primes", n))
+    } else {
+        find_small_factor(n).map(|f| format!("Warning: modulus {} is weak - it has the small factor {}", n, f))
+    }
+}
+
+// Check that a public exponent e can really be part of a key with modulus n,
+// returning a warning if not.  e must be between 1 and n (exclusive) - e = 1
+// "signs" every hash as itself.  If n can be factored into two primes, e must
+// also be coprime to Carmichael's totient of n, or there is no private
+// exponent to go with it and no signature will ever verify.
+
+pub fn check_public_exponent(n: u32, e: u32) -> Option<String> {
+    if e <= 1 || e >= n {
+        return Some(format!("Warning: public exponent {} must be greater than 1 and less than the modulus {}", e, n));
+    }
+    match factor_modulus(n) {
+        Some((p, q)) if is_prime(q) => {
+            let totient = carmichael_totient(p, q);
+            let g = e.gcd(&totient);
+            if g == 1 {
+                None
+            } else {
+                Some(format!("Warning: public exponent {} shares the factor {} with the totient {}, \
+                              so no private key goes with it", e, g, totient))
+            }
+        },
+        _ => None,
+    }
+}
+
+// Return all of the primes less than `limit`, using the Sieve of Eratosthenes.
+// This starts with a list of every number, then crosses out every multiple
+// of 2, every multiple of 3, and so on - whatever is left must be prime.
+// See https://en.wikipedia.org/wiki/Sieve_of_Eratosthenes
+
+pub fn sieve_primes(limit: u32) -> Vec<u32> {
+    let limit = limit as usize;
+    let mut is_candidate = vec![true; limit];
+    let mut primes = Vec::new();
+
+    for n in 2..limit {
+        if is_candidate[n] {
+            primes.push(n as u32);
+            let mut multiple = n * n;
+            while multiple < limit {
+                is_candidate[multiple] = false;
+                multiple += n;
+            }
+        }
+    }
+    primes
+}
+
+// Count exactly how many primes could be chosen for p or q if each can have
+// at most `prime_bits` bits, using the sieve.  Like get_random_prime(), this
+// does not count 2.  Returns None if there are too many bits to sieve.
+
+pub fn count_key_primes(prime_bits: u32) -> Option<u64> {
+    if prime_bits > SIEVE_BITS {
+        None
+    } else {
+        let primes = sieve_primes(1 << prime_bits);
+        Some(primes.len().saturating_sub(1) as u64)
+    }
+}
+
+// Estimate how many primes could be chosen for p or q if each can have at
+// most `prime_bits` bits, as a power of 2.  The prime number theorem says
+// there are roughly x / ln(x) primes below x.  With x = 2^bits, that works
+// out as 2^(bits - log2(bits * ln(2))) - working with the power of 2 means
+// we never have to store a number as big as 2^bits.
+
+pub fn log2_estimate_key_primes(prime_bits: u32) -> f64 {
+    let bits = prime_bits as f64;
+    bits - (bits * 2f64.ln()).log2()
+}
+
+// Give a rough idea of how strong an RSA key with a modulus of this many
+// bits is.  The boundaries are loosely based on how big a modulus has been
+// publicly factored (829 bits, in 2020) and on current recommendations
+// (at least 2048 bits, or 3072 to stay safe for longer).  Every key this
+// program generates is 32 bits, which can be factored in well under a
+// second!
+
+pub fn strength_label(modulus_bits: u32) -> &'static str {
+    match modulus_bits {
+        0..=127 => "trivially crackable",
+        128..=511 => "very weak",
+        512..=1023 => "weak",
+        1024..=2047 => "legacy (no longer recommended)",
+        2048..=3071 => "acceptable",
+        _ => "strong",
+    }
+}
+
+// The number of bits in a modulus, i.e. not counting leading 0s
+
+pub fn modulus_bits(n: u32) -> u32 {
+    32 - n.leading_zeros()
+}
+
+// Check to see if two integers, x and y are coprime.  Numbers are coprime
+// if their only common factor is 1.  For example, 4 and 8 are not coprime
+// since they both contain 2 as one of their factors; 5 and 8 are coprime
+// since they do not share any factors.
+
+pub fn is_coprime(x: u32, y: u32) -> bool {
+    num::integer::gcd(x, y) == 1
+}
+
+// A simple implementation of the Carmichael's totient function
+pub fn carmichael_totient(x: u32, y: u32) -> u32 {
+    num::integer::lcm(x - 1, y - 1)
+}
+
+// A simple implementation of Euler's totient function, for a product of
+// two primes.  This is what the original RSA paper used; Carmichael's
+// totient always divides it, so exponents which work for Euler's totient
+// also work for Carmichael's, but Euler's is bigger than it needs to be.
+pub fn euler_totient(x: u32, y: u32) -> u32 {
+    (x - 1) * (y - 1)
+}
+
+// The extended Euclidean algorithm: as well as the greatest common divisor
+// g of a and b, find x and y such that a * x + b * y = g.  Euclid's
+// algorithm repeatedly replaces (a, b) with (b, a mod b) until b is 0;
+// this also keeps track of how to write each remainder as a sum of
+// multiples of a and b.  g is never negative.
+// The results are i128s, since they do not always fit in an i64 - the gcd
+// of i64::MIN and 0 is 2^63, one more than i64::MAX.  Working in i128s
+// also means nothing can overflow along the way (e.g. i64::MIN / -1).
+// See https://en.wikipedia.org/wiki/Extended_Euclidean_algorithm
+
+pub fn extended_gcd(a: i64, b: i64) -> (i128, i128, i128) {
+    // Each remainder r, with s and t such that a * s + b * t = r
+    let mut r = (a as i128, b as i128);
+    let mut s = (1, 0);
+    let mut t = (0, 1);
+
+    while r.1 != 0 {
+        let q = r.0 / r.1;
+        r = (r.1, r.0 - q * r.1);
+        s = (s.1, s.0 - q * s.1);
+        t = (t.1, t.0 - q * t.1);
+    }
+
+    if r.0 < 0 {
+        (-r.0, -s.0, -t.0)
+    } else {
+        (r.0, s.0, t.0)
+    }
+}
+
+// Modular multiplicative inverse, using the extended Euclidean algorithm:
+// if a * x + m * y = 1, then a * x = 1 modulo m, so x is the inverse.
+// a only has an inverse modulo m if they are coprime, so this returns None
+// if they are not (e.g. 4 has no inverse modulo 8, since 4 * x is always
+// 0 or 4 modulo 8).
+
+pub fn mmi(a_unsigned: u32, m_unsigned: u32) -> Option<u32> {
+
+    // Generally, we have been using unsigned integers but we
+    // need signed for this algorithm.
+    let a: i64 = a_unsigned as i64;
+    let m: i64 = m_unsigned as i64;
+
+    if m == 0 {
+        return None;
+    }
+
+    // g is gcd(a, m) - if it is not 1, there is no inverse
+    let (g, x, _) = extended_gcd(a, m);
+    if g != 1 {
+        return None;
+    }
+
+    // x can be negative, but we want the inverse between 0 and m - 1
+    Some(x.rem_euclid(m as i128) as u32)
+}
+
+// Given any object, return its 32-bit hash.  A hash is simply a fixed
+// size representation of an arbitrary amount of data.  For example,
+// a simple hash function might be to take all of the letters of a string,
+// add up their ASCII values, and return the result modulo 10.  No matter
+// what the size of the input, the ouput will always be one digit (0 - 9).
+// Another exercise in this course will delve deeply into hash functions.
+
+pub fn get_hash<T: Hash + ?Sized>(t: &T) -> u32 {
+    let mut s = DefaultHasher::new();
+    t.hash(&mut s);
+    let r = s.finish();
+    r as u32
+}
+
+// Which hash function to sign messages with.  get_hash() uses Rust's
+// DefaultHasher, whose algorithm is not guaranteed to stay the same between
+// versions of Rust - so a signature made by a program built with one version
+// might not verify with another.  SHA-256 is a fixed standard, so its
+// signatures will always verify, wherever they are checked.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum HashFunction {
+    /// get_hash() - Rust's DefaultHasher (the default, so that existing
+    /// signatures still verify)
+    #[default]
+    Default,
+    /// get_hash_sha256() - SHA-256, truncated to 32 bits
+    Sha256,
+}
+
+// Return a 32-bit hash of some bytes using SHA-256.  SHA-256 produces 256
+// bits, which is far too big for our moduli, so this just keeps the first
+// 32 bits (as a big-endian number) - like get_hash(), it is then reduced
+// modulo the modulus when signing.
+
+pub fn get_hash_sha256(data: &[u8]) -> u32 {
+    let mut hasher = Sha256::new();
+    hasher.input(data);
+    let mut digest = [0u8; 32];
+    hasher.result(&mut digest);
+    u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]])
+}
+
+// A helper function which might be useful for testing your key pair
+// generation - if `(d * e) % n == 1` does not hold, the key pair is
+// not valid.
+// The function attribute `#[allow(dead_code)]` is because under normal
+// cirumstances, this code will not be used - it's merely for debugging help.
+// But we don't really need a warning about this every time we compile -
+// this turns that off.
+
+#[allow(dead_code)]
+pub fn check_vals(d: u32, e: u32, n: u32) {
+    let bd = BigUint::from(d);
+    let be = BigUint::from(e);
+    let bn = BigUint::from(n);
+    let one = BigUint::from(1 as u32);
+    if (bd * be) % bn != one {
+        panic!("Error: (d * e) % n != 1");
+    } else {
+        println!("all is good");
+    }
+}
+
+// Raise x to the power of y modulo z and return the result.
+// This works for any unsigned integer type (u32 for the keys here, but
+// u64 too, for when the keys get wider).
+
+pub fn raise_power_modulo<T: Into<BigUint> + FromPrimitive>(x: T, y: T, z: T) -> T {
+    // Internally convert to biguints, simply to take advantage of
+    // the built-in modpow() function.  The squaring and multiplying
+    // happens in a BigUint, so it can never overflow, however wide x is.
+    let xb: BigUint = x.into();
+    let yb: BigUint = y.into();
+    let zb: BigUint = z.into();
+
+    let r = xb.modpow(&yb, &zb);
+
+    // r is smaller than z, so it always fits back into a T
+    r.to_u128()
+        .and_then(T::from_u128)
+        .expect("the result is smaller than the modulus")
+
+}
+
+/// How long each phase of generating a key pair took
+pub struct KeygenProfile {
+    pub primes: Duration,
+    pub private_exponent: Duration,
+    pub public_exponent: Duration,
+}
+
+/// Which totient function to use when generating keys
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Totient {
+    /// Carmichael's totient, lcm(p - 1, q - 1) (the default)
+    #[default]
+    Carmichael,
+    /// Euler's totient, (p - 1) * (q - 1)
+    Euler,
+}
+
+// ****************************************************************
+// WORK STARTS HERE
+// ****************************************************************
+
+// Given a random number generator, produce two distinct pseudorandom primes.
+
+pub fn generate_two_primes(mut rng: &mut impl Rng, rounds: usize) -> (u32, u32) {
+
+    // TODO 1
+    
+    let mut p;
+    let mut q;
+
+    // Generally this loop should not execute more than once, but on the
+    // off chance that we generate the same prime twice, we loop until
+    // they are distinct.
+    loop {
+
+        // Step 1: Generate two random primes for p and q
+        //         Hint: the get_random_prime() function might be useful
+        
+        p = get_random_prime(&mut rng, MIN_KEY_VAL, MAX_KEY_VAL, rounds)
+            .expect("there are primes below MAX_KEY_VAL");
+        q = get_random_prime(&mut rng, MIN_KEY_VAL, MAX_KEY_VAL, rounds)
+            .expect("there are primes below MAX_KEY_VAL");
+
+        // Step 2: Break out of the loop if p and q are distinct (i.e.
+        //         not the same)
+        if p != q {
+            break;
+        }
+    }
+
+    // Step 3: Return p and q as a tuple
+    (p, q)
+}
+
+
+
+// We need to pick a private exponent which is greater than 1 and less than
+// c, and is coprime with c.  This can be pseudorandomly generated via the
+// random number generator, rng, passed in via argumemt.
+
+pub fn choose_private_exponent(c: u32, rng: &mut impl Rng) -> u32 {
+
+    // TODO 2
+    
+    let mut p;
+    
+    loop {
+        // Step 1: Generate a random integer betwen 2 and c
+        //         (c itself is never generated)
+        p = rng.gen_range(2..c);
+        
+        // Step 2: If the generated integer and c are coprime, break
+        //         out of the loop
+        if is_coprime(p, c) {
+            break;
+        }
+    }
+
+    // Step 3: Return the gen
+    p
+
+}
+
+// The public exponent is simply the multiplicative inverse of e modulo n.
+// This is None if e and n are not coprime, since then there is no inverse.
+
+pub fn compute_public_exponent(e: u32, n: u32) -> Option<u32> {
+
+    // TODO 3
+
+    // Step 1: Generate and return the multiplicative inverse of e modulo n.
+    //         Hint: the mmi() function might be useful here.
+    mmi(e, n)
+}
+
+
+// Given a random number generator rng, return a keypair.  This keypair will
+// consist of a modulus, a private exponent, and a public exponent.
+// Since the modulus is shared between public and private keys, there is no
+// no need to send it back twice.
+
+pub fn generate_key_pair(rng: &mut impl Rng) -> KeyPair {
+    generate_key_pair_with(rng, Totient::Carmichael, DEFAULT_PRIME_ROUNDS)
+}
+
+// Same as generate_key_pair(), but using the given totient function and
+// number of Miller-Rabin rounds.  Both totients produce valid keys, but
+// different ones.
+
+pub fn generate_key_pair_with(rng: &mut impl Rng, totient: Totient,
+                          rounds: usize) -> KeyPair {
+    generate_key_components(rng, totient, rounds).key_pair()
+}
+
+// Same as generate_key_pair_with(), but return all of the values worked
+// out along the way, including the primes and the totient.
+
+pub fn generate_key_components(mut rng: &mut impl Rng, totient: Totient,
+                               rounds: usize) -> KeyComponents {
+
+    // TODO 4
+    
+    // Step 1: Choose two distinct prime numbers, p and q.
+    //         I recommend you work on TODO 1 before this.
+    let (p, q) = generate_two_primes(&mut rng, rounds);
+    // get_random_prime() never returns 2, which would make the key
+    // degenerate
+    assert!(p > 2 && q > 2, "the primes must both be greater than 2");
+
+    // Step 2: Compute m = p * q (will be the modulus)
+    let m = p * q;
+
+    // Step 3: Compute n = Carmichael's totient function of p, q
+    //         Carmichael's Totient is simply lcm(p - 1, q - 1) - I have
+    //         included a helper function, carmichael_totient(), for you.
+    //         (Euler's totient can be used instead if asked for.)
+    let n = match totient {
+        Totient::Carmichael => carmichael_totient(p, q),
+        Totient::Euler => euler_totient(p, q),
+    };
+    
+    // Step 4: Choose some e which is coprime to n and 1 < e < n
+    //         I recommend you work on TODO 2 before this.
+    let e = choose_private_exponent(n, &mut rng);
+    
+    // Step 5: Compute the modular multiplicative inverse for d
+    //           I recommend you work on TODO 3 before this.
+    let d = compute_public_exponent(e, n).expect("e is chosen to be coprime to n");
+
+    // DEBUG: Perform a sanity check before returning.
+    //         Verify that d * e = 1 modulo n.
+    //         If it does not, panic!
+    // If your code works, this is superfluous, but may be useful for
+    // testing.  Uncomment the next line to turn this check on.
+    // check_vals(d, e, n);
+    
+    // Return a KeyComponents with the following elements:
+    // 1. The primes (p and q)
+    // 2. Modulus (m)
+    // 3. Totient (n)
+    // 4. Private Exponent (e)
+    // 5. Public Exponent (d)
+    KeyComponents { p, q, modulus: m, totient: n, private_exp: e, public_exp: d }
+}
+
+
+// Same as generate_key_pair_with(), but finding the primes with
+// get_random_prime_parallel() using `threads` threads.  The primes come
+// from each thread's own random number generator, so only the private
+// exponent comes from `rng`.
+
+pub fn generate_key_pair_threaded(rng: &mut impl Rng, totient: Totient,
+                                  rounds: usize, threads: usize) -> KeyPair {
+    let (p, q) = loop {
+        let p = get_random_prime_parallel(threads, rounds);
+        let q = get_random_prime_parallel(threads, rounds);
+        if p != q {
+            break (p, q);
+        }
+    };
+    let n = match totient {
+        Totient::Carmichael => carmichael_totient(p, q),
+        Totient::Euler => euler_totient(p, q),
+    };
+    let e = choose_private_exponent(n, rng);
+    let d = compute_public_exponent(e, n).expect("e is chosen to be coprime to n");
+    KeyPair { modulus: p * q, private_exp: e, public_exp: d }
+}
+
+// Same as generate_key_pair_with(), but also time each of the steps.  This
+// shows that nearly all of the time goes into finding the primes - the
+// exponents only need a few gcd()s and one modular inverse.
+// This is kept separate so that normal key generation has no timers in it.
+
+pub fn generate_key_pair_profiled(rng: &mut impl Rng, totient: Totient,
+                              rounds: usize) -> (KeyPair, KeygenProfile) {
+    let start = Instant::now();
+    let (p, q) = generate_two_primes(rng, rounds);
+    let primes = start.elapsed();
+
+    let m = p * q;
+    let n = match totient {
+        Totient::Carmichael => carmichael_totient(p, q),
+        Totient::Euler => euler_totient(p, q),
+    };
+
+    let start = Instant::now();
+    let e = choose_private_exponent(n, rng);
+    let private_exponent = start.elapsed();
+
+    let start = Instant::now();
+    let d = compute_public_exponent(e, n).expect("e is chosen to be coprime to n");
+    let public_exponent = start.elapsed();
+
+    (KeyPair { modulus: m, private_exp: e, public_exp: d },
+     KeygenProfile { primes, private_exponent, public_exponent })
+}
+
+// Generate a key pair whose modulus has about `bits` bits (it may have one
+// less), by picking primes with half of the bits each.
+
+pub fn generate_key_pair_bits(rng: &mut impl Rng, bits: u32) -> KeyPair {
+    let random_prime = |rng: &mut dyn RngCore, prime_bits: u32| loop {
+        let p = rng.gen_range((1 << (prime_bits - 1))..(1 << prime_bits));
+        if is_prime_mr(p as u64, DEFAULT_PRIME_ROUNDS as u32) {
+            return p;
+        }
+    };
+    let (p, q) = loop {
+        let p = random_prime(rng, bits / 2);
+        let q = random_prime(rng, bits - bits / 2);
+        if p != q {
+            break (p, q);
+        }
+    };
+    let t = carmichael_totient(p, q);
+    let e = choose_private_exponent(t, rng);
+    KeyPair { modulus: p * q, private_exp: e, public_exp: compute_public_exponent(e, t).expect("e is chosen to be coprime to t") }
+}
+
+// Generate a key with a modulus of about `bits` bits and crack it from its
+// public half, returning the key, the cracked key and how long cracking took.
+
+pub fn keyspace_attack(rng: &mut impl Rng, bits: u32) -> (KeyPair, Option<KeyPair>, Duration) {
+    let keys = generate_key_pair_bits(rng, bits);
+    let start = Instant::now();
+    let cracked = crack_key(keys.modulus, keys.public_exp);
+    (keys, cracked, start.elapsed())
+}
+
+// ****************************************************************
+// Big keys
+// ****************************************************************
+
+// Everything above uses u32s, which keeps the code simple but means every
+// key can be cracked in a fraction of a second.  These functions do the
+// same things with BigUints, so that keys can be as big as you like (e.g.
+// `generate --bits 2048`).  They follow the u32 versions step by step.
+
+// The smallest and largest moduli (in bits) `generate --bits` will make
+pub const MIN_BIG_KEY_BITS: u64 = 16;
+pub const MAX_BIG_KEY_BITS: u64 = 4096;
+
+// An RSA key pair with BigUint values - see KeyPair.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BigKeyPair {
+    pub modulus: BigUint,
+    pub public_exp: BigUint,
+    pub private_exp: BigUint,
+}
+
+// A random number with exactly `bits` bits (i.e. the top bit is set).
+
+pub fn random_biguint_bits(rng: &mut impl Rng, bits: u64) -> BigUint {
+    let mut bytes = vec![0u8; bits.div_ceil(8) as usize];
+    rng.fill_bytes(&mut bytes);
+    let n = BigUint::from_bytes_be(&bytes) >> (bytes.len() * 8 - bits as usize);
+    n | (BigUint::one() << (bits as usize - 1))
+}
+
+// A random number from `low` up to (but not including) `high`.
+
+pub fn random_biguint_range(rng: &mut impl Rng, low: &BigUint, high: &BigUint) -> BigUint {
+    let range = high - low;
+    // Take 64 extra bits, so that the remainder is very close to uniform
+    let n = random_biguint_bits(rng, range.bits() as u64 + 64);
+    low + n % range
+}
+
+// Check whether n is a probable prime with `rounds` rounds of Miller-Rabin
+// using random witnesses (see miller_rabin()).
+
+pub fn is_prime_big(n: &BigUint, rounds: usize, rng: &mut impl Rng) -> bool {
+    let two = BigUint::from(2u32);
+    if *n < two {
+        return false;
+    }
+    for &a in MILLER_RABIN_WITNESSES.iter() {
+        let a = BigUint::from(a);
+        if *n == a {
+            return true;
+        }
+        if (n % &a).is_zero() {
+            return false;
+        }
+    }
+
+    let n_minus_one = n - 1u32;
+    let mut d = n_minus_one.clone();
+    let mut s = 0;
+    while d.is_even() {
+        d >>= 1;
+        s += 1;
+    }
+
+    'witness: for _ in 0..rounds {
+        let a = random_biguint_range(rng, &two, &n_minus_one);
+        let mut x = a.modpow(&d, n);
+        if x.is_one() || x == n_minus_one {
+            continue;
+        }
+        for _ in 1..s {
+            x = x.modpow(&two, n);
+            if x == n_minus_one {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+// A random prime with exactly `bits` bits - see get_random_prime().  The
+// top two bits are both set, so that the product of two of these always
+// has exactly as many bits as the two primes put together.
+
+pub fn get_random_big_prime(rng: &mut impl Rng, bits: u64) -> BigUint {
+    loop {
+        let p = random_biguint_bits(rng, bits) | (BigUint::one() << (bits as usize - 2));
+        if is_prime_big(&p, MILLER_RABIN_WITNESSES.len(), rng) {
+            return p;
+        }
+    }
+}
+
+// Choose a random private exponent between 2 and c which is coprime with
+// c - see choose_private_exponent().
+
+pub fn choose_private_exponent_big(c: &BigUint, rng: &mut impl Rng) -> BigUint {
+    let two = BigUint::from(2u32);
+    loop {
+        let p = random_biguint_range(rng, &two, c);
+        if p.gcd(c).is_one() {
+            return p;
+        }
+    }
+}
+
+// The multiplicative inverse of e modulo n, using the extended Euclidean
+// algorithm like mmi() - but with BigInts, since the working values can
+// go negative.
+
+pub fn compute_public_exponent_big(e: &BigUint, n: &BigUint) -> BigUint {
+    let m = BigInt::from(n.clone());
+    let mut mn = (m.clone(), BigInt::from(e.clone()));
+    let mut xy = (BigInt::zero(), BigInt::one());
+
+    while !mn.1.is_zero() {
+        let q = &mn.0 / &mn.1;
+        xy = (xy.1.clone(), xy.0 - &q * &xy.1);
+        mn = (mn.1.clone(), mn.0 % &mn.1);
+    }
+
+    xy.0.mod_floor(&m).to_biguint().unwrap()
+}
+
+// Generate a key pair whose modulus has about `bits` bits, from two primes
+// with half of the bits each - see generate_key_pair_with().
+
+pub fn generate_big_key_pair(rng: &mut impl Rng, bits: u64, totient: Totient) -> BigKeyPair {
+    let (p, q) = loop {
+        let p = get_random_big_prime(rng, bits / 2);
+        let q = get_random_big_prime(rng, bits - bits / 2);
+        if p != q {
+            break (p, q);
+        }
+    };
+    let m = &p * &q;
+    let (p1, q1) = (p - 1u32, q - 1u32);
+    let n = match totient {
+        Totient::Carmichael => p1.lcm(&q1),
+        Totient::Euler => p1 * q1,
+    };
+    let e = choose_private_exponent_big(&n, rng);
+    let d = compute_public_exponent_big(&e, &n);
+    BigKeyPair { modulus: m, private_exp: e, public_exp: d }
+}
+
+// Sign a message with a BigUint private key - see sign_message().
+
+pub fn sign_message_big<T: Hash + ?Sized>(msg: &T, priv_key_mod: &BigUint, priv_key_exp: &BigUint) -> BigUint {
+    sign_hash_big(get_hash(msg), priv_key_mod, priv_key_exp)
+}
+
+// Verify a signature with a BigUint public key - see verify_signature().
+
+pub fn verify_signature_big<T: Hash + ?Sized>(msg: &T, sig: &BigUint, pub_key_mod: &BigUint, pub_key_exp: &BigUint) -> bool {
+    verify_hash_big(get_hash(msg), sig, pub_key_mod, pub_key_exp)
+}
+
+// Sign a hash which has already been worked out with a BigUint private key
+// - see sign_hash().
+
+pub fn sign_hash_big(h: u32, priv_key_mod: &BigUint, priv_key_exp: &BigUint) -> BigUint {
+    (BigUint::from(h) % priv_key_mod).modpow(priv_key_exp, priv_key_mod)
+}
+
+// Verify a signature of a hash with a BigUint public key - see verify_hash().
+// Like verify_hash_detailed(), a modulus below 2 never verifies.
+
+pub fn verify_hash_big(h: u32, sig: &BigUint, pub_key_mod: &BigUint, pub_key_exp: &BigUint) -> bool {
+    *pub_key_mod >= BigUint::from(2u32)
+        && sig.modpow(pub_key_exp, pub_key_mod) == BigUint::from(h) % pub_key_mod
+}
+
+// Given a message, a private key modulus, and a private key exponent,
+// return its signature as a 32-bit unsigned integer.
+
+pub fn sign_message(msg: String, priv_key_mod: u32, priv_key_exp: u32) -> u32 {
+    // TODO 5
+    
+    // Step 1: Produce a hash value of the message.  Note that I have
+    // included a get_hash() function for you to use.  
+    let h = get_hash(&msg);
+    
+    // Step 2: Raise the hash to the power of the private key exponent, modulo the
+    // private key modulus (which is, of course, same as the public key modulus).
+    // Note that I have included a raise_power_modulo() function.
+    let r = raise_power_modulo(h, priv_key_exp, priv_key_mod);
+
+    // Step 3: Return the result of the previous operation
+    r
+    
+}
+
+// Sign a message just like sign_message(), but also return the hash of the
+// message as a (hash, signature) tuple.  This lets you check by hand that
+// raise_power_modulo(hash, priv_key_exp, priv_key_mod) really is the signature.
+
+pub fn sign_message_showing_hash(msg: String, priv_key_mod: u32, priv_key_exp: u32) -> (u32, u32) {
+    let h = get_hash(&msg);
+    (h, sign_message(msg, priv_key_mod, priv_key_exp))
+}
+
+// Write a key pair out in the format used by key files - one labelled
+// number per line, with the private exponent kept apart from the public key
+// (and marked as secret), followed by "end" so that a file which has been
+// cut short can be told apart from a complete one.
+
+pub fn format_key_file(keys: &KeyPair) -> String {
+    format!("# RSA key pair\n\
+             public modulus: {}\n\
+             public exponent: {}\n\
+             # PRIVATE - do not share the line below with anyone\n\
+             private exponent: {}\n\
+             end\n",
+            keys.modulus, keys.public_exp, keys.private_exp)
+}
+
+// Read a key pair written by format_key_file().  Lines starting with # are
+// comments, and every other line must be exactly as format_key_file()
+// writes it - including the final "end", so that a truncated file is never
+// read as a (wrong) key.
+
+pub fn parse_key_file(s: &str) -> Result<KeyPair, String> {
+    let mut lines = s.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#'));
+    let mut field = |label: &str| -> Result<u32, String> {
+        let line = lines.next().ok_or_else(|| format!("key file is truncated - missing {}", label))?;
+        line.strip_prefix(label)
+            .and_then(|rest| rest.strip_prefix(": "))
+            .ok_or_else(|| format!("expected {} in key file, found {:?}", label, line))?
+            .parse::<u32>()
+            .map_err(|_| format!("invalid {} in key file: {:?}", label, line))
+    };
+    let modulus = field("public modulus")?;
+    let public_exp = field("public exponent")?;
+    let private_exp = field("private exponent")?;
+    match lines.next() {
+        Some("end") => {},
+        _ => return Err("key file is truncated - missing end".to_string()),
+    }
+    if lines.next().is_some() {
+        return Err("unexpected data after end of key file".to_string());
+    }
+    Ok(KeyPair { modulus, public_exp, private_exp })
+}
+
+// Sign anything which can be hashed, not just a String - e.g. the raw bytes
+// of a file, which might not be valid UTF-8.  A String gives exactly the
+// same signature as sign_message() does.
+
+pub fn sign_data<T: Hash + ?Sized>(data: &T, priv_key_mod: u32, priv_key_exp: u32) -> u32 {
+    sign_hash(get_hash(data), priv_key_mod, priv_key_exp)
+}
+
+// Verify a signature made by sign_data() - see verify_signature().
+
+pub fn verify_data<T: Hash + ?Sized>(data: &T, sig: u32, pub_key_mod: u32, pub_key_exp: u32) -> bool {
+    verify_hash(get_hash(data), sig, pub_key_mod, pub_key_exp)
+}
+
+// Why a signature did not verify.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VerifyError {
+    /// Raising the signature to the public exponent gave `got`, but the
+    /// hash of the message (modulo the modulus) is `expected` - either the
+    /// message, the signature or the key is wrong
+    HashMismatch { expected: u32, got: u32 },
+    /// The modulus is 0 or 1, so it can not be part of a key
+    InvalidModulus,
+    /// The signature is not less than the modulus, so it can not have been
+    /// made with this key (every signature is a number modulo the modulus)
+    SignatureTooLarge,
+}
+
+impl std::fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            VerifyError::HashMismatch { expected, got } =>
+                write!(f, "the signature is of the hash {}, but the message's hash is {}", got, expected),
+            VerifyError::InvalidModulus => write!(f, "the modulus must be at least 2"),
+            VerifyError::SignatureTooLarge => write!(f, "the signature must be smaller than the modulus"),
+        }
+    }
+}
+
+// Verify a signature like verify_signature(), but say why it did not
+// verify instead of just returning false.
+
+pub fn verify_signature_detailed(msg: String, sig: u32, pub_key_mod: u32, pub_key_exp: u32) -> Result<(), VerifyError> {
+    verify_hash_detailed(get_hash(&msg), sig, pub_key_mod, pub_key_exp)
+}
+
+// Verify a signature of a hash which has already been worked out, saying
+// why it did not verify - see verify_signature_detailed().
+
+pub fn verify_hash_detailed(h: u32, sig: u32, pub_key_mod: u32, pub_key_exp: u32) -> Result<(), VerifyError> {
+    if pub_key_mod < 2 {
+        return Err(VerifyError::InvalidModulus);
+    }
+    if sig >= pub_key_mod {
+        return Err(VerifyError::SignatureTooLarge);
+    }
+    let expected = h % pub_key_mod;
+    let got = raise_power_modulo(sig, pub_key_exp, pub_key_mod);
+    if got == expected {
+        Ok(())
+    } else {
+        Err(VerifyError::HashMismatch { expected, got })
+    }
+}
+
+// Sign a hash which has already been worked out, with whichever hash
+// function - e.g. get_hash_sha256().  This is the second half of
+// sign_message().
+
+pub fn sign_hash(h: u32, priv_key_mod: u32, priv_key_exp: u32) -> u32 {
+    raise_power_modulo(h % priv_key_mod, priv_key_exp, priv_key_mod)
+}
+
+// Verify a signature of a hash which has already been worked out - the
+// second half of verify_signature().
+
+pub fn verify_hash(h: u32, sig: u32, pub_key_mod: u32, pub_key_exp: u32) -> bool {
+    raise_power_modulo(sig, pub_key_exp, pub_key_mod) == h % pub_key_mod
+}
+
+// Combine a message with a nonce ("number used once"), giving the data which
+// is actually signed.
+// Signing the same message with the same key always gives the same
+// signature, so anybody who has seen a signed message can "replay" it later
+// and it will still verify - e.g. a signed "pay Bob 5 billcoins" could be
+// sent again and again.  If every signature includes a different nonce
+// (such as the time it was signed), an old signature will not verify for a
+// new nonce, so the verifier can refuse any nonce it has already seen.
+// The nonce is all digits, so the ":" always marks where the message starts.
+
+pub fn nonced_message(msg: &str, nonce: u64) -> String {
+    format!("{}:{}", nonce, msg)
+}
+
+// Sign many messages with the same private key, one per line of `lines`,
+// returning each message with its signature.  Blank lines (including ones
+// which are only whitespace) are skipped.
+
+pub fn sign_batch<'a>(lines: impl Iterator<Item = &'a str>, priv_key_mod: u32,
+                      priv_key_exp: u32) -> Vec<(&'a str, u32)> {
+    lines.filter(|line| !line.trim().is_empty())
+        .map(|line| (line, sign_message(line.to_string(), priv_key_mod, priv_key_exp)))
+        .collect()
+}
+
+// Given a message and a list of signers, each of which is a (signature,
+// public key modulus, public key exponent) triple, check that every one of
+// them signed the message.  Returns the indices (into `signers`) of the
+// signers whose signatures did NOT verify - so the message was signed by
+// all of them only if this is empty.  A signer whose modulus is less than
+// 2 can not have signed anything, so it is always counted as invalid.
+// This is the simplest possible kind of "multi-signature": every signer
+// signs separately and the verifier checks each signature in turn.  Real
+// schemes (e.g. BLS or Schnorr/MuSig) can combine all the signatures into
+// one, which is much smaller and quicker to check.
+
+pub fn aggregate_verify(msg: &str, signers: &[(u32, u32, u32)]) -> Vec<usize> {
+    signers.iter()
+        .enumerate()
+        .filter(|(_, &(sig, n, e))| n < 2 || !verify_signature(msg.to_string(), sig, n, e))
+        .map(|(j, _)| j)
+        .collect()
+}
+
+// Blind signatures let somebody get a message signed without the signer
+// ever seeing the message (or even its hash) - e.g. a bank signing a digital
+// coin without being able to tell which coin it signed, so it cannot later
+// trace who spent it.
+// 1. The requester picks a random blinding factor r (coprime to n) and
+//    sends hash * r^e mod n, which looks like a random number.
+// 2. The signer signs it as normal, giving (hash * r^e)^d = hash^d * r
+//    (mod n), since r^(e * d) = r.
+// 3. The requester multiplies by the inverse of r, leaving hash^d - exactly
+//    the signature the signer would have made for the message itself.
+// See [here](https://en.wikipedia.org/wiki/Blind_signature#Blind_RSA_signatures).
+
+// Multiply x by y modulo z, without overflowing.
+
+pub fn multiply_modulo(x: u32, y: u32, z: u32) -> u32 {
+    ((x as u64 * y as u64) % z as u64) as u32
+}
+
+// Step 1: blind the hash of a message with the blinding factor r, using the
+// signer's public key.
+
+pub fn blind_message(msg: String, pub_key_mod: u32, pub_key_exp: u32, r: u32) -> u32 {
+    let h = get_hash(&msg) % pub_key_mod;
+    multiply_modulo(h, raise_power_modulo(r, pub_key_exp, pub_key_mod), pub_key_mod)
+}
+
+// Step 2: sign a blinded hash.  This is the same as signing a hash
+// directly - the signer cannot tell the difference.
+
+pub fn sign_blinded(blinded: u32, priv_key_mod: u32, priv_key_exp: u32) -> u32 {
+    raise_power_modulo(blinded, priv_key_exp, priv_key_mod)
+}
+
+// Step 3: remove the blinding factor r from a blind signature, giving a
+// normal signature of the original message.  This is None if r has no
+// inverse modulo n, since then the blinding can not be removed.
+
+pub fn unblind_signature(blind_sig: u32, pub_key_mod: u32, r: u32) -> Option<u32> {
+    Some(multiply_modulo(blind_sig, mmi(r, pub_key_mod)?, pub_key_mod))
+}
+
+// The same steps can also protect the signer.  Someone who can time how long
+// the signer takes (or watch how much power it uses) can learn about the
+// private exponent from the numbers being exponentiated.  If the signer
+// blinds the hash itself with a random r before signing, and unblinds the
+// result afterwards, the number it raises to the power d is random every
+// time, but the signature is exactly the same as sign_hash() gives.
+// This needs the public exponent too, to blind with.  It is an error if it
+// does not match the private exponent, since then the signature would be
+// wrong.
+
+pub fn sign_hash_blinded(rng: &mut impl Rng, h: u32, priv_key_mod: u32, priv_key_exp: u32,
+                         pub_key_exp: u32) -> Result<u32, String> {
+    if priv_key_mod < 2 {
+        return Err("the modulus must be at least 2".to_string());
+    }
+    let h = h % priv_key_mod;
+
+    // r must have an inverse modulo n, so that it can be removed again
+    let r = loop {
+        let r = rng.gen_range(1..priv_key_mod);
+        if is_coprime(r, priv_key_mod) {
+            break r;
+        }
+    };
+
+    let blinded = multiply_modulo(h, raise_power_modulo(r, pub_key_exp, priv_key_mod), priv_key_mod);
+    let blind_sig = sign_blinded(blinded, priv_key_mod, priv_key_exp);
+    let sig = unblind_signature(blind_sig, priv_key_mod, r)
+        .expect("r is chosen to be coprime to n");
+
+    if raise_power_modulo(sig, pub_key_exp, priv_key_mod) != h {
+        return Err("the public exponent does not match the private key".to_string());
+    }
+    Ok(sig)
+}
+
+// Given a message, a signature, a public key modulus, and a public key exponent,
+// return true if the signature was signed by the equivalent private key, or
+// false if not.
+
+pub fn verify_signature(msg: String, sig: u32, pub_key_mod: u32, pub_key_exp: u32) -> bool {
+
+    // TODO 6
+    
+    // Step 1: Get the hash value of the message.
+    //         Remember there is a get_hash() function for you to use.
+    let h = get_hash(&msg);
+        
+    // Step 2: Raise the signature to the power of pub_key_exp modulo
+    //         pub_key_mod.  Remember there is a raise_power_modulo() function
+    //         for you to use.
+    let r = raise_power_modulo(sig, pub_key_exp, pub_key_mod);
+
+    // Step 3: Return true if the result of the previous operation is equal to
+    // the hash value modulo the public key modulus, false otherwise.
+    r == h % pub_key_mod
+}
+
+// The same keys can be used to encrypt a message instead of signing it:
+// anybody can encrypt with the public key, but only the owner of the private
+// key can decrypt.  Here each byte m of the message is encrypted on its own,
+// as c = m^e mod n, and decrypted again with m = c^d mod n.
+// Encrypting one byte at a time like this is NOT secure - every "e" in the
+// message encrypts to the same number, so it is no better than a
+// substitution cipher, and anyone can just encrypt all 256 possible bytes
+// and compare.  Real RSA encrypts a (randomly padded) key for a symmetric
+// cipher instead.
+
+// Encrypt each byte of a message with a public key.  The modulus must be
+// bigger than every byte, or two bytes could encrypt to the same number.
+
+pub fn encrypt_message(msg: &str, pub_key_mod: u32, pub_key_exp: u32) -> Result<Vec<u32>, String> {
+    if pub_key_mod <= u8::MAX as u32 {
+        return Err(format!("the modulus must be bigger than {}", u8::MAX));
+    }
+    Ok(msg.bytes()
+        .map(|m| raise_power_modulo(m as u32, pub_key_exp, pub_key_mod))
+        .collect())
+}
+
+// Decrypt a message encrypted by encrypt_message() with the private key,
+// giving an error if it does not decrypt to a UTF-8 string (e.g. because
+// the wrong key was used).  Like encrypt_message(), the modulus must be
+// bigger than any byte.
+
+pub fn decrypt_message(cipher: &[u32], priv_key_mod: u32, priv_key_exp: u32) -> Result<String, String> {
+    if priv_key_mod <= u8::MAX as u32 {
+        return Err(format!("the modulus must be bigger than {}", u8::MAX));
+    }
+    let bytes = cipher.iter()
+        .map(|c| {
+            let m = raise_power_modulo(*c, priv_key_exp, priv_key_mod);
+            u8::try_from(m).map_err(|_| format!("{} does not decrypt to a byte", c))
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+    String::from_utf8(bytes).map_err(|_| "the message is not valid UTF-8".to_string())
+}
+
+// Tests start here
+// Run "cargo test" to run all of them
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ****************************************************************
+    // is_prime(n) function
+    // ****************************************************************
+
+    
+    #[test]
+    fn test_5_is_prime() {
+        assert!(is_prime(5), "5 should be prime");
+    }
+
+    #[test]
+    fn test_6_is_not_prime() {
+        assert!(!is_prime(6), "6 should not be prime");
+    }
+
+    #[test]
+    fn test_1000_is_not_prime() {
+        assert!(!is_prime(1000), "1000 should not be prime");
+    }
+    
+    #[test]
+    fn test_1223_is_prime() {
+        assert!(is_prime(1223), "1223 should be prime");
+    }
+
+    
+    // ****************************************************************
+    // miller_rabin() function
+    // ****************************************************************
+
+    #[test]
+    fn test_miller_rabin_agrees_with_is_prime() {
+        for n in 0..5000 {
+            assert_eq!(miller_rabin(n, DEFAULT_PRIME_ROUNDS), is_prime(n), "{}", n);
+        }
+    }
+
+    #[test]
+    fn test_miller_rabin_more_rounds() {
+        // Known primes, including some above MAX_KEY_VAL
+        for p in [65521, 65537, 1000003, 4294967291].iter() {
+            for rounds in 1..=MILLER_RABIN_WITNESSES.len() {
+                assert!(miller_rabin(*p, rounds), "{} with {} rounds", p, rounds);
+            }
+        }
+        // Known composites, including Carmichael numbers and strong
+        // pseudoprimes to small bases
+        for c in [561, 1105, 8321, 1373653, 25326001, 3215031751].iter() {
+            for rounds in DEFAULT_PRIME_ROUNDS..=MILLER_RABIN_WITNESSES.len() {
+                assert!(!miller_rabin(*c, rounds), "{} with {} rounds", c, rounds);
+            }
+        }
+    }
+
+    #[test]
+    fn test_miller_rabin_one_round_can_be_fooled() {
+        // 8321 = 53 * 157 gets past the witness 2, but not 3
+        assert!(miller_rabin(8321, 1));
+        assert!(!miller_rabin(8321, 2));
+    }
+
+    #[test]
+    fn test_is_prime_mr_rejects_carmichael_numbers() {
+        // Carmichael numbers fool the Fermat test for every base coprime
+        // to them, but not Miller-Rabin
+        for c in [561, 1105, 1729, 41041, 825265].iter() {
+            assert!(!is_prime_mr(*c, 1), "{}", c);
+            assert!(!is_prime(*c as u32), "{}", c);
+        }
+    }
+
+    #[test]
+    fn test_is_prime_mr_agrees_with_is_prime() {
+        for n in 0..5000 {
+            assert_eq!(is_prime_mr(n, 1), is_prime(n as u32), "{}", n);
+        }
+    }
+
+    #[test]
+    fn test_is_prime_mr_large() {
+        // Below the deterministic bound
+        assert!(is_prime_mr(4294967291, 1));
+        assert!(is_prime_mr(1_000_000_000_000_000_003, 1));
+        assert!(!is_prime_mr(3215031751, 1));
+        assert!(!is_prime_mr(4294967291 * 65521, 1));
+        // Above it
+        assert!(is_prime_mr(18446744073709551557, 20));
+        assert!(!is_prime_mr(4294967291 * 4294967279, 20));
+    }
+
+    // ****************************************************************
+    // get_random_prime() function
+    // ****************************************************************
+
+    #[test]
+    fn test_gets_random_prime() {
+        let mut rng = rand::thread_rng();
+        let p = get_random_prime(&mut rng, MIN_KEY_VAL, MAX_KEY_VAL, DEFAULT_PRIME_ROUNDS).unwrap();
+        assert!(is_prime(p));
+    }
+
+    #[test]
+    fn test_random_primes_within_bounds() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let p = get_random_prime(&mut rng, MIN_KEY_VAL, MAX_KEY_VAL, DEFAULT_PRIME_ROUNDS).unwrap();
+            assert!(p >= 3);
+            assert!(p < MAX_KEY_VAL);
+        }
+    }
+
+    #[test]
+    fn test_random_prime_in_chosen_range() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let p = get_random_prime(&mut rng, 100, 200, DEFAULT_PRIME_ROUNDS).unwrap();
+            assert!((100..200).contains(&p));
+            assert!(is_prime(p));
+        }
+        // 199 is the only prime in the range, so it must always be picked
+        for _ in 0..10 {
+            assert_eq!(get_random_prime(&mut rng, 198, 200, DEFAULT_PRIME_ROUNDS), Ok(199));
+        }
+    }
+
+    #[test]
+    fn test_random_prime_never_two() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..1000 {
+            let p = get_random_prime(&mut rng, 0, 6, DEFAULT_PRIME_ROUNDS).unwrap();
+            assert!(p == 3 || p == 5);
+        }
+        assert_eq!(get_random_prime(&mut rng, 2, 3, DEFAULT_PRIME_ROUNDS),
+                   Err("there are no primes other than 2 from 2 up to 3".to_string()));
+    }
+
+    #[test]
+    fn test_keys_from_small_primes_are_not_degenerate() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..200 {
+            let p = get_random_prime(&mut rng, 2, 20, DEFAULT_PRIME_ROUNDS).unwrap();
+            let q = get_random_prime(&mut rng, 2, 20, DEFAULT_PRIME_ROUNDS).unwrap();
+            if p == q {
+                continue;
+            }
+            let t = carmichael_totient(p, q);
+            assert!(t > 2, "p = {}, q = {}", p, q);
+            let e = choose_private_exponent(t, &mut rng);
+            let d = compute_public_exponent(e, t).unwrap();
+            for h in 0..p * q {
+                assert_eq!(raise_power_modulo(sign_hash(h, p * q, e), d, p * q), h);
+            }
+        }
+    }
+
+    #[test]
+    fn test_random_prime_bad_range() {
+        let mut rng = rand::thread_rng();
+        assert_eq!(get_random_prime(&mut rng, 200, 100, DEFAULT_PRIME_ROUNDS),
+                   Err("the range 200..100 is empty".to_string()));
+        assert_eq!(get_random_prime(&mut rng, 100, 100, DEFAULT_PRIME_ROUNDS),
+                   Err("the range 100..100 is empty".to_string()));
+        // 114 to 126 are all composite
+        assert_eq!(get_random_prime(&mut rng, 114, 127, DEFAULT_PRIME_ROUNDS),
+                   Err("there are no primes from 114 up to 127".to_string()));
+    }
+
+    #[test]
+    fn test_parallel_random_prime() {
+        for threads in [1, 2, 4].iter() {
+            let p = get_random_prime_parallel(*threads, DEFAULT_PRIME_ROUNDS);
+            assert!(is_prime(p));
+            assert!((3..MAX_KEY_VAL).contains(&p));
+        }
+    }
+
+    #[test]
+    fn test_generate_key_pair_threaded() {
+        let keys = generate_key_pair_threaded(&mut rand::thread_rng(), Totient::Carmichael, DEFAULT_PRIME_ROUNDS, 4);
+        assert!(keys.verify("dog".to_string(), keys.sign("dog".to_string())));
+    }
+
+    // Not really a test - compares how long the serial and parallel prime
+    // searches take.  Run it with
+    // `cargo test --release -- --ignored --nocapture bench_parallel_prime`
+
+    #[test]
+    #[ignore]
+    fn bench_parallel_prime() {
+        const PRIMES: u32 = 2000;
+        let mut rng = rand::thread_rng();
+        let start = std::time::Instant::now();
+        for _ in 0..PRIMES {
+            get_random_prime(&mut rng, MIN_KEY_VAL, MAX_KEY_VAL, DEFAULT_PRIME_ROUNDS).unwrap();
+        }
+        println!("serial: {:?} per prime", start.elapsed() / PRIMES);
+        for threads in [2, 4, 8].iter() {
+            let start = std::time::Instant::now();
+            for _ in 0..PRIMES {
+                get_random_prime_parallel(*threads, DEFAULT_PRIME_ROUNDS);
+            }
+            println!("{} threads: {:?} per prime", threads, start.elapsed() / PRIMES);
+        }
+    }
+
+    // ****************************************************************
+    // coprimes() function
+    // ****************************************************************
+
+    #[test]
+    fn test_8_5_are_coprime() {
+        assert!(is_coprime(8, 5), "8, 5 should be coprime");
+    }
+
+    #[test]
+    fn test_8_6_are_not_coprime() {
+        assert!(!is_coprime(8, 6), "8, 6 should not be coprime");
+    }
+
+    // ****************************************************************
+    // check_modulus() function
+    // ****************************************************************
+
+    #[test]
+    fn test_find_small_factor() {
+        assert_eq!(find_small_factor(3 * 1000003), Some(3));
+        assert_eq!(find_small_factor(997 * 65521), Some(997));
+        assert_eq!(find_small_factor(4228098967), None);
+    }
+
+    #[test]
+    fn test_check_modulus_small_factor_is_weak() {
+        assert!(check_modulus(3 * 1000003).is_some());
+    }
+
+    #[test]
+    fn test_check_modulus_prime_is_weak() {
+        assert!(check_modulus(65521).is_some());
+    }
+
+    #[test]
+    fn test_check_modulus_semiprime_is_ok() {
+        assert!(check_modulus(4228098967).is_none());
+        assert!(check_modulus(1240214083).is_none());
+    }
+
+    #[test]
+    fn test_check_public_exponent() {
+        assert_eq!(check_public_exponent(3233, 0),
+                   Some("Warning: public exponent 0 must be greater than 1 and less than the modulus 3233".to_string()));
+        assert!(check_public_exponent(3233, 1).is_some());
+        assert!(check_public_exponent(3233, 3233).is_some());
+        assert!(check_public_exponent(3233, 5000).is_some());
+        assert_eq!(check_public_exponent(3233, 413), None);
+        assert_eq!(check_public_exponent(3233, 17), None);
+    }
+
+    #[test]
+    fn test_check_public_exponent_shares_factor_with_totient() {
+        // The totient of 3233 = 53 * 61 is lcm(52, 60) = 780 = 2^2 * 3 * 5 * 13
+        for &(e, g) in [(13, 13), (26, 26), (15, 15), (2, 2), (14, 2)].iter() {
+            assert_eq!(check_public_exponent(3233, e),
+                       Some(format!("Warning: public exponent {} shares the factor {} with the totient 780, \
+                                     so no private key goes with it", e, g)));
+        }
+    }
+
+    #[test]
+    fn test_check_public_exponent_generated_keys() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            let keys = generate_key_pair(&mut rng);
+            assert_eq!(check_public_exponent(keys.modulus, keys.public_exp), None);
+        }
+    }
+
+    // ****************************************************************
+    // factor_modulus(), crack_key() and keyspace_attack() functions
+    // ****************************************************************
+
+    #[test]
+    fn test_factor_modulus() {
+        assert_eq!(factor_modulus(3233), Some((53, 61)));
+        let (p, q) = factor_modulus(4228098967).unwrap();
+        assert!(1 < p && p <= q && p * q == 4228098967);
+        assert_eq!(factor_modulus(14), Some((2, 7)));
+        assert_eq!(factor_modulus(65537), None);
+    }
+
+    #[test]
+    fn test_factor_modulus_counting() {
+        // 2, then 3, 5, ..., 53
+        assert_eq!(factor_modulus_counting(3233), (Some((53, 61)), 1 + 26));
+        assert_eq!(factor_modulus_counting(14), (Some((2, 7)), 1));
+        // Every odd number from 3 to 255 (sqrt(65537) is just over 256)
+        assert_eq!(factor_modulus_counting(65537), (None, 1 + 127));
+    }
+
+    #[test]
+    fn test_crack_key() {
+        let keys = KeyPair { modulus: 3233, private_exp: 17, public_exp: 413 };
+        assert_eq!(crack_key(3233, 413), Some(keys));
+    }
+
+    #[test]
+    fn test_generate_key_pair_bits() {
+        let mut rng = rand::thread_rng();
+        for bits in MIN_ATTACK_BITS..=MAX_ATTACK_BITS {
+            let keys = generate_key_pair_bits(&mut rng, bits);
+            let n_bits = modulus_bits(keys.modulus);
+            assert!(n_bits == bits || n_bits == bits - 1, "{} bits for {}", n_bits, bits);
+            let sig = keys.sign("dog".to_string());
+            assert!(keys.verify("dog".to_string(), sig));
+        }
+    }
+
+    #[test]
+    fn test_keyspace_attack_recovers_private_exponent() {
+        let mut rng = rand::thread_rng();
+        let start = Instant::now();
+        let (keys, cracked, elapsed) = keyspace_attack(&mut rng, 16);
+        assert!(elapsed <= start.elapsed());
+        assert_eq!(cracked, Some(keys));
+    }
+
+    // ****************************************************************
+    // sieve_primes() and key space functions
+    // ****************************************************************
+
+    #[test]
+    fn test_sieve_primes_small() {
+        assert_eq!(sieve_primes(30), vec![2, 3, 5, 7, 11, 13, 17, 19, 23, 29]);
+        assert!(sieve_primes(2).is_empty());
+    }
+
+    #[test]
+    fn test_sieve_primes_agrees_with_is_prime() {
+        let primes = sieve_primes(5000);
+        let expected: Vec<u32> = (0..5000).filter(|n| is_prime(*n)).collect();
+        assert_eq!(primes, expected);
+    }
+
+    // 2 is never chosen as a key prime, so there is one less than the sieve
+    #[test]
+    fn test_key_space_prime_count_for_max_key_val() {
+        let num_primes = count_key_primes(16).unwrap();
+        assert_eq!(num_primes as usize, sieve_primes(MAX_KEY_VAL).len() - 1);
+        assert_eq!(num_primes, 6541);
+    }
+
+    #[test]
+    fn test_key_space_large_sizes_are_estimated() {
+        assert_eq!(count_key_primes(SIEVE_BITS + 1), None);
+        let log2_n = log2_estimate_key_primes(1024);
+        assert!(log2_n > 1013.0 && log2_n < 1015.0);
+    }
+
+    // The estimate should be in the right ballpark of the real count
+    #[test]
+    fn test_key_space_estimate_close_to_exact() {
+        let exact = (count_key_primes(20).unwrap() as f64).log2();
+        let estimate = log2_estimate_key_primes(20);
+        assert!((exact - estimate).abs() < 0.2);
+    }
+
+    // The same seed must always give the same keys, so that exercises can
+    // be marked against known keys
+
+    #[test]
+    fn test_generate_key_pair_from_seed_is_reproducible() {
+        for seed in [0, 1, 12345, u64::MAX].iter() {
+            let first = generate_key_pair(&mut StdRng::seed_from_u64(*seed));
+            let second = generate_key_pair(&mut StdRng::seed_from_u64(*seed));
+            assert_eq!(first, second);
+            assert!(first.verify("dog".to_string(), first.sign("dog".to_string())));
+        }
+        assert_ne!(generate_key_pair(&mut StdRng::seed_from_u64(1)),
+                   generate_key_pair(&mut StdRng::seed_from_u64(2)));
+    }
+
+    // TODO 1 test
+
+
+    // Generate 10 times.  At each iteration, the resultant values
+    // should both be prime and distinct from each other (i.e. not equal).
+    // This is kind of a poor man's stochastic testing. =)
+    #[test]
+    fn test_generate_two_primes() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..10 {
+            let (p, q) = generate_two_primes(&mut rng, DEFAULT_PRIME_ROUNDS);
+            assert!(p != q);
+            assert!(is_prime(p));
+            assert!(is_prime(q));
+        }
+    }
+
+    // TODO 2 tests
+
+    #[test]
+    fn test_choose_private_exponent() {
+        let c = 70429;
+        let mut rng = rand::thread_rng();
+        for _ in 0..10 {
+            let p = choose_private_exponent(c, &mut rng);
+            assert!(is_coprime(p, c));
+            assert!(p > 1);
+            assert!(p < c);
+
+        }
+
+    }
+
+    #[test]
+    fn test_choose_private_exponent_small_range() {
+        // With c = 5, only 2, 3 and 4 are in range, and all are coprime to 5.
+        // All three should turn up, and 5 never should.
+        let mut rng = rand::thread_rng();
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..200 {
+            let p = choose_private_exponent(5, &mut rng);
+            assert!((2..5).contains(&p));
+            seen.insert(p);
+        }
+        assert_eq!(seen.len(), 3);
+    }
+
+    // TODO 3 tests
+    // fn compute_public_exponent(e: u32, n: u32) -> Option<u32> {
+
+    #[test]
+    fn test_compute_public_exponent_1() {
+        let e: u32 = 600010331;
+        let n: u32 = 654955584;
+        let r = compute_public_exponent(e, n);
+        assert!(r == Some(4070099));
+    }
+
+    #[test]
+    fn test_compute_public_exponent_big() {
+        let e: u32 = 54741371;
+        let n: u32 = 314700540;
+        let r = compute_public_exponent(e, n);
+        assert!(r == Some(151583711));
+    }
+
+    #[test]
+    fn test_compute_public_exponent_not_coprime() {
+        assert_eq!(compute_public_exponent(6, 3120), None);
+    }
+
+    #[test]
+    fn test_mmi() {
+        assert_eq!(mmi(17, 3120), Some(2753));
+        assert_eq!(mmi(3, 11), Some(4));
+        assert_eq!(mmi(4, 8), None);
+        assert_eq!(mmi(0, 7), None);
+        assert_eq!(mmi(5, 1), Some(0));
+        assert_eq!(mmi(1, 0), None);
+    }
+
+    #[test]
+    fn test_mmi_is_the_inverse() {
+        for m in 1..150u32 {
+            for a in 0..150u32 {
+                let expected = (0..m).find(|x| (a * x) % m == 1 % m);
+                assert_eq!(mmi(a, m), expected, "mmi({}, {})", a, m);
+            }
+        }
+    }
+
+    #[test]
+    fn test_extended_gcd() {
+        let pairs = [(240, 46), (46, 240), (17, 3120), (0, 5), (5, 0), (0, 0),
+                     (-240, 46), (240, -46), (1 << 40, 3 << 20), (4228098967, 26379711)];
+        let extremes = [(i64::MIN, 0), (0, i64::MIN), (i64::MIN, i64::MIN), (i64::MIN, -1),
+                        (i64::MIN, i64::MAX), (i64::MAX, i64::MIN), (i64::MIN, 3)];
+        for (a, b) in pairs.iter().chain(extremes.iter()) {
+            let (g, x, y) = extended_gcd(*a, *b);
+            assert_eq!(*a as i128 * x + *b as i128 * y, g, "{} * {} + {} * {} != {}", a, x, b, y, g);
+            assert!(g >= 0);
+            assert_eq!(g as u64, num::integer::gcd(a.unsigned_abs(), b.unsigned_abs()));
+        }
+        assert_eq!(extended_gcd(240, 46), (2, -9, 47));
+        assert_eq!(extended_gcd(i64::MIN, 0).0, 1 << 63);
+    }
+
+    // TODO 4 tests
+
+    #[test]
+    fn test_generate_key_pair_hash_500() {
+        let h = 500;
+        let mut rng = rand::thread_rng();
+        for _ in 0..10 {
+            let KeyPair { modulus: m, private_exp: e, public_exp: d } = generate_key_pair(&mut rng);
+            let r1 = raise_power_modulo(h, d, m);
+            let r2 = raise_power_modulo(r1, e, m);
+            assert!(r2 == h % m);
+        }
+
+    }
+
+    #[test]
+    fn test_generate_key_pair_hash_99999999() {
+        let h = 99999999;
+        let mut rng = rand::thread_rng();
+        for _ in 0..10 {
+            let KeyPair { modulus: m, private_exp: e, public_exp: d } = generate_key_pair(&mut rng);
+            let r1 = raise_power_modulo(h, d, m);
+            let r2 = raise_power_modulo(r1, e, m);
+            assert!(r2 == h % m);
+        }
+
+    }
+
+    // ****************************************************************
+    // KeyPair struct
+    // ****************************************************************
+
+    #[test]
+    fn test_key_pair_sign_verify() {
+        // p = 61, q = 53, so lcm(60, 52) = 780 and 17 * 413 = 1 (mod 780)
+        let keys = KeyPair { modulus: 3233, private_exp: 17, public_exp: 413 };
+        let sig = keys.sign("dog".to_string());
+        assert_eq!(sig, Signature(sign_message("dog".to_string(), 3233, 17)));
+        assert!(keys.verify("dog".to_string(), sig));
+        assert!(!keys.verify("cat".to_string(), sig));
+    }
+
+    #[test]
+    fn test_signature_verify() {
+        let keys = KeyPair { modulus: 3233, private_exp: 17, public_exp: 413 };
+        let sig: Signature = keys.sign("dog".to_string());
+        assert!(sig.verify("dog".to_string(), keys.public_key()));
+        assert!(!sig.verify("cat".to_string(), keys.public_key()));
+        assert!(Signature(11318728).verify("dog".to_string(), (4228098967, 26379711)));
+    }
+
+    #[test]
+    fn test_tampered_signature_does_not_verify() {
+        let mut rng = rand::thread_rng();
+        let keys = generate_key_pair(&mut rng);
+        assert!(!Signature(0).verify("meow".to_string(), keys.public_key()));
+        assert!(!keys.verify("meow".to_string(), Signature(0)));
+    }
+
+    #[test]
+    fn test_generated_key_pair_round_trips() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..10 {
+            let keys = generate_key_pair(&mut rng);
+            let sig = keys.sign("meow".to_string());
+            assert!(keys.verify("meow".to_string(), sig));
+            assert!(!keys.verify("woof".to_string(), sig));
+        }
+    }
+
+    #[test]
+    fn test_euler_totient() {
+        assert_eq!(euler_totient(5, 7), 24);
+        assert_eq!(carmichael_totient(5, 7), 12);
+        assert_eq!(euler_totient(65521, 65519), 65520 * 65518);
+    }
+
+    #[test]
+    fn test_generate_key_pair_each_totient_round_trips() {
+        let mut rng = rand::thread_rng();
+        for totient in [Totient::Carmichael, Totient::Euler].iter() {
+            for _ in 0..10 {
+                let KeyPair { modulus: m, private_exp: e, public_exp: d } = generate_key_pair_with(&mut rng, *totient, DEFAULT_PRIME_ROUNDS);
+                let sig = sign_message("meow".to_string(), m, e);
+                assert!(verify_signature("meow".to_string(), sig, m, d));
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_key_components() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            let c = generate_key_components(&mut rng, Totient::Carmichael, DEFAULT_PRIME_ROUNDS);
+            assert!(is_prime(c.p) && is_prime(c.q) && c.p != c.q);
+            assert_eq!(c.modulus, c.p * c.q);
+            assert_eq!(c.totient, (c.p - 1).lcm(&(c.q - 1)));
+            assert_eq!((c.private_exp as u64 * c.public_exp as u64) % c.totient as u64, 1);
+            assert_eq!(c.key_pair(), KeyPair { modulus: c.modulus, private_exp: c.private_exp, public_exp: c.public_exp });
+        }
+        let c = generate_key_components(&mut rng, Totient::Euler, DEFAULT_PRIME_ROUNDS);
+        assert_eq!(c.totient, (c.p - 1) * (c.q - 1));
+    }
+
+    #[test]
+    fn test_generate_key_components_matches_key_pair() {
+        let c = generate_key_components(&mut StdRng::seed_from_u64(42), Totient::Carmichael, DEFAULT_PRIME_ROUNDS);
+        assert_eq!(c.key_pair(), generate_key_pair(&mut StdRng::seed_from_u64(42)));
+    }
+
+    #[test]
+    fn test_generate_key_pair_profiled() {
+        let mut rng = rand::thread_rng();
+        let (keys, profile) = generate_key_pair_profiled(&mut rng, Totient::Carmichael,
+                                                             DEFAULT_PRIME_ROUNDS);
+        assert!(profile.primes > Duration::from_nanos(0));
+        assert!(keys.verify("meow".to_string(), keys.sign("meow".to_string())));
+    }
+
+    #[test]
+    fn test_strength_label() {
+        assert_eq!(strength_label(32), "trivially crackable");
+        assert_eq!(strength_label(127), "trivially crackable");
+        assert_eq!(strength_label(256), "very weak");
+        assert_eq!(strength_label(512), "weak");
+        assert_eq!(strength_label(1024), "legacy (no longer recommended)");
+        assert_eq!(strength_label(2048), "acceptable");
+        assert_eq!(strength_label(4096), "strong");
+    }
+
+    #[test]
+    fn test_modulus_bits() {
+        assert_eq!(modulus_bits(1), 1);
+        assert_eq!(modulus_bits(255), 8);
+        assert_eq!(modulus_bits(4228098967), 32);
+    }
+
+    // TODO 5 tests
+
+    #[test]
+    fn test_sign_message_foo() {
+        let msg: String = "foo".to_string();
+        let sig = sign_message(msg, 262373123, 120571543);
+        assert!(sig == 111862601);
+    }
+
+    #[test]
+    fn test_sign_message_bar() {
+        let msg: String = "bar".to_string();
+        let sig = sign_message(msg, 3360057163, 423721031);
+        assert!(sig == 2318946848);
+    }
+
+    #[test]
+    fn test_sign_message_meow() {
+        let msg: String = "meow".to_string();
+        let sig = sign_message(msg, 1240214083, 97643729);
+        assert!(sig == 866459596);
+    }
+
+    
+    #[test]
+    fn test_sign_message_showing_hash() {
+        let msg: String = "meow".to_string();
+        let (h, sig) = sign_message_showing_hash(msg.clone(), 1240214083, 97643729);
+        assert_eq!(h, get_hash(&msg));
+        assert_eq!(sig, 866459596);
+        assert_eq!(raise_power_modulo(h, 97643729, 1240214083), sig);
+    }
+
+    // TODO 6 tests
+
+    // ****************************************************************
+    // raise_power_modulo() function
+    // ****************************************************************
+
+    // Square-and-multiply in u128, which can't overflow for a u64 modulus
+    fn slow_power_modulo(x: u64, mut y: u64, z: u64) -> u64 {
+        let z = z as u128;
+        let mut base = x as u128 % z;
+        let mut r = 1 % z;
+        while y > 0 {
+            if y & 1 == 1 {
+                r = r * base % z;
+            }
+            base = base * base % z;
+            y >>= 1;
+        }
+        r as u64
+    }
+
+    #[test]
+    fn test_raise_power_modulo_u32() {
+        assert_eq!(raise_power_modulo(2u32, 10, 1000), 24);
+        assert_eq!(raise_power_modulo(5u32, 0, 7), 1);
+        assert_eq!(raise_power_modulo(u32::MAX, u32::MAX, u32::MAX), 0);
+        assert_eq!(raise_power_modulo(u32::MAX - 1, u32::MAX, u32::MAX) as u64,
+                   slow_power_modulo(u32::MAX as u64 - 1, u32::MAX as u64, u32::MAX as u64));
+    }
+
+    #[test]
+    fn test_raise_power_modulo_u64() {
+        // The largest prime which fits into a u64, so Fermat's little
+        // theorem says x ^ (p - 1) = 1
+        let p: u64 = 18446744073709551557;
+        assert_eq!(raise_power_modulo(u64::MAX, p - 1, p), 1);
+        assert_eq!(raise_power_modulo(2u64, p - 1, p), 1);
+
+        for &(x, y, z) in [(u64::MAX, u64::MAX, u64::MAX - 1),
+                           (u64::MAX - 1, u64::MAX - 2, u64::MAX),
+                           (1 << 63, 3, (1 << 63) + 1),
+                           (12345678901234567890, 98765432109876543, 1 << 40)].iter() {
+            assert_eq!(raise_power_modulo(x, y, z), slow_power_modulo(x, y, z));
+        }
+    }
+
+    #[test]
+    fn test_raise_power_modulo_matches_u32() {
+        for &(x, y, z) in [(123u32, 17, 3233), (866459596, 97643729, 1240214083)].iter() {
+            assert_eq!(raise_power_modulo(x as u64, y as u64, z as u64),
+                       raise_power_modulo(x, y, z) as u64);
+        }
+    }
+
+    // ****************************************************************
+    // sign_data() and verify_data() functions
+    // ****************************************************************
+
+    #[test]
+    fn test_sign_data_matches_sign_message() {
+        for msg in ["dog", "meow", ""].iter() {
+            assert_eq!(sign_data(&msg.to_string(), 3233, 17), sign_message(msg.to_string(), 3233, 17));
+            assert_eq!(sign_data(*msg, 3233, 17), sign_message(msg.to_string(), 3233, 17));
+        }
+    }
+
+    #[test]
+    fn test_sign_verify_bytes() {
+        let keys = KeyPair { modulus: 3233, private_exp: 17, public_exp: 413 };
+        for data in [&[][..], &[0xff, 0xfe, 0x00, 0x80][..], b"hello"].iter() {
+            let sig = sign_data(*data, keys.modulus, keys.private_exp);
+            assert!(verify_data(*data, sig, keys.modulus, keys.public_exp));
+        }
+    }
+
+    // ****************************************************************
+    // get_hash_sha256() and sign_hash() functions
+    // ****************************************************************
+
+    #[test]
+    fn test_get_hash_sha256_known_values() {
+        // SHA-256("abc") = ba7816bf 8f01cfea ...
+        assert_eq!(get_hash_sha256(b"abc"), 0xba7816bf);
+        // SHA-256("") = e3b0c442 98fc1c14 ...
+        assert_eq!(get_hash_sha256(b""), 0xe3b0c442);
+    }
+
+    #[test]
+    fn test_sha256_sign_verify_round_trips() {
+        let keys = KeyPair { modulus: 3233, private_exp: 17, public_exp: 413 };
+        for msg in ["dog", "meow", ""].iter() {
+            let h = get_hash_sha256(msg.as_bytes());
+            let sig = sign_hash(h, keys.modulus, keys.private_exp);
+            assert!(verify_hash(h, sig, keys.modulus, keys.public_exp));
+        }
+    }
+
+    #[test]
+    fn test_cross_hash_verification_fails() {
+        let keys = KeyPair { modulus: 3233, private_exp: 17, public_exp: 413 };
+        let sig = sign_hash(get_hash_sha256(b"dog"), keys.modulus, keys.private_exp);
+        assert!(!verify_signature("dog".to_string(), sig, keys.modulus, keys.public_exp));
+        let sig = sign_message("dog".to_string(), keys.modulus, keys.private_exp);
+        assert!(!verify_hash(get_hash_sha256(b"dog"), sig, keys.modulus, keys.public_exp));
+    }
+
+    #[test]
+    fn test_sign_hash_big_matches_sign_hash() {
+        let h = get_hash_sha256(b"dog");
+        assert_eq!(sign_hash_big(h, &BigUint::from(3233u32), &BigUint::from(17u32)),
+                   BigUint::from(sign_hash(h, 3233, 17)));
+    }
+
+    // ****************************************************************
+    // verify_signature_detailed() function
+    // ****************************************************************
+
+    #[test]
+    fn test_verify_signature_detailed_ok() {
+        let sig = sign_message("dog".to_string(), 3233, 17);
+        assert_eq!(verify_signature_detailed("dog".to_string(), sig, 3233, 413), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_signature_detailed_hash_mismatch() {
+        let sig = sign_message("dog".to_string(), 3233, 17);
+        let expected = get_hash(&"cat".to_string()) % 3233;
+        let got = get_hash(&"dog".to_string()) % 3233;
+        assert_eq!(verify_signature_detailed("cat".to_string(), sig, 3233, 413),
+                   Err(VerifyError::HashMismatch { expected, got }));
+    }
+
+    #[test]
+    fn test_verify_signature_detailed_invalid_modulus() {
+        assert_eq!(verify_signature_detailed("dog".to_string(), 0, 0, 413), Err(VerifyError::InvalidModulus));
+        assert_eq!(verify_signature_detailed("dog".to_string(), 0, 1, 413), Err(VerifyError::InvalidModulus));
+    }
+
+    #[test]
+    fn test_verify_signature_detailed_signature_too_large() {
+        let sig = sign_message("dog".to_string(), 3233, 17);
+        assert_eq!(verify_signature_detailed("dog".to_string(), sig + 3233, 3233, 413),
+                   Err(VerifyError::SignatureTooLarge));
+    }
+
+    #[test]
+    fn test_verify_signature_detailed_agrees_with_verify_signature() {
+        for sig in 0..3233 {
+            assert_eq!(verify_signature_detailed("dog".to_string(), sig, 3233, 413).is_ok(),
+                       verify_signature("dog".to_string(), sig, 3233, 413));
+        }
+    }
+
+    // ****************************************************************
+    // format_key_file() and parse_key_file() functions
+    // ****************************************************************
+
+    #[test]
+    fn test_key_file_round_trips() {
+        let keys = KeyPair { modulus: 3233, private_exp: 17, public_exp: 413 };
+        assert_eq!(parse_key_file(&format_key_file(&keys)), Ok(keys));
+    }
+
+    #[test]
+    fn test_key_file_format() {
+        let keys = KeyPair { modulus: 3233, private_exp: 17, public_exp: 413 };
+        let file = format_key_file(&keys);
+        assert!(file.contains("public modulus: 3233\n"));
+        assert!(file.contains("public exponent: 413\n"));
+        assert!(file.contains("private exponent: 17\n"));
+        assert!(file.ends_with("end\n"));
+    }
+
+    #[test]
+    fn test_truncated_key_file_is_refused() {
+        let file = format_key_file(&KeyPair { modulus: 3233, private_exp: 17, public_exp: 413 });
+        // Cutting the file off anywhere before the end must be an error
+        for len in 0..file.trim_end().len() {
+            assert!(parse_key_file(&file[..len]).is_err(), "{:?}", &file[..len]);
+        }
+    }
+
+    #[test]
+    fn test_bad_key_files_are_refused() {
+        assert!(parse_key_file("public modulus: 3233\nprivate exponent: 17\npublic exponent: 413\nend\n").is_err());
+        assert!(parse_key_file("public modulus: x\npublic exponent: 413\nprivate exponent: 17\nend\n").is_err());
+        assert!(parse_key_file("public modulus: 3233\npublic exponent: 413\nprivate exponent: 17\nend\nmore\n").is_err());
+    }
+
+    // ****************************************************************
+    // nonced_message() function
+    // ****************************************************************
+
+    #[test]
+    fn test_different_nonces_give_different_signatures() {
+        let (m, d) = (1240214083, 97643729);
+        let sig1 = sign_message(nonced_message("meow", 1), m, d);
+        let sig2 = sign_message(nonced_message("meow", 2), m, d);
+        assert!(sig1 != sig2);
+    }
+
+    #[test]
+    fn test_nonced_signature_only_verifies_with_its_nonce() {
+        let mut rng = rand::thread_rng();
+        let KeyPair { modulus: m, private_exp: e, public_exp: d } = generate_key_pair(&mut rng);
+        let sig1 = sign_message(nonced_message("meow", 1), m, e);
+        let sig2 = sign_message(nonced_message("meow", 2), m, e);
+
+        assert!(verify_signature(nonced_message("meow", 1), sig1, m, d));
+        assert!(verify_signature(nonced_message("meow", 2), sig2, m, d));
+        assert!(!verify_signature(nonced_message("meow", 2), sig1, m, d));
+        assert!(!verify_signature(nonced_message("meow", 1), sig2, m, d));
+        assert!(!verify_signature("meow".to_string(), sig1, m, d));
+    }
+
+    #[test]
+    fn test_sign_hash_bigger_than_modulus() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..10 {
+            let KeyPair { modulus: m, private_exp: e, public_exp: d } = generate_key_pair(&mut rng);
+            // Find a long message whose hash is bigger than the modulus.
+            // raise_power_modulo() reduces the hash modulo the modulus
+            // itself, so this signs and verifies like any other message.
+            let msg = (0..)
+                .map(|j| format!("{} - a rather long message which needs signing", j))
+                .find(|msg| get_hash(msg) > m)
+                .unwrap();
+            let sig = sign_message(msg.clone(), m, e);
+            assert!(sig < m);
+            assert!(verify_signature(msg, sig, m, d));
+        }
+    }
+
+    // ****************************************************************
+    // Big key functions
+    // ****************************************************************
+
+    #[test]
+    fn test_random_biguint_bits() {
+        let mut rng = rand::thread_rng();
+        for bits in [1, 7, 8, 9, 64, 100].iter() {
+            assert_eq!(random_biguint_bits(&mut rng, *bits).bits(), *bits as usize);
+        }
+    }
+
+    #[test]
+    fn test_is_prime_big_agrees_with_is_prime() {
+        let mut rng = rand::thread_rng();
+        for n in 0..2000u32 {
+            assert_eq!(is_prime_big(&BigUint::from(n), 5, &mut rng), is_prime(n), "{}", n);
+        }
+        // 2^127 - 1 is prime, 2^128 + 1 is not
+        let m127 = (BigUint::one() << 127) - 1u32;
+        assert!(is_prime_big(&m127, 10, &mut rng));
+        assert!(!is_prime_big(&((BigUint::one() << 128) + 1u32), 10, &mut rng));
+    }
+
+    #[test]
+    fn test_compute_public_exponent_big_matches_mmi() {
+        for (e, n) in [(17, 3120), (3, 11), (7, 40), (65537, 3233 * 7)].iter() {
+            assert_eq!(compute_public_exponent_big(&BigUint::from(*e as u32), &BigUint::from(*n as u32)),
+                       BigUint::from(mmi(*e as u32, *n as u32).unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_big_sign_matches_u32_sign() {
+        let (n, d) = (BigUint::from(3233u32), BigUint::from(17u32));
+        assert_eq!(sign_message_big("dog", &n, &d),
+                   BigUint::from(sign_message("dog".to_string(), 3233, 17)));
+    }
+
+    #[test]
+    fn test_big_keys_round_trip() {
+        let mut rng = rand::thread_rng();
+        for bits in [64, 128].iter() {
+            let keys = generate_big_key_pair(&mut rng, *bits, Totient::Carmichael);
+            assert_eq!(keys.modulus.bits() as u64, *bits);
+            let sig = sign_message_big("dog", &keys.modulus, &keys.private_exp);
+            assert!(verify_signature_big("dog", &sig, &keys.modulus, &keys.public_exp));
+            assert!(!verify_signature_big("cat", &sig, &keys.modulus, &keys.public_exp));
+        }
+    }
+
+    #[test]
+    fn test_verify_hash_big_rejects_tiny_modulus() {
+        let e = BigUint::from(99999999999u64);
+        for n in [0u32, 1].iter() {
+            assert!(!verify_hash_big(0, &BigUint::from(0u32), &BigUint::from(*n), &e));
+            assert!(!verify_hash_big(5, &BigUint::from(5u32), &BigUint::from(*n), &e));
+        }
+    }
+
+    // ****************************************************************
+    // sign_batch() function
+    // ****************************************************************
+
+    #[test]
+    fn test_sign_batch() {
+        let keys = KeyPair { modulus: 3233, private_exp: 17, public_exp: 413 };
+        let messages = "pay Alice 5\n\npay Bob 7\n   \npay Carol 9";
+        let signed = sign_batch(messages.lines(), keys.modulus, keys.private_exp);
+        let signed_messages: Vec<&str> = signed.iter().map(|(msg, _)| *msg).collect();
+        assert_eq!(signed_messages, vec!["pay Alice 5", "pay Bob 7", "pay Carol 9"]);
+        for (msg, sig) in signed {
+            assert!(verify_signature(msg.to_string(), sig, keys.modulus, keys.public_exp), "{}", msg);
+        }
+    }
+
+    #[test]
+    fn test_sign_batch_empty() {
+        assert!(sign_batch("".lines(), 3233, 17).is_empty());
+        assert!(sign_batch("\n\n".lines(), 3233, 17).is_empty());
+    }
+
+    // ****************************************************************
+    // aggregate_verify() function
+    // ****************************************************************
+
+    #[test]
+    fn test_aggregate_verify_all_valid() {
+        let mut rng = rand::thread_rng();
+        let KeyPair { modulus: m, private_exp: e, public_exp: d } = generate_key_pair(&mut rng);
+        let sig = sign_message("dog".to_string(), m, e);
+        let signers = [(11318728, 4228098967, 26379711), (sig, m, d)];
+        assert!(aggregate_verify("dog", &signers).is_empty());
+    }
+
+    #[test]
+    fn test_aggregate_verify_one_invalid() {
+        let mut rng = rand::thread_rng();
+        let KeyPair { modulus: m, private_exp: e, public_exp: d } = generate_key_pair(&mut rng);
+        let sig = sign_message("cat".to_string(), m, e);
+        let signers = [(11318728, 4228098967, 26379711), (sig, m, d)];
+        assert_eq!(aggregate_verify("dog", &signers), vec![1]);
+    }
+
+    #[test]
+    fn test_aggregate_verify_modulus_below_2() {
+        let signers = [(0, 1, 0), (11318728, 4228098967, 26379711), (0, 0, 3)];
+        assert_eq!(aggregate_verify("dog", &signers), vec![0, 2]);
+    }
+
+    // ****************************************************************
+    // Blind signature functions
+    // ****************************************************************
+
+    #[test]
+    fn test_unblinded_signature_verifies() {
+        let keys = KeyPair { modulus: 3233, private_exp: 17, public_exp: 413 };
+        for r in [2, 3, 100, 1000, 3232].iter() {
+            let blinded = blind_message("dog".to_string(), keys.modulus, keys.public_exp, *r);
+            let blind_sig = sign_blinded(blinded, keys.modulus, keys.private_exp);
+            let sig = unblind_signature(blind_sig, keys.modulus, *r).unwrap();
+            assert!(verify_signature("dog".to_string(), sig, keys.modulus, keys.public_exp), "r = {}", r);
+            assert_eq!(sig, sign_message("dog".to_string(), keys.modulus, keys.private_exp));
+            assert!(!verify_signature("cat".to_string(), sig, keys.modulus, keys.public_exp));
+        }
+    }
+
+    #[test]
+    fn test_unblind_without_inverse() {
+        // 53 is a factor of 3233, so it has no inverse
+        assert_eq!(unblind_signature(100, 3233, 53), None);
+    }
+
+    #[test]
+    fn test_blinding_hides_the_hash() {
+        let h = get_hash(&"dog".to_string()) % 3233;
+        let blinded: Vec<u32> = [2, 3, 100].iter()
+            .map(|r| blind_message("dog".to_string(), 3233, 413, *r))
+            .collect();
+        assert!(!blinded.contains(&h));
+        assert!(blinded[0] != blinded[1] && blinded[1] != blinded[2]);
+    }
+
+    #[test]
+    fn test_sign_hash_blinded_verifies() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..10 {
+            let keys = generate_key_pair(&mut rng);
+            let h = get_hash("dog");
+            let plain = sign_hash(h, keys.modulus, keys.private_exp);
+            let blinded = sign_hash_blinded(&mut rng, h, keys.modulus, keys.private_exp, keys.public_exp).unwrap();
+            assert!(verify_hash(h, plain, keys.modulus, keys.public_exp));
+            assert!(verify_hash(h, blinded, keys.modulus, keys.public_exp));
+            assert_eq!(blinded, plain);
+        }
+    }
+
+    #[test]
+    fn test_sign_hash_blinded_wrong_exponent() {
+        let mut rng = rand::thread_rng();
+        let h = get_hash("dog");
+        assert_eq!(sign_hash_blinded(&mut rng, h, 3233, 17, 413), Ok(sign_hash(h, 3233, 17)));
+        assert_eq!(sign_hash_blinded(&mut rng, h, 3233, 17, 7),
+                   Err("the public exponent does not match the private key".to_string()));
+        assert!(sign_hash_blinded(&mut rng, h, 1, 17, 413).is_err());
+    }
+
+    // ****************************************************************
+    // encrypt_message() and decrypt_message() functions
+    // ****************************************************************
+
+    #[test]
+    fn test_encrypt_decrypt_hello() {
+        let keys = KeyPair { modulus: 3233, private_exp: 17, public_exp: 413 };
+        let cipher = encrypt_message("hello", keys.modulus, keys.public_exp).unwrap();
+        assert_eq!(cipher.len(), 5);
+        assert_ne!(cipher[0], 'h' as u32);
+        assert_eq!(decrypt_message(&cipher, keys.modulus, keys.private_exp), Ok("hello".to_string()));
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_generated_key() {
+        let keys = generate_key_pair(&mut rand::thread_rng());
+        let msg = "héllo wörld";
+        let cipher = encrypt_message(msg, keys.modulus, keys.public_exp).unwrap();
+        assert_eq!(decrypt_message(&cipher, keys.modulus, keys.private_exp), Ok(msg.to_string()));
+    }
+
+    #[test]
+    fn test_encrypt_small_modulus() {
+        assert!(encrypt_message("hello", 255, 3).is_err());
+    }
+
+    #[test]
+    fn test_decrypt_small_modulus() {
+        assert_eq!(decrypt_message(&[1], 0, 3), Err("the modulus must be bigger than 255".to_string()));
+        assert!(decrypt_message(&[1], 255, 3).is_err());
+        assert!(decrypt_message(&[], 1, 3).is_err());
+    }
+
+    #[test]
+    fn test_decrypt_with_wrong_key() {
+        let cipher = encrypt_message("hello", 3233, 413).unwrap();
+        assert_ne!(decrypt_message(&cipher, 3233, 19), Ok("hello".to_string()));
+    }
+
+    // ****************************************************************
+    // verify_signature() function
+    // ****************************************************************
+
+    // This signature is correct
+    #[test]
+    fn test_verify_signature_dog_correct() {
+        assert!(verify_signature("dog".to_string(),
+                               11318728,
+                               4228098967,
+                               26379711));
+    }
+
+    // This signature is incorrect
+    #[test]
+    fn test_verify_signature_dog_incorrect() {
+        assert!(!verify_signature("dog".to_string(),
+                               0,
+                               4228098967,
+                               26379711));
+    }
+
+}
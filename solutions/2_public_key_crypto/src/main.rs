@@ -1,285 +1,355 @@
-//! # A Simple Public-Key Implementation
+//! # A Simple Public-Key Implementation - command line
 //!
-//! IMPORTANT - This should not be used for anything important!
-//! The goal of this program is to teach you the basics of public-key cryptography
-//! but it has not been audited or protected against other attacks.  Additionally,
-//! it is trivially crackable due to the relatively small number of possible
-//! keys and does not use industrial-strength randomization.  When using cryptography for
-//! any professional purpose, follow this cardinal rule - "don't roll your own crypto!"  
+//! This is the command-line program for the RSA routines in the library
+//! (see lib.rs, which explains how it all works).  All it does is read the
+//! arguments, call the library and print out the results.
 //!
-//! ## Overview
-//!
-//! Although most people think of public-key cryptography as simply encrypting
-//! communications, it can also be used to prove that a particular identity
-//! wrote something.  As long as the private key is not shared with anybody,
-//! then only the person who owns the private key will able to reliably sign messages
-//! with it.  This is done in a three-step process, detailed below.
-//!
-//! ### Key Generation
-//!
-//! Two keys, a public key and a private key, are produced.  These keys are
-//! mathematically linked in a way that we will see in the algorithm.  As their names
-//! imply, the public key should be publicly shared and the private key must
-//! be kept private.
-//!
-//! ### Signing
-//!
-//! Using the message as input, you "sign" it with your private key.  This will
-//! produce a number that can prove that you wrote the message.
-//!
-//! ### Verifying
-//!
-//! Using the message and the produced signature, anybody can prove that you
-//! generated that signature, without knowing the private key, using only
-//! the public key.
-//!
-//! ### Example and Walkthrough
-//!
-//! ```
+//! ```text
 //! $ cargo run generate
-//! Private key: 902962279, 278653459
-//! Public key: 902962279, 291642999
-//! 
-//! $ cargo run sign meow 902962279 278653459
-//! Signature: 124665060
-//! 
-//! $ cargo run verify meow 124665060 902962279 291642999
-//! Signature verified!
-//! 
-//! $ cargo run verify meow 111111 902962279 291642999
-//! SIGNATURE INVALID!
+//! $ cargo run sign <msg> <priv_key_mod> <priv_key_exp>
+//! $ cargo run verify <msg> <signature> <pub_key_mod> <pub_key_exp>
 //! ```
-//!
-//! We can see above that a person, let's call her Alice, has generated a
-//! keypair - the private key (902962279, 278653459) which she keeps secret,
-//! and a public key (902962279, 291642999) which she publicizes widely to
-//! anyone who wants to communicate with her.
-//!
-//! Alice would like to prove to the world that she wrote the message "meow".
-//! Perhaps she insists that she is a cat, or this is a game like
-//! rock-paper-scissors.  She can publish "meow" with the signature "124665060"
-//! and anyone can look up her public key and prove that she (or at least
-//! someone with her private key) wrote it.
-//!
-//! There are many other uses for signatures in blockchain technology,
-//! many of which will be covered later in the course.
-//!
-//! ## RSA Algorithm
-//!
-//! This is a relatively straightforward implementation of the paper
-//! ["A Method for Obtaining Digital Signatures and Public-Key Cryptosystems"
-//! by Rivest, Shamir and Adleman]([https://people.csail.mit.edu/rivest/Rsapaper.pdf]).
-//!
-//! If some of the terminology seems strange, don't worry, we will go through
-//! it step by step in the functions.
-//!
-//! 1. Choose two different prime numbers, p and q.
-//! 2. Compute n = p * q.
-//! 3. Compute the Carmichael's totient function (lcm(p - 1, q - 1) to get t.
-//! 4. Choose an integer, e, where 1 < e < t and e is coprime to t.
-//! 5. Compute the modular multiplicative inverse of e mod t to get d.
-//! 6. The public key is (n, e).  The private key is (n, d).
-//!
-//! To sign a message, we will take a hash of the message and raise it
-//! to the power of d modulo n.  To verify a message, we will take the
-//! signature and raise it to the power of e modulo n to get a value r.
-//! If r == h modulo n, then the signatures match and it is valid; otherwise
-//! invalid.
 
-// External crates that we use for a mathematical functions dealing
-// with large integers - quite common in cryptography.
-
-extern crate num_bigint;
-extern crate num_traits;
+use public_key_solution::*;
 
 use num_bigint::BigUint;
-use num_traits::cast::ToPrimitive;
-
 use rand::prelude::*;
 use std::env;
-use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::io;
 use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-// Our two keys can not be higher than this value
-// This makes cracking the code relatively simple, but frees us
-// from having to use BigUint everywhere for multiplication!
-const MAX_KEY_VAL: u32 = 65536;
+// Printed after generating keys, unless --no-rng-note is given.
+const CSPRNG_NOTE: &str = "Note: real key generation needs a cryptographically secure random \
+number generator (CSPRNG) - see \
+https://en.wikipedia.org/wiki/Cryptographically_secure_pseudorandom_number_generator";
+
+// The most threads generate --threads will start
+const MAX_THREADS: usize = 64;
+
+// The digits used for base58 - all of the digits and letters, except for
+// 0, O, I and l, which are easy to mix up.  This is the same alphabet
+// Bitcoin uses for its addresses.
+const BASE58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+// The digits used for URL-safe base64 ("base64url") - like normal base64,
+// but with - and _ instead of + and /, which have special meanings in URLs.
+const BASE64URL_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
 
 // The different functions supported by the program -
 // 1. Generate a keypair
 // 2. Sign a message
 // 3. Verify a signature against a message
+// 4. Calculate how many keys are possible
+// 5. Verify that a message was signed by several signers
+// 6. Say how strong a modulus is
+// 7-9. Blind a message, sign a blinded message, and unblind the signature
+// 10. Check whether two numbers are coprime
+// 11. Time how long it takes to crack a key of a given size
+// 12-13. Encrypt and decrypt a message
+// 14. Sign every line of a file
+// 15. Recover the private key from a public key
+// 16. Check that generating, signing and verifying all work
 enum Function {
     Generate,
     Sign,
     Verify,
+    KeySpace,
+    AggregateVerify,
+    Classify,
+    Blind,
+    SignBlinded,
+    Unblind,
+    CheckCoprime,
+    KeyspaceAttack,
+    Encrypt,
+    Decrypt,
+    SignBatch,
+    Crack,
+    SelfTest,
 }
 
-// ****************************************************************
-// Helper functions
-// ****************************************************************
+// Print out how many possible keys there are if each of the two primes can
+// have at most `prime_bits` bits.  A modulus is made from two different
+// primes, and p * q is the same as q * p, so n primes give n * (n - 1) / 2
+// possible moduli - this is how many someone would have to try to be sure
+// of guessing yours.
+
+fn print_key_space(prime_bits: u32, output: &mut impl Write) {
+    writeln!(output, "Each prime has at most {} bits", prime_bits).unwrap();
+    match count_key_primes(prime_bits) {
+        Some(n) => {
+            writeln!(output, "Primes to choose from: {}", n).unwrap();
+            writeln!(output, "Possible moduli: {}", n * n.saturating_sub(1) / 2).unwrap();
+        },
+        None => {
+            let log2_n = log2_estimate_key_primes(prime_bits);
+            writeln!(output, "Primes to choose from: about 2^{:.1}", log2_n).unwrap();
+            writeln!(output, "Possible moduli: about 2^{:.1}", 2.0 * log2_n - 1.0).unwrap();
+        },
+    }
+}
 
+/// Simple function to tell the user about appropriate usage.
+fn print_usage(output: &mut impl Write) {
+    writeln!(output, "Usage:").unwrap();
+    writeln!(output, "generate - generates a public/private keypair").unwrap();
+    writeln!(output, "sign <msg> <priv_key_mod> <priv_key_exp>- signs a message with private key").unwrap();
+    writeln!(output, "verify <msg> <signature> <pub_key_mod> <pub_key_exp> - verifies a message").unwrap();
+    writeln!(output, "aggregate-verify <msg> <sig1> <n1> <e1> [<sig2> <n2> <e2> ...] - verifies that every signer signed a message").unwrap();
+    writeln!(output, "keyspace [bits] - shows how many keys are possible (for this program, or for a given modulus size)").unwrap();
+    writeln!(output, "classify <modulus> - shows how strong a key with this modulus is").unwrap();
+    writeln!(output, "blind <msg> <pub_key_mod> <pub_key_exp> <r> - blinds the hash of a message with the blinding factor r").unwrap();
+    writeln!(output, "sign-blinded <blinded> <priv_key_mod> <priv_key_exp> - signs a blinded hash without seeing the message").unwrap();
+    writeln!(output, "unblind <blind_signature> <pub_key_mod> <r> - turns a blind signature into a signature of the message").unwrap();
+    writeln!(output, "check-coprime <a> <b> - shows the greatest common divisor of two numbers, and whether they are coprime").unwrap();
+    writeln!(output, "selftest - generates a keypair and checks that it signs and verifies a message").unwrap();
+    writeln!(output, "crack <pub_key_mod> <pub_key_exp> - recovers the private key from a public key, by factoring the modulus").unwrap();
+    writeln!(output, "keyspace-attack <bits> - generates a key with a modulus of about this many bits (8-32) and times cracking it").unwrap();
+    writeln!(output, "sign-batch <file> <priv_key_mod> <priv_key_exp> - signs every (non-blank) line of a file, or of standard input if <file> is -").unwrap();
+    writeln!(output, "encrypt <msg> <pub_key_mod> <pub_key_exp> - encrypts each byte of a message with a public key").unwrap();
+    writeln!(output, "decrypt \"<c1> <c2> ...\" <priv_key_mod> <priv_key_exp> - decrypts a message made by encrypt").unwrap();
+    writeln!(output, "sign / verify --hash default|sha256 - which hash function to sign with (default is Rust's DefaultHasher, which can change between Rust versions)").unwrap();
+    writeln!(output, "sign --blind <pub_key_exp> ... - blind the hash with a random number before signing, so the time signing takes says nothing about the private key").unwrap();
+    writeln!(output, "sign / verify --file <path> ... - sign or verify the contents of a file instead of a message").unwrap();
+    writeln!(output, "For sign and verify, a <msg> of - reads the message from standard input").unwrap();
+    writeln!(output, "Options:").unwrap();
+    writeln!(output, "generate --algo carmichael|euler - which totient function to use (default carmichael)").unwrap();
+    writeln!(output, "generate --profile-keygen - also show how long each step of generating the keys took").unwrap();
+    writeln!(output, "generate --prime-rounds <k> - how many Miller-Rabin witnesses to check primes with (1-12, default 5)").unwrap();
+    writeln!(output, "generate --no-rng-note - do not print the note about secure random number generators").unwrap();
+    writeln!(output, "generate --threads <n> - search for the primes with n threads at once (not with --seed, --insecure-rng, --profile-keygen or --bits)").unwrap();
+    writeln!(output, "generate --seed <n> - generate the same keys every time for the same seed, e.g. for marking exercises").unwrap();
+    writeln!(output, "generate --insecure-rng <seed> - INSECURE: generate keys from a 16-bit seed, to show how easily they are found").unwrap();
+    writeln!(output, "generate --bits <n> - generate a key with an n-bit modulus using BigUints (16-4096, not with --qr or --profile-keygen)").unwrap();
+    writeln!(output, "generate --out <file> - also save the key pair to a file").unwrap();
+    writeln!(output, "sign <msg> --key <file> / verify <msg> <signature> --key <file> - use the keys saved by generate --out").unwrap();
+    writeln!(output, "generate --verbose - also show the primes, modulus and totient the keys were made from (not with --json, --threads, --profile-keygen or --bits)").unwrap();
+    writeln!(output, "generate --json - print only the keys, as a JSON object (not with --qr or --profile-keygen)").unwrap();
+    writeln!(output, "generate --qr - also print the public key as a short string to put in a QR code").unwrap();
+    writeln!(output, "sign --show-hash - also print the hash of the message which was signed").unwrap();
+    writeln!(output, "sign --nonce <n> / --timestamp - sign the message together with a nonce (or the current time)").unwrap();
+    writeln!(output, "verify / aggregate-verify --nonce <n> - verify a signature made with sign --nonce or --timestamp").unwrap();
+    writeln!(output, "sign / verify --encode decimal|base58|base64url|hex - how the signature is written (default decimal)").unwrap();
+    writeln!(output, "sign / verify --hex - the same as --encode hex (verify also reads any signature starting with 0x as hex)").unwrap();
+    writeln!(output, "verify --check-modulus - warn if the public key modulus is weak").unwrap();
+}
 
-/// Check primality of a given unsigned integer
-/// This is a pretty straightforward implementation of the `6k +/- 1` trial
-/// division primality test described
-/// [here](https://en.wikipedia.org/wiki/Primality_test#Simple_methods).
+/// The optional flags which can be passed to the different functions
+#[derive(Default)]
+struct Flags {
+    show_hash: bool,
+    check_modulus: bool,
+    nonce: Option<u64>,
+    timestamp: bool,
+    encoding: Encoding,
+    totient: Totient,
+    qr: bool,
+    profile_keygen: bool,
+    prime_rounds: usize,
+    no_rng_note: bool,
+    insecure_seed: Option<u16>,
+    seed: Option<u64>,
+    threads: Option<usize>,
+    json: bool,
+    bits: Option<u64>,
+    out: Option<String>,
+    key: Option<String>,
+    file: bool,
+    hash: HashFunction,
+    blind: Option<u32>,
+    verbose: bool,
+}
 
-fn is_prime(n: u32) -> bool {
-    if n <= 3 {
-        return n > 1;
-    } else if n % 2 == 0 || n % 3 == 0 {
-        return false;
+impl Flags {
+    /// The seed to generate keys from, if they should come from a seeded
+    /// (and so reproducible) random number generator instead of
+    /// rand::thread_rng() - given by either --seed or --insecure-rng.
+    fn rng_seed(&self) -> Option<u64> {
+        self.seed.or_else(|| self.insecure_seed.map(u64::from))
     }
+}
 
-    let mut i = 5;
+/// A message to sign or verify - either text from the command line (or
+/// standard input), or the raw bytes of a file given with --file, which
+/// might not be valid UTF-8.  Hashing a Text gives exactly the same hash as
+/// hashing the String itself, so signatures of text do not change.
+enum Message {
+    Text(String),
+    Bytes(Vec<u8>),
+}
 
-    while i * i <= n {
-        if n % i == 0 || n % (i + 2) == 0 {
-            return false;
+impl Hash for Message {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Message::Text(s) => s.hash(state),
+            Message::Bytes(b) => b.hash(state),
         }
-        i += 6;
     }
-                
-    true
-        
 }
 
-/// This function will return a random prime.
-/// It does this by randomly generating an integer and testing if it's
-/// prime.  There are definitely more efficient algorithms for this,
-/// but this is meant to be as simple as possible.
-
-fn get_random_prime(rng: &mut rand::prelude::ThreadRng) -> u32 {
-
-    // Generate a random 16-bit unsigned integer.
-    let mut p: u32; 
-
-    // Keep generating random numbers and putting them in `p` until
-    // the generated number is found to be prime.
-    // Note that Rust does not have a do...while equivalent, so this
-    // break statement may seem strange if you are coming from a different
-    // language.
-    loop {
-        
-        p = rng.gen_range(3, MAX_KEY_VAL);
-
-        if is_prime(p) {
-            break;
+impl Message {
+    /// The hash of the message with the given hash function.  SHA-256
+    /// hashes the bytes of the message (for text, its UTF-8 bytes).
+    fn digest(&self, hash: HashFunction) -> u32 {
+        match (hash, self) {
+            (HashFunction::Default, _) => get_hash(self),
+            (HashFunction::Sha256, Message::Text(s)) => get_hash_sha256(s.as_bytes()),
+            (HashFunction::Sha256, Message::Bytes(b)) => get_hash_sha256(b),
         }
-
     }
 
-    // Return the last generated number, which should be prime
-    p
-
+    /// The message to actually sign when a nonce is used - see
+    /// nonced_message().  Files get the same "<nonce>:" prefix.
+    fn nonced(self, nonce: u64) -> Message {
+        match self {
+            Message::Text(s) => Message::Text(nonced_message(&s, nonce)),
+            Message::Bytes(b) => {
+                let mut nonced = format!("{}:", nonce).into_bytes();
+                nonced.extend(b);
+                Message::Bytes(nonced)
+            },
+        }
+    }
 }
 
-// Check to see if two integers, x and y are coprime.  Numbers are coprime
-// if their only common factor is 1.  For example, 4 and 8 are not coprime
-// since they both contain 2 as one of their factors; 5 and 8 are coprime
-// since they do not share any factors.
-
-fn is_coprime(x: u32, y: u32) -> bool {
-    num::integer::gcd(x, y) == 1
+/// The ways a signature can be written out
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum Encoding {
+    /// A plain decimal number (the default)
+    #[default]
+    Decimal,
+    /// Base58, as used by Bitcoin
+    Base58,
+    /// URL-safe base64 of the four (big-endian) bytes, without padding
+    Base64Url,
+    /// Hexadecimal, written with a leading "0x"
+    Hex,
 }
 
-// A simple implementation of the Carmichael's totient function
-fn carmichael_totient(x: u32, y: u32) -> u32 {
-    num::integer::lcm(x - 1, y - 1)
+/// Remove the flag `name` from `args` if it is present, returning whether
+/// or not it was found.  This lets flags appear anywhere on the command line
+/// without affecting the argument counts checked by `args_good()`.
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().position(|a| a == name) {
+        Some(i) => {
+            args.remove(i);
+            true
+        },
+        None => false
+    }
 }
 
-// Modular multiplicative inverse code based on Rosetta Code's MMI code:
-// https://rosettacode.org/wiki/Modular_inverse#Rust
-
-fn mmi(a_unsigned: u32, m_unsigned: u32) -> u32 {
+/// Remove the option `name` and the value following it from `args`,
+/// returning the value if the option was given.  It is an error for the
+/// option to be the last argument, since then it has no value.
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>, String> {
+    match args.iter().position(|a| a == name) {
+        Some(i) => {
+            if i + 1 >= args.len() {
+                return Err(format!("{} requires a value", name));
+            }
+            let val = args.remove(i + 1);
+            args.remove(i);
+            Ok(Some(val))
+        },
+        None => Ok(None)
+    }
+}
 
-    // Generally, we have been using unsigned integers but we
-    // need signed for this algorithm.
-    let a: i64 = a_unsigned as i64;
-    let m: i64 = m_unsigned as i64;
-    
-    let mut mn = (m, a);
-    let mut xy = (0, 1);
-    
-    while mn.1 != 0 {
-        xy = (xy.1, xy.0 - (mn.0 / mn.1) * xy.1);
-        mn = (mn.1, mn.0 % mn.1);
+/// Pull all of the optional flags out of `args`, leaving only the arguments
+/// which `args_good()` knows how to check.
+fn parse_flags(args: &mut Vec<String>) -> Result<Flags, String> {
+    let mut flags = Flags {
+        show_hash: take_flag(args, "--show-hash"),
+        check_modulus: take_flag(args, "--check-modulus"),
+        timestamp: take_flag(args, "--timestamp"),
+        qr: take_flag(args, "--qr"),
+        profile_keygen: take_flag(args, "--profile-keygen"),
+        prime_rounds: DEFAULT_PRIME_ROUNDS,
+        no_rng_note: take_flag(args, "--no-rng-note"),
+        json: take_flag(args, "--json"),
+        verbose: take_flag(args, "--verbose"),
+        ..Default::default()
+    };
+
+    if let Some(nonce) = take_option(args, "--nonce")? {
+        flags.nonce = Some(nonce.parse::<u64>()
+                           .map_err(|_| "nonce must be a non-negative integer".to_string())?);
     }
-    
-    while xy.0 < 0 {
-        xy.0 += m;
+    if let Some(encoding) = take_option(args, "--encode")? {
+        flags.encoding = match encoding.as_ref() {
+            "decimal" => Encoding::Decimal,
+            "base58" => Encoding::Base58,
+            "base64url" => Encoding::Base64Url,
+            "hex" => Encoding::Hex,
+            _ => return Err(format!("Unknown encoding: {}", encoding)),
+        };
     }
-
-    if xy.0 > 0 {
-        xy.0 as u32
-    } else {
-        // This should not happen - if it does, there's a bug in my code
-        panic!("Received negative inverse");
+    if take_flag(args, "--hex") {
+        if flags.encoding != Encoding::Decimal && flags.encoding != Encoding::Hex {
+            return Err("--hex can not be used with another --encode".to_string());
+        }
+        flags.encoding = Encoding::Hex;
     }
-}
-
-// Given any object, return its 32-bit hash.  A hash is simply a fixed
-// size representation of an arbitrary amount of data.  For example,
-// a simple hash function might be to take all of the letters of a string,
-// add up their ASCII values, and return the result modulo 10.  No matter
-// what the size of the input, the ouput will always be one digit (0 - 9).
-// Another exercise in this course will delve deeply into hash functions.
-
-fn get_hash<T: Hash>(t: &T) -> u32 {
-    let mut s = DefaultHasher::new();
-    t.hash(&mut s);
-    let r = s.finish();
-    r as u32
-}
-
-// A helper function which might be useful for testing your key pair
-// generation - if `(d * e) % n == 1` does not hold, the key pair is
-// not valid.
-// The function attribute `#[allow(dead_code)]` is because under normal
-// cirumstances, this code will not be used - it's merely for debugging help.
-// But we don't really need a warning about this every time we compile -
-// this turns that off.
-
-#[allow(dead_code)]
-fn check_vals(d: u32, e: u32, n: u32) {
-    let bd = BigUint::from(d);
-    let be = BigUint::from(e);
-    let bn = BigUint::from(n);
-    let one = BigUint::from(1 as u32);
-    if (bd * be) % bn != one {
-        panic!("Error: (d * e) % n != 1");
-    } else {
-        println!("all is good");
+    if let Some(algo) = take_option(args, "--algo")? {
+        flags.totient = match algo.as_ref() {
+            "carmichael" => Totient::Carmichael,
+            "euler" => Totient::Euler,
+            _ => return Err(format!("Unknown totient: {}", algo)),
+        };
     }
-}
-
-// Raise x to the power of y modulo z and return the result.
-
-fn raise_power_modulo(x: u32, y: u32, z: u32) -> u32 {
-    // Internally convert to biguints, simply to take advantage of
-    // the built-in modpow() function
-    let xb: BigUint = BigUint::from(x);
-    let yb: BigUint = BigUint::from(y);
-    let zb: BigUint = BigUint::from(z);
-
-    let r  = xb.modpow(&BigUint::from(yb),
-                       &BigUint::from(zb));
 
-    r.to_u32().unwrap()
-
-}
+    if let Some(bits) = take_option(args, "--bits")? {
+        flags.bits = match bits.parse::<u64>() {
+            Ok(b) if (MIN_BIG_KEY_BITS..=MAX_BIG_KEY_BITS).contains(&b) => Some(b),
+            _ => return Err(format!("bits must be between {} and {}", MIN_BIG_KEY_BITS, MAX_BIG_KEY_BITS)),
+        };
+    }
+    flags.out = take_option(args, "--out")?;
+    flags.file = take_flag(args, "--file");
+    if let Some(hash) = take_option(args, "--hash")? {
+        flags.hash = match hash.as_ref() {
+            "default" => HashFunction::Default,
+            "sha256" => HashFunction::Sha256,
+            _ => return Err(format!("Unknown hash function: {}", hash)),
+        };
+    }
+    flags.key = take_option(args, "--key")?;
+    if let Some(seed) = take_option(args, "--insecure-rng")? {
+        flags.insecure_seed = Some(seed.parse::<u16>()
+                                   .map_err(|_| "insecure seed must be between 0 and 65535".to_string())?);
+    }
+    if let Some(seed) = take_option(args, "--seed")? {
+        if flags.insecure_seed.is_some() {
+            return Err("--seed and --insecure-rng can not be used together".to_string());
+        }
+        flags.seed = Some(seed.parse::<u64>()
+                          .map_err(|_| "seed must be a non-negative integer".to_string())?);
+    }
+    if let Some(threads) = take_option(args, "--threads")? {
+        flags.threads = match threads.parse::<usize>() {
+            Ok(t) if (1..=MAX_THREADS).contains(&t) => Some(t),
+            _ => return Err(format!("threads must be between 1 and {}", MAX_THREADS)),
+        };
+    }
+    if let Some(pub_key_exp) = take_option(args, "--blind")? {
+        flags.blind = Some(pub_key_exp.parse::<u32>()
+                           .map_err(|_| "the public exponent for --blind must be a 32-bit number".to_string())?);
+    }
+    if let Some(rounds) = take_option(args, "--prime-rounds")? {
+        flags.prime_rounds = match rounds.parse::<usize>() {
+            Ok(r) if (1..=MILLER_RABIN_WITNESSES.len()).contains(&r) => r,
+            _ => return Err(format!("prime rounds must be between 1 and {}",
+                                    MILLER_RABIN_WITNESSES.len())),
+        };
+    }
 
-/// Simple function to tell the user about appropriate usage and exit with exit code 1.
-fn print_usage_and_exit() {
-    println!("Usage:");
-    println!("generate - generates a public/private keypair");
-    println!("sign <msg> <priv_key_mod> <priv_key_exp>- signs a message with private key");
-    println!("verify <msg> <signature> <pub_key_mod> <pub_key_exp> - verifies a message");
-    std::process::exit(1);
+    Ok(flags)
 }
 
-
 /// "Wrapper function" to check that the arguments passed in are
 /// valid.  If all arguments are good, call the correct
 /// function (Generate, Sign, or Verify).
@@ -293,14 +363,14 @@ fn args_good(args: &Vec<String>) -> Result<Function, String> {
 
     if args.len() < 2 {
         return Err("Not enough arguments".to_string());
-    } else if args.len() > 6 {
+    } else if args.len() > 6 && args[1] != "aggregate-verify" {
         return Err("Too many arguments".to_string());
     }
 
     // A different number of arguments is expected for each of the
     // different functions
     
-    match args[1].as_ref() {
+    let function = match args[1].as_ref() {
         "generate" => {
             if args.len() != 2 {
                 Err("generate takes no arguments".to_string())
@@ -324,240 +394,995 @@ fn args_good(args: &Vec<String>) -> Result<Function, String> {
             }
 
         },
-        _ => {
-            Err("Unrecognized first argument".to_string())
+        "keyspace" => {
+            if args.len() > 3 {
+                Err("keyspace takes at most one argument".to_string())
+            } else {
+                Ok(Function::KeySpace)
+            }
+
         },
-    }
+        "classify" => {
+            if args.len() != 3 {
+                Err("classify requires one argument".to_string())
+            } else {
+                Ok(Function::Classify)
+            }
 
-}
+        },
+        "blind" => {
+            if args.len() != 6 {
+                Err("blind requires four arguments".to_string())
+            } else {
+                Ok(Function::Blind)
+            }
 
-// Simple helper function to print out a keypair
+        },
+        "sign-blinded" => {
+            if args.len() != 5 {
+                Err("sign-blinded requires three arguments".to_string())
+            } else {
+                Ok(Function::SignBlinded)
+            }
 
-fn print_keys(n: u32, d: u32, e: u32) {
-    println!("Private key: {}, {}", n, d);
-    println!("Public key: {}, {}", n, e);
-}
+        },
+        "unblind" => {
+            if args.len() != 5 {
+                Err("unblind requires three arguments".to_string())
+            } else {
+                Ok(Function::Unblind)
+            }
 
+        },
+        "check-coprime" => {
+            if args.len() != 4 {
+                Err("check-coprime requires two arguments".to_string())
+            } else {
+                Ok(Function::CheckCoprime)
+            }
 
-// ****************************************************************
-// WORK STARTS HERE
-// ****************************************************************
+        },
+        "keyspace-attack" => {
+            if args.len() != 3 {
+                Err("keyspace-attack requires one argument".to_string())
+            } else {
+                Ok(Function::KeyspaceAttack)
+            }
 
-// Given a random number generator, produce two distinct pseudorandom primes.
+        },
+        "encrypt" => {
+            if args.len() != 5 {
+                Err("encrypt requires three arguments".to_string())
+            } else {
+                Ok(Function::Encrypt)
+            }
 
-fn generate_two_primes(mut rng: &mut rand::prelude::ThreadRng) -> (u32, u32) {
+        },
+        "decrypt" => {
+            if args.len() != 5 {
+                Err("decrypt requires three arguments".to_string())
+            } else {
+                Ok(Function::Decrypt)
+            }
 
-    // TODO 1
-    
-    let mut p;
-    let mut q;
+        },
+        "sign-batch" => {
+            if args.len() != 5 {
+                Err("sign-batch requires three arguments".to_string())
+            } else {
+                Ok(Function::SignBatch)
+            }
 
-    // Generally this loop should not execute more than once, but on the
-    // off chance that we generate the same prime twice, we loop until
-    // they are distinct.
-    loop {
+        },
+        "crack" => {
+            if args.len() != 4 {
+                Err("crack requires two arguments".to_string())
+            } else {
+                Ok(Function::Crack)
+            }
 
-        // Step 1: Generate two random primes for p and q
-        //         Hint: the get_random_prime() function might be useful
-        
-        p = get_random_prime(&mut rng);
-        q = get_random_prime(&mut rng);
+        },
+        "selftest" => {
+            if args.len() != 2 {
+                Err("selftest does not take any arguments".to_string())
+            } else {
+                Ok(Function::SelfTest)
+            }
 
-        // Step 2: Break out of the loop if p and q are distinct (i.e.
-        //         not the same)
-        if p != q {
-            break;
+        },
+        "aggregate-verify" => {
+            // The message, then a signature, modulus and exponent for each
+            // of at least one signer
+            if args.len() < 6 || !(args.len() - 3).is_multiple_of(3) {
+                Err("aggregate-verify requires a message and then a signature, modulus and exponent for each signer".to_string())
+            } else {
+                Ok(Function::AggregateVerify)
+            }
+
+        },
+        _ => {
+            Err("Unrecognized first argument".to_string())
+        },
+    }?;
+
+    // Then check that every argument which should be a number is one, so
+    // that run() can parse them without having to worry about errors
+    for (j, name, size) in number_args(&function, args) {
+        check_number(&args[j], name, size)?;
+        // Nothing can be done modulo 0 or 1 (and modulo 0 would panic).
+        // Verifying is the exception - it says that the modulus is why a
+        // signature is invalid.
+        let verifying = matches!(function, Function::Verify | Function::AggregateVerify);
+        if name == "modulus" && !verifying && matches!(args[j].trim_start_matches('0'), "" | "1") {
+            return Err("the modulus must be at least 2".to_string());
         }
     }
-
-    // Step 3: Return p and q as a tuple
-    (p, q)
+    Ok(function)
 }
 
+/// How big a number an argument can be
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum NumberSize {
+    /// It must fit in a u32
+    U32,
+    /// It can be any size - keys for sign and verify can be BigUints
+    Any,
+}
 
+/// The arguments of a function which have to be numbers, as (index into
+/// args, what the argument is, how big it can be).  Signatures for sign and
+/// verify are not included, since they can be in base58 or base64url -
+/// decode_signature() checks those.
+fn number_args(function: &Function, args: &[String]) -> Vec<(usize, &'static str, NumberSize)> {
+    use NumberSize::*;
+    match function {
+        Function::Generate => vec![],
+        Function::Sign => vec![(3, "modulus", Any), (4, "exponent", Any)],
+        Function::Verify => vec![(4, "modulus", Any), (5, "exponent", Any)],
+        Function::KeySpace => (2..args.len()).map(|j| (j, "bits", U32)).collect(),
+        Function::Classify => vec![(2, "modulus", U32)],
+        Function::Blind => vec![(3, "modulus", U32), (4, "exponent", U32), (5, "blinding factor", U32)],
+        Function::SignBlinded => vec![(2, "blinded hash", U32), (3, "modulus", U32), (4, "exponent", U32)],
+        Function::Unblind => vec![(2, "blind signature", U32), (3, "modulus", U32), (4, "blinding factor", U32)],
+        Function::CheckCoprime => vec![(2, "number", U32), (3, "number", U32)],
+        Function::KeyspaceAttack => vec![(2, "bits", U32)],
+        Function::Crack => vec![(2, "modulus", U32), (3, "exponent", U32)],
+        Function::SelfTest => vec![],
+        Function::Encrypt | Function::Decrypt | Function::SignBatch => vec![(3, "modulus", U32), (4, "exponent", U32)],
+        Function::AggregateVerify => (3..args.len())
+            .map(|j| (j, ["signature", "modulus", "exponent"][(j - 3) % 3], U32))
+            .collect(),
+    }
+}
 
-// We need to pick a private exponent which is greater than 1 and less than
-// c, and is coprime with c.  This can be pseudorandomly generated via the
-// random number generator, rng, passed in via argumemt.
+/// Check that the argument `arg` (described by `name`, e.g. "modulus") is
+/// a non-negative integer, which fits in a u32 unless `size` is Any.
+fn check_number(arg: &str, name: &str, size: NumberSize) -> Result<(), String> {
+    if arg.is_empty() || !arg.bytes().all(|c| c.is_ascii_digit()) {
+        Err(format!("{} must be a non-negative integer", name))
+    } else if size == NumberSize::U32 && arg.parse::<u32>().is_err() {
+        Err(format!("{} must be at most {}", name, u32::MAX))
+    } else {
+        Ok(())
+    }
+}
 
-fn choose_private_exponent(c: u32, rng: &mut rand::prelude::ThreadRng) -> u32 {
+// Write a number out in base58.
 
-    // TODO 2
-    
-    let mut p;
-    
+fn encode_base58(x: u32) -> String {
+    // Repeatedly divide by 58, collecting the remainders as digits
+    // (least significant first).  0 is written as a single "zero"
+    // digit.
+    let mut digits = Vec::new();
+    let mut n = x;
     loop {
-        // Step 1: Generate a random integer betwen 2 and c
-        p = rng.gen_range(2, c);
-        
-        // Step 2: If the generated integer and c are coprime, break
-        //         out of the loop
-        if is_coprime(p, c) {
+        digits.push(BASE58_ALPHABET[(n % 58) as usize]);
+        n /= 58;
+        if n == 0 {
             break;
         }
     }
+    digits.reverse();
+    String::from_utf8(digits).unwrap()
+}
 
-    // Step 3: Return the gen
-    p
+// Read a number written out by encode_base58(), returning None if it is
+// not valid base58 or is too big for a u32.
 
+fn decode_base58(s: &str) -> Option<u32> {
+    if s.is_empty() {
+        return None;
+    }
+    let mut n: u32 = 0;
+    for c in s.bytes() {
+        let digit = BASE58_ALPHABET.iter().position(|&d| d == c)?;
+        n = n.checked_mul(58)?.checked_add(digit as u32)?;
+    }
+    Some(n)
 }
 
-// The public exponent is simply the multiplicative inverse of e modulo n
+// Write a public key (modulus n, exponent e) as a single short string,
+// "pk:<n>.<e>" with both numbers in base58.  This is small enough to fit
+// in a tiny QR code, so a key can be shared by pointing a phone camera at
+// the screen.
 
-fn compute_public_exponent(e: u32, n: u32) -> u32 {
+fn encode_public_key(n: u32, e: u32) -> String {
+    format!("pk:{}.{}", encode_base58(n), encode_base58(e))
+}
 
-    // TODO 3
+// Read a public key written out by encode_public_key(), returning the
+// modulus and exponent.
+// This program only ever writes these out (it is whoever scans the QR code
+// that reads them), so #[allow(dead_code)] stops the compiler from warning
+// that this is only used by the tests.
 
-    // Step 1: Generate and return the multiplicative inverse of e modulo n.
-    //         Hint: the mmi() function might be useful here.
-    mmi(e, n)
+#[allow(dead_code)]
+fn decode_public_key(s: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("Invalid public key: {}", s);
+    let (n, e) = s.strip_prefix("pk:")
+        .and_then(|rest| rest.split_once('.'))
+        .ok_or_else(invalid)?;
+    Ok((decode_base58(n).ok_or_else(invalid)?, decode_base58(e).ok_or_else(invalid)?))
 }
 
+// Write a signature out as a string using the given encoding.
+// Signatures are just numbers, but base58 and base64url are shorter and
+// safe to paste into chat messages or URLs.
+
+fn encode_signature(sig: u32, encoding: Encoding) -> String {
+    match encoding {
+        Encoding::Decimal => sig.to_string(),
+        Encoding::Hex => format!("{:#x}", sig),
+        Encoding::Base58 => encode_base58(sig),
+        Encoding::Base64Url => {
+            // Every 6 bits of the 4 bytes becomes one character, with the
+            // last character only using 2 bits (padded out with 0s).
+            let bits = (sig as u64) << 4;
+            (0..6).rev()
+                .map(|j| BASE64URL_ALPHABET[((bits >> (j * 6)) & 0x3f) as usize] as char)
+                .collect()
+        },
+    }
+}
 
-// Given a random number generator rng, return a keypair.  This keypair will
-// consist of a modulus, a private exponent, and a public exponent.
-// Since the modulus is shared between public and private keys, there is no
-// no need to send it back twice.
+// Read a signature which was written out by encode_signature() with the
+// same encoding.  The "0x" on a hex signature is optional, and a decimal
+// signature starting with "0x" is read as hex instead.
+
+fn decode_signature(s: &str, encoding: Encoding) -> Result<u32, String> {
+    let invalid = || format!("Invalid {:?} signature: {}", encoding, s);
+    match encoding {
+        Encoding::Decimal if s.starts_with("0x") => decode_signature(s, Encoding::Hex),
+        Encoding::Decimal => s.parse::<u32>().map_err(|_| invalid()),
+        Encoding::Hex => {
+            let digits = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+            // from_str_radix() would allow a leading "+"
+            if digits.is_empty() || !digits.bytes().all(|c| c.is_ascii_hexdigit()) {
+                return Err(invalid());
+            }
+            u32::from_str_radix(digits, 16).map_err(|_| invalid())
+        },
+        Encoding::Base58 => decode_base58(s).ok_or_else(invalid),
+        Encoding::Base64Url => {
+            if s.len() != 6 {
+                return Err(invalid());
+            }
+            let mut bits: u64 = 0;
+            for c in s.bytes() {
+                let digit = BASE64URL_ALPHABET.iter().position(|&d| d == c).ok_or_else(invalid)?;
+                bits = (bits << 6) | digit as u64;
+            }
+            // The 4 padding bits at the end must be 0, otherwise several
+            // strings would decode to the same signature
+            if bits & 0xf != 0 {
+                return Err(invalid());
+            }
+            Ok((bits >> 4) as u32)
+        },
+    }
+}
 
-fn generate_key_pair(mut rng: &mut rand::prelude::ThreadRng) -> (u32, u32, u32) {
+// Simple helper function to print out a keypair
 
-    // TODO 4
-    
-    // Step 1: Choose two distinct prime numbers, p and q.
-    //         I recommend you work on TODO 1 before this.
-    let (p, q) = generate_two_primes(&mut rng);
+fn print_keys(n: u32, d: u32, e: u32, output: &mut impl Write) {
+    writeln!(output, "Private key: {}, {}", n, d).unwrap();
+    writeln!(output, "Public key: {}, {}", n, e).unwrap();
+}
 
-    // Step 2: Compute m = p * q (will be the modulus)
-    let m = p * q;
+// Write a key pair as a JSON object, for other programs to read, e.g.
+// {"modulus":3233,"private_exponent":17,"public_exponent":413}
+// The numbers are plain decimal integers, so this works for BigUints too.
 
-    // Step 3: Compute n = Carmichael's totient function of p, q
-    //         Carmichael's Totient is simply lcm(p - 1, q - 1) - I have
-    //         included a helper function, carmichael_totient(), for you.
-    let n = carmichael_totient(p, q);
-    
-    // Step 4: Choose some e which is coprime to n and 1 < e < n
-    //         I recommend you work on TODO 2 before this.
-    let e = choose_private_exponent(n, &mut rng);
-    
-    // Step 5: Compute the modular multiplicative inverse for d
-    //           I recommend you work on TODO 3 before this.
-    let d = compute_public_exponent(e, n);
-
-    // DEBUG: Perform a sanity check before returning.
-    //         Verify that d * e = 1 modulo n.
-    //         If it does not, panic!
-    // If your code works, this is superfluous, but may be useful for
-    // testing.  Uncomment the next line to turn this check on.
-    // check_vals(d, e, n);
-    
-    // Return a three-tuple with the following elements:
-    // 1. Modulus (m)
-    // 2. Private Exponent (e)
-    // 3. Public Exponent (d)
-    (m, e, d)
+fn format_keys_json<T: std::fmt::Display>(n: T, d: T, e: T) -> String {
+    format!("{{\"modulus\":{},\"private_exponent\":{},\"public_exponent\":{}}}", n, d, e)
 }
 
+// The same as print_keys(), for keys which may not fit in a u32
 
-// Given a message, a private key modulus, and a private key exponent,
-// return its signature as a 32-bit unsigned integer.
+fn print_big_keys(keys: &BigKeyPair, output: &mut impl Write) {
+    writeln!(output, "Private key: {}, {}", keys.modulus, keys.private_exp).unwrap();
+    writeln!(output, "Public key: {}, {}", keys.modulus, keys.public_exp).unwrap();
+}
 
-fn sign_message(msg: String, priv_key_mod: u32, priv_key_exp: u32) -> u32 {
-    // TODO 5
-    
-    // Step 1: Produce a hash value of the message.  Note that I have
-    // included a get_hash() function for you to use.  
-    let h = get_hash(&msg);
-    
-    // Step 2: Raise the hash to the power of the private key exponent, modulo the
-    // private key modulus (which is, of course, same as the public key modulus).
-    // Note that I have included a raise_power_modulo() function.
-    let r = raise_power_modulo(h, priv_key_exp, priv_key_mod);
 
-    // Step 3: Return the result of the previous operation
-    r
-    
+// Generate a key pair from `rng` the way the flags ask for - timing each
+// step (and printing how long they took) if asked to profile it.  This is
+// generic over the random number generator so that --seed and --insecure-rng
+// can swap in a seeded one.
+
+fn generate_keys_for_flags(rng: &mut impl Rng, flags: &Flags, output: &mut impl Write) -> KeyPair {
+    if let Some(threads) = flags.threads {
+        generate_key_pair_threaded(rng, flags.totient, flags.prime_rounds, threads)
+    } else if flags.profile_keygen {
+        let (keys, profile) = generate_key_pair_profiled(rng, flags.totient, flags.prime_rounds);
+        print_keygen_profile(&profile, output);
+        keys
+    } else if flags.verbose {
+        let components = generate_key_components(rng, flags.totient, flags.prime_rounds);
+        print_key_components(&components, flags.totient, output);
+        components.key_pair()
+    } else {
+        generate_key_pair_with(rng, flags.totient, flags.prime_rounds)
+    }
 }
 
+// Print every value worked out while generating a key pair, in the order
+// they were worked out.
 
-// Given a message, a signature, a public key modulus, and a public key exponent,
-// return true if the signature was signed by the equivalent private key, or
-// false if not.
+fn print_key_components(c: &KeyComponents, totient: Totient, output: &mut impl Write) {
+    writeln!(output, "Prime p: {}", c.p).unwrap();
+    writeln!(output, "Prime q: {}", c.q).unwrap();
+    writeln!(output, "Modulus (p * q): {}", c.modulus).unwrap();
+    match totient {
+        Totient::Carmichael => writeln!(output, "Carmichael's totient (lcm(p - 1, q - 1)): {}", c.totient).unwrap(),
+        Totient::Euler => writeln!(output, "Euler's totient ((p - 1) * (q - 1)): {}", c.totient).unwrap(),
+    }
+    writeln!(output, "Private exponent (random, coprime to the totient): {}", c.private_exp).unwrap();
+    writeln!(output, "Public exponent (inverse of the private exponent modulo the totient): {}", c.public_exp).unwrap();
+}
 
-fn verify_signature(msg: String, sig: u32, pub_key_mod: u32, pub_key_exp: u32) -> bool {
+// Print how long each step of generating a key pair took, along with what
+// percentage of the total time it was.
+
+fn print_keygen_profile(profile: &KeygenProfile, output: &mut impl Write) {
+    let total = profile.primes + profile.private_exponent + profile.public_exponent;
+    let phases = [("generate_two_primes", profile.primes),
+                  ("choose_private_exponent", profile.private_exponent),
+                  ("compute_public_exponent", profile.public_exponent)];
+    for (name, time) in phases.iter() {
+        writeln!(output, "{}: {:?} ({:.1}%)",
+                 name, time, 100.0 * time.as_secs_f64() / total.as_secs_f64()).unwrap();
+    }
+}
 
-    // TODO 6
-    
-    // Step 1: Get the hash value of the message.
-    //         Remember there is a get_hash() function for you to use.
-    let h = get_hash(&msg);
-        
-    // Step 2: Raise the signature to the power of pub_key_exp modulo
-    //         pub_key_mod.  Remember there is a raise_power_modulo() function
-    //         for you to use.
-    let r = raise_power_modulo(sig, pub_key_exp, pub_key_mod);
+// Parse a key or signature which may be too big for a u32.
 
-    // Step 3: Return true if the result of the previous operation is equal to
-    // the hash value modulo the public key modulus, false otherwise.
-    r == h % pub_key_mod
+fn parse_biguint(s: &str) -> Result<BigUint, String> {
+    s.parse::<BigUint>().map_err(|_| format!("invalid number {}", s))
 }
 
-fn main() {
+// Return the current time as the number of seconds since the Unix epoch,
+// for use as a nonce.
 
-    // Get the arguments from the environment
-    
-    let mut args = Vec::new();
-    for argument in env::args() {
-        args.push(argument);
+fn current_timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Load the key pair saved in the key file `path` by `generate --out`.
+fn load_key_file(path: &str) -> Result<KeyPair, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("could not read key file {}: {}", path, e))?;
+    parse_key_file(&contents)
+}
+
+/// Add the key from a key file to the arguments of sign or verify, so that
+/// `sign <msg> --key <file>` works exactly like `sign <msg> <mod> <exp>` -
+/// sign gets the private key and verify gets the public key.  The arguments
+/// of any other function are left alone (and args_good() will complain if
+/// they are not right).
+fn add_key_args(args: &mut Vec<String>, keys: &KeyPair) {
+    match (args.get(1).map(String::as_str), args.len()) {
+        (Some("sign"), 3) => args.extend(vec![keys.modulus.to_string(), keys.private_exp.to_string()]),
+        (Some("verify"), 4) => args.extend(vec![keys.modulus.to_string(), keys.public_exp.to_string()]),
+        _ => {},
+    }
+}
+
+/// Get the message to sign or verify from its argument - with --file, the
+/// argument is a file to read, otherwise it is passed to get_message().
+fn read_message(arg: &str, file: bool, input: &mut impl Read) -> Result<Message, String> {
+    if file {
+        fs::read(arg).map(Message::Bytes).map_err(|e| format!("could not read {}: {}", arg, e))
+    } else {
+        Ok(Message::Text(get_message(arg, input)))
+    }
+}
+
+/// Get the message to sign or verify.  Normally this is simply the argument
+/// itself, but if the argument is "-", the message is read from `input`
+/// instead (with any trailing newline removed).
+fn get_message(arg: &str, input: &mut impl Read) -> String {
+    if arg == "-" {
+        let mut msg = String::new();
+        input.read_to_string(&mut msg).unwrap();
+        msg.trim_end_matches(&['\n', '\r'][..]).to_string()
+    } else {
+        arg.to_string()
+    }
+}
+
+/// The message which `selftest` signs.
+const SELF_TEST_MESSAGE: &str = "The quick brown fox jumps over the lazy dog";
+
+/// Generate a keypair, sign a message with it and check that the signature
+/// verifies, and that it no longer does once a bit of it has been flipped.
+/// Writes PASS or FAIL for each stage, and returns the stages that failed.
+fn self_test(rng: &mut impl Rng, output: &mut impl Write) -> Result<(), String> {
+    let mut failed = Vec::new();
+    let mut check = |stage: &'static str, passed: bool, output: &mut dyn Write| {
+        writeln!(output, "{}: {}", if passed { "PASS" } else { "FAIL" }, stage).unwrap();
+        if !passed {
+            failed.push(stage);
+        }
+    };
+
+    let keys = generate_key_pair(rng);
+    // The exponents must be inverses modulo Carmichael's totient
+    let inverses = factor_modulus(keys.modulus).is_some_and(|(p, q)| {
+        (keys.private_exp as u64 * keys.public_exp as u64) % carmichael_totient(p, q) as u64 == 1
+    });
+    check("generate a keypair", inverses, output);
+
+    let sig = sign_message(SELF_TEST_MESSAGE.to_string(), keys.modulus, keys.private_exp);
+    check("sign a message", sig < keys.modulus, output);
+
+    let verified = verify_signature(SELF_TEST_MESSAGE.to_string(), sig, keys.modulus, keys.public_exp);
+    check("verify the signature", verified, output);
+
+    // Flipping the lowest bit keeps the signature below the modulus,
+    // unless it was one less than an odd modulus
+    let tampered = if sig ^ 1 < keys.modulus { sig ^ 1 } else { sig ^ 2 };
+    let rejected = !verify_signature(SELF_TEST_MESSAGE.to_string(), tampered, keys.modulus, keys.public_exp);
+    check("reject a tampered signature", rejected, output);
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("self test failed: {}", failed.join(", ")))
+    }
+}
+
+/// Run the program with the command-line arguments `args` (including the
+/// program name), reading any message given as "-" from `input` and writing
+/// everything the program prints to `output`.
+/// Returns the exit code of the program - 0 on success, 1 if the arguments
+/// were bad or a signature did not verify.
+/// Keeping this separate from main() lets the tests drive the whole program
+/// without having to spawn a new process.
+fn run(mut args: Vec<String>, mut input: impl Read, mut output: impl Write) -> i32 {
+
+    // Pull out any flags before checking the rest of the arguments
+    let flags = match parse_flags(&mut args) {
+        Ok(f) => f,
+        Err(e) => {
+            writeln!(output, "Error: {}", e).unwrap();
+            print_usage(&mut output);
+            return 1;
+        },
+    };
+
+    if let Some(path) = &flags.key {
+        match load_key_file(path) {
+            Ok(keys) => add_key_args(&mut args, &keys),
+            Err(e) => {
+                writeln!(output, "Error: {}", e).unwrap();
+                return 1;
+            },
+        }
     }
 
     // Check if the arguments passed in from the command line are good
     let args_ok = args_good(&args);
 
     // If the arguments were good, perform the correct function
-    // Note that there is another way this can fail, if you pass in
-    // something that is expected to be an integer but cannot be parsed.
-    // This check should also probably be in args_good() but the error
-    // is pretty self-explanatory ("Invalid Digit")
+    // args_good() has already checked that every argument which should be
+    // a number is one, so parsing them here can not fail.
     
     // Otherwise, display the error, show usage, and exit
     match args_ok {
         Ok(f) => {
             match f {
+                Function::Generate if flags.threads.is_some()
+                    && (flags.rng_seed().is_some() || flags.profile_keygen || flags.bits.is_some()) => {
+                    // The threads use their own random number generators, so
+                    // the keys could not be reproduced from a seed
+                    writeln!(output, "Error: --threads can not be used with --seed, --insecure-rng, \
+                                      --profile-keygen or --bits").unwrap();
+                    1
+                },
+                Function::Generate if flags.json && (flags.qr || flags.profile_keygen) => {
+                    // Anything else printed would stop the output being JSON
+                    writeln!(output, "Error: --json can not be used with --qr or --profile-keygen").unwrap();
+                    1
+                },
+                Function::Generate if flags.verbose
+                    && (flags.json || flags.threads.is_some() || flags.profile_keygen || flags.bits.is_some()) => {
+                    writeln!(output, "Error: --verbose can not be used with --json, --threads, \
+                                      --profile-keygen or --bits").unwrap();
+                    1
+                },
+                Function::Generate if flags.bits.is_some() => {
+                    if flags.qr || flags.profile_keygen || flags.out.is_some() {
+                        writeln!(output, "Error: --bits can not be used with --qr, --profile-keygen or --out").unwrap();
+                        return 1;
+                    }
+                    let bits = flags.bits.unwrap();
+                    let keys = match flags.rng_seed() {
+                        Some(seed) => generate_big_key_pair(&mut StdRng::seed_from_u64(seed),
+                                                            bits, flags.totient),
+                        None => generate_big_key_pair(&mut rand::thread_rng(), bits, flags.totient),
+                    };
+                    if flags.json {
+                        writeln!(output, "{}", format_keys_json(&keys.modulus, &keys.private_exp,
+                                                                &keys.public_exp)).unwrap();
+                        return 0;
+                    }
+                    print_big_keys(&keys, &mut output);
+                    let bits = keys.modulus.bits() as u32;
+                    writeln!(output, "Key strength: {} ({}-bit modulus)", strength_label(bits), bits).unwrap();
+                    if !flags.no_rng_note && flags.rng_seed().is_none() {
+                        writeln!(output, "{}", CSPRNG_NOTE).unwrap();
+                    }
+                    0
+                },
                 Function::Generate => {
-                    let mut rng = rand::thread_rng();
-                    let (m, d, e) = generate_key_pair(&mut rng);
-                    print_keys(m, d, e);
+                    let keys = match flags.rng_seed() {
+                        Some(seed) => generate_keys_for_flags(&mut StdRng::seed_from_u64(seed),
+                                                              &flags, &mut output),
+                        None => generate_keys_for_flags(&mut rand::thread_rng(), &flags, &mut output),
+                    };
+                    if flags.json {
+                        writeln!(output, "{}", format_keys_json(keys.modulus, keys.private_exp,
+                                                                keys.public_exp)).unwrap();
+                    } else {
+                        print_keys(keys.modulus, keys.private_exp, keys.public_exp, &mut output);
+                        let bits = modulus_bits(keys.modulus);
+                        writeln!(output, "Key strength: {} ({}-bit modulus)", strength_label(bits), bits).unwrap();
+                    }
+                    if flags.qr {
+                        writeln!(output, "Public key (QR payload): {}",
+                                 encode_public_key(keys.modulus, keys.public_exp)).unwrap();
+                    }
+                    if let Some(path) = &flags.out {
+                        if let Err(e) = fs::write(path, format_key_file(&keys)) {
+                            writeln!(output, "Error: could not write key file {}: {}", path, e).unwrap();
+                            return 1;
+                        }
+                        if !flags.json {
+                            writeln!(output, "Wrote keys to {}", path).unwrap();
+                        }
+                    }
+                    if flags.json {
+                        // Only the JSON is printed
+                        return 0;
+                    }
+                    if let Some(seed) = flags.insecure_seed {
+                        writeln!(output, "Warning: these keys came from a generator seeded with {}, \
+                                          one of only 65536 possible seeds - anyone can find them \
+                                          by trying every seed!", seed).unwrap();
+                    } else if !flags.no_rng_note {
+                        match flags.seed {
+                            Some(seed) => writeln!(output, "Note: these keys came from seed {} - anyone who \
+                                                            knows the seed can generate them again", seed).unwrap(),
+                            None => writeln!(output, "{}", CSPRNG_NOTE).unwrap(),
+                        }
+                    }
+                    0
                 },
                 Function::Sign => {
-                    let msg: String = args[2].clone();
-                    let priv_key_mod = args[3].parse::<u32>().unwrap();
-                    let priv_key_exp = args[4].parse::<u32>().unwrap();
-                    let sig = sign_message(msg, priv_key_mod, priv_key_exp);
-                    println!("Signature: {}", sig);
+                    let mut msg = match read_message(&args[2], flags.file, &mut input) {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            writeln!(output, "Error: {}", e).unwrap();
+                            return 1;
+                        }
+                    };
+
+                    let nonce = if flags.timestamp {
+                        Some(current_timestamp())
+                    } else {
+                        flags.nonce
+                    };
+                    if let Some(n) = nonce {
+                        msg = msg.nonced(n);
+                        writeln!(output, "Nonce: {}", n).unwrap();
+                    }
+
+                    let h = msg.digest(flags.hash);
+                    if flags.show_hash {
+                        writeln!(output, "Message hash: {}", h).unwrap();
+                    }
+
+                    // Keys which are too big for a u32 are signed with BigUints
+                    match (args[3].parse::<u32>(), args[4].parse::<u32>()) {
+                        (Ok(priv_key_mod), Ok(priv_key_exp)) => {
+                            let sig = match flags.blind {
+                                Some(pub_key_exp) => {
+                                    match sign_hash_blinded(&mut rand::thread_rng(), h, priv_key_mod,
+                                                            priv_key_exp, pub_key_exp) {
+                                        Ok(sig) => sig,
+                                        Err(e) => {
+                                            writeln!(output, "Error: {}", e).unwrap();
+                                            return 1;
+                                        }
+                                    }
+                                },
+                                None => sign_hash(h, priv_key_mod, priv_key_exp),
+                            };
+                            writeln!(output, "Signature: {}", encode_signature(sig, flags.encoding)).unwrap();
+                        },
+                        _ if flags.blind.is_some() => {
+                            writeln!(output, "Error: --blind only works with keys which fit in 32 bits").unwrap();
+                            return 1;
+                        },
+                        _ => {
+                            let key = parse_biguint(&args[3]).and_then(|n| Ok((n, parse_biguint(&args[4])?)));
+                            let (priv_key_mod, priv_key_exp) = match key {
+                                Ok(key) if flags.encoding == Encoding::Decimal => key,
+                                Ok(_) => {
+                                    writeln!(output, "Error: big keys only support decimal signatures").unwrap();
+                                    return 1;
+                                },
+                                Err(e) => {
+                                    writeln!(output, "Error: {}", e).unwrap();
+                                    return 1;
+                                }
+                            };
+                            writeln!(output, "Signature: {}",
+                                     sign_hash_big(h, &priv_key_mod, &priv_key_exp)).unwrap();
+                        }
+                    }
+                    // The signature only verifies with the same hash function,
+                    // so say which one it needs (unless it is the default)
+                    if flags.hash == HashFunction::Sha256 {
+                        writeln!(output, "Hash function: sha256 (verify with --hash sha256)").unwrap();
+                    }
+                    0
+                },
+                Function::KeySpace => {
+                    // By default, show the key space for this program's
+                    // keys.  Otherwise, each prime has half of the bits
+                    // of the modulus.
+                    let prime_bits = if args.len() == 3 {
+                        args[2].parse::<u32>().unwrap() / 2
+                    } else {
+                        32 - (MAX_KEY_VAL - 1).leading_zeros()
+                    };
+                    if prime_bits < 2 {
+                        writeln!(output, "Error: the modulus must have at least 4 bits").unwrap();
+                        return 1;
+                    }
+                    print_key_space(prime_bits, &mut output);
+                    0
+                },
+                Function::Classify => {
+                    match args[2].parse::<u32>() {
+                        Ok(n) => {
+                            writeln!(output, "{} ({}-bit modulus)",
+                                     strength_label(modulus_bits(n)), modulus_bits(n)).unwrap();
+                            0
+                        },
+                        Err(_) => {
+                            writeln!(output, "Error: invalid modulus {}", args[2]).unwrap();
+                            1
+                        }
+                    }
                 },
                 Function::Verify => {
-                    let msg: String = args[2].clone();
-                    let sig = args[3].parse::<u32>().unwrap();
+                    let mut msg = match read_message(&args[2], flags.file, &mut input) {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            writeln!(output, "Error: {}", e).unwrap();
+                            return 1;
+                        }
+                    };
+                    if let Some(n) = flags.nonce {
+                        msg = msg.nonced(n);
+                    }
+
+                    // Keys which are too big for a u32 are checked with BigUints
+                    if args[4].parse::<u32>().is_err() || args[5].parse::<u32>().is_err() {
+                        let key = parse_biguint(&args[3])
+                            .and_then(|sig| Ok((sig, parse_biguint(&args[4])?, parse_biguint(&args[5])?)));
+                        let (sig, pub_key_mod, pub_key_exp) = match key {
+                            Ok(key) if flags.encoding == Encoding::Decimal => key,
+                            Ok(_) => {
+                                writeln!(output, "Error: big keys only support decimal signatures").unwrap();
+                                return 1;
+                            },
+                            Err(e) => {
+                                writeln!(output, "Error: {}", e).unwrap();
+                                return 1;
+                            }
+                        };
+                        return if verify_hash_big(msg.digest(flags.hash), &sig, &pub_key_mod, &pub_key_exp) {
+                            writeln!(output, "Signature verified!").unwrap();
+                            0
+                        } else {
+                            writeln!(output, "SIGNATURE INVALID!").unwrap();
+                            1
+                        };
+                    }
+
+                    let sig = match decode_signature(&args[3], flags.encoding) {
+                        Ok(sig) => sig,
+                        Err(e) => {
+                            writeln!(output, "Error: {}", e).unwrap();
+                            return 1;
+                        }
+                    };
                     let pub_key_mod = args[4].parse::<u32>().unwrap();
                     let pub_key_exp = args[5].parse::<u32>().unwrap();
 
-                    let r = verify_signature(msg, sig, pub_key_mod, pub_key_exp);
-                    if r {
-                        println!("Signature verified!");
+                    if flags.check_modulus {
+                        if let Some(warning) = check_modulus(pub_key_mod) {
+                            writeln!(output, "{}", warning).unwrap();
+                        }
+                    }
+                    // A mistyped exponent would otherwise just make every
+                    // signature look invalid.  (A modulus below 2 is
+                    // reported by verify_hash_detailed() instead.)
+                    if pub_key_mod >= 2 {
+                        if let Some(warning) = check_public_exponent(pub_key_mod, pub_key_exp) {
+                            writeln!(output, "{}", warning).unwrap();
+                        }
+                    }
+
+                    match verify_hash_detailed(msg.digest(flags.hash), sig, pub_key_mod, pub_key_exp) {
+                        Ok(()) => {
+                            writeln!(output, "Signature verified!").unwrap();
+                            0
+                        },
+                        Err(e) => {
+                            writeln!(output, "SIGNATURE INVALID!").unwrap();
+                            writeln!(output, "Reason: {}", e).unwrap();
+                            1
+                        }
+                    }
+
+                },
+                Function::Blind => {
+                    let msg: String = get_message(&args[2], &mut input);
+                    let pub_key_mod = args[3].parse::<u32>().unwrap();
+                    let pub_key_exp = args[4].parse::<u32>().unwrap();
+                    let r = args[5].parse::<u32>().unwrap();
+                    // r must have an inverse modulo n, or it could never
+                    // be removed again
+                    if r < 2 || r >= pub_key_mod || !is_coprime(r, pub_key_mod) {
+                        writeln!(output, "Error: the blinding factor must be between 2 and the modulus, and coprime to it").unwrap();
+                        return 1;
+                    }
+                    writeln!(output, "Blinded hash: {}", blind_message(msg, pub_key_mod, pub_key_exp, r)).unwrap();
+                    0
+                },
+                Function::SignBlinded => {
+                    let blinded = args[2].parse::<u32>().unwrap();
+                    let priv_key_mod = args[3].parse::<u32>().unwrap();
+                    let priv_key_exp = args[4].parse::<u32>().unwrap();
+                    writeln!(output, "Blind signature: {}", sign_blinded(blinded, priv_key_mod, priv_key_exp)).unwrap();
+                    0
+                },
+                Function::Unblind => {
+                    let blind_sig = args[2].parse::<u32>().unwrap();
+                    let pub_key_mod = args[3].parse::<u32>().unwrap();
+                    let r = args[4].parse::<u32>().unwrap();
+                    // unblind_signature() gives None if r is not coprime to n
+                    let sig = if r >= 2 && r < pub_key_mod {
+                        unblind_signature(blind_sig, pub_key_mod, r)
+                    } else {
+                        None
+                    };
+                    match sig {
+                        Some(sig) => {
+                            writeln!(output, "Signature: {}", encode_signature(sig, flags.encoding)).unwrap();
+                            0
+                        },
+                        _ => {
+                            writeln!(output, "Error: the blinding factor must be between 2 and the modulus, and coprime to it").unwrap();
+                            1
+                        }
+                    }
+                },
+                Function::CheckCoprime => {
+                    match (args[2].parse::<u32>(), args[3].parse::<u32>()) {
+                        (Ok(a), Ok(b)) => {
+                            writeln!(output, "gcd({}, {}) = {}", a, b, num::integer::gcd(a, b)).unwrap();
+                            if is_coprime(a, b) {
+                                writeln!(output, "{} and {} are coprime", a, b).unwrap();
+                            } else {
+                                writeln!(output, "{} and {} are not coprime", a, b).unwrap();
+                            }
+                            0
+                        },
+                        _ => {
+                            writeln!(output, "Error: invalid number {} or {}", args[2], args[3]).unwrap();
+                            1
+                        }
+                    }
+                },
+                Function::SelfTest => {
+                    match self_test(&mut rand::thread_rng(), &mut output) {
+                        Ok(()) => {
+                            writeln!(output, "All stages passed").unwrap();
+                            0
+                        },
+                        Err(e) => {
+                            writeln!(output, "Error: {}", e).unwrap();
+                            1
+                        }
+                    }
+                },
+                Function::Crack => {
+                    // This is what crack_key() does, but one step at a time
+                    // so that each step can be shown
+                    let pub_key_mod = args[2].parse::<u32>().unwrap();
+                    let pub_key_exp = args[3].parse::<u32>().unwrap();
+                    let (p, q) = match factor_modulus_counting(pub_key_mod) {
+                        (Some((p, q)), divisions) => {
+                            writeln!(output, "Factored {} = {} * {} with {} trial divisions",
+                                     pub_key_mod, p, q, divisions).unwrap();
+                            (p, q)
+                        },
+                        (None, divisions) => {
+                            writeln!(output, "Error: {} has no factors (tried {} trial divisions) - it is not \
+                                              a product of two primes", pub_key_mod, divisions).unwrap();
+                            return 1;
+                        }
+                    };
+                    let totient = carmichael_totient(p, q);
+                    writeln!(output, "Carmichael's totient: lcm({} - 1, {} - 1) = {}", p, q, totient).unwrap();
+                    match mmi(pub_key_exp, totient) {
+                        Some(priv_key_exp) => {
+                            writeln!(output, "Private key: {}, {}", pub_key_mod, priv_key_exp).unwrap();
+                            0
+                        },
+                        None => {
+                            writeln!(output, "Error: the exponent {} has no inverse modulo {}, so it is not \
+                                              part of a key for this modulus", pub_key_exp, totient).unwrap();
+                            1
+                        }
+                    }
+                },
+                Function::KeyspaceAttack => {
+                    let bits = match args[2].parse::<u32>() {
+                        Ok(b) if (MIN_ATTACK_BITS..=MAX_ATTACK_BITS).contains(&b) => b,
+                        _ => {
+                            writeln!(output, "Error: the key size must be between {} and {} bits",
+                                     MIN_ATTACK_BITS, MAX_ATTACK_BITS).unwrap();
+                            return 1;
+                        }
+                    };
+                    let (keys, cracked, elapsed) = keyspace_attack(&mut rand::thread_rng(), bits);
+                    writeln!(output, "Key: {}-bit modulus {}, public exponent {}",
+                             modulus_bits(keys.modulus), keys.modulus, keys.public_exp).unwrap();
+                    match cracked {
+                        Some(cracked) if cracked.private_exp == keys.private_exp => {
+                            writeln!(output, "Cracked in {:?}: private exponent is {}",
+                                     elapsed, cracked.private_exp).unwrap();
+                            writeln!(output, "Trial division needs up to 2^{} divisions for this size of key",
+                                     modulus_bits(keys.modulus).div_ceil(2)).unwrap();
+                            0
+                        },
+                        _ => {
+                            writeln!(output, "Failed to crack the key").unwrap();
+                            1
+                        }
+                    }
+                },
+                Function::SignBatch => {
+                    let contents = if args[2] == "-" {
+                        let mut contents = String::new();
+                        input.read_to_string(&mut contents).map(|_| contents)
+                    } else {
+                        fs::read_to_string(&args[2])
+                    };
+                    let contents = match contents {
+                        Ok(contents) => contents,
+                        Err(e) => {
+                            writeln!(output, "Error: could not read {}: {}", args[2], e).unwrap();
+                            return 1;
+                        }
+                    };
+                    let priv_key_mod = args[3].parse::<u32>().unwrap();
+                    let priv_key_exp = args[4].parse::<u32>().unwrap();
+                    let signed = sign_batch(contents.lines(), priv_key_mod, priv_key_exp);
+                    for (msg, sig) in signed.iter() {
+                        writeln!(output, "{}\t{}", msg, sig).unwrap();
+                    }
+                    writeln!(output, "Signed {} messages", signed.len()).unwrap();
+                    0
+                },
+                Function::Encrypt => {
+                    let msg: String = get_message(&args[2], &mut input);
+                    let pub_key_mod = args[3].parse::<u32>().unwrap();
+                    let pub_key_exp = args[4].parse::<u32>().unwrap();
+                    match encrypt_message(&msg, pub_key_mod, pub_key_exp) {
+                        Ok(cipher) => {
+                            let cipher: Vec<String> = cipher.iter().map(|c| c.to_string()).collect();
+                            writeln!(output, "{}", cipher.join(" ")).unwrap();
+                            0
+                        },
+                        Err(e) => {
+                            writeln!(output, "Error: {}", e).unwrap();
+                            1
+                        }
+                    }
+                },
+                Function::Decrypt => {
+                    let cipher = args[2].split_whitespace()
+                        .map(|c| c.parse::<u32>().map_err(|_| format!("invalid ciphertext {}", c)))
+                        .collect::<Result<Vec<u32>, String>>();
+                    let priv_key_mod = args[3].parse::<u32>().unwrap();
+                    let priv_key_exp = args[4].parse::<u32>().unwrap();
+                    match cipher.and_then(|c| decrypt_message(&c, priv_key_mod, priv_key_exp)) {
+                        Ok(msg) => {
+                            writeln!(output, "{}", msg).unwrap();
+                            0
+                        },
+                        Err(e) => {
+                            writeln!(output, "Error: {}", e).unwrap();
+                            1
+                        }
+                    }
+                },
+                Function::AggregateVerify => {
+                    let mut msg: String = get_message(&args[2], &mut input);
+                    if let Some(n) = flags.nonce {
+                        msg = nonced_message(&msg, n);
+                    }
+                    let signers: Vec<(u32, u32, u32)> = args[3..]
+                        .chunks(3)
+                        .map(|c| (c[0].parse::<u32>().unwrap(),
+                                  c[1].parse::<u32>().unwrap(),
+                                  c[2].parse::<u32>().unwrap()))
+                        .collect();
+
+                    let failed = aggregate_verify(&msg, &signers);
+                    if failed.is_empty() {
+                        writeln!(output, "All {} signatures verified!", signers.len()).unwrap();
+                        0
                     } else {
-                        println!("SIGNATURE INVALID!"); 
+                        for j in failed {
+                            let (sig, n, e) = signers[j];
+                            writeln!(output, "SIGNATURE INVALID for signer {} (signature {}, public key {}, {})",
+                                     j + 1, sig, n, e).unwrap();
+                        }
+                        1
                     }
 
                 },
             }
         },
         Err(e) => {
-            println!("Error: {}", e);
-            print_usage_and_exit();
+            writeln!(output, "Error: {}", e).unwrap();
+            print_usage(&mut output);
+            1
         },
     }
 }
 
+fn main() {
+
+    // Get the arguments from the environment
+    
+    let mut args = Vec::new();
+    for argument in env::args() {
+        args.push(argument);
+    }
+
+    let code = run(args, io::stdin(), io::stdout());
+    std::process::exit(code);
+}
+
 // Tests start here
 // Run "cargo test" to run all of them
 
@@ -566,180 +1391,850 @@ mod tests {
     use super::*;
 
     // ****************************************************************
-    // is_prime(n) function
+    // miller_rabin() function
     // ****************************************************************
 
-    
     #[test]
-    fn test_5_is_prime() {
-        assert!(is_prime(5), "5 should be prime");
+    fn test_run_generate_prime_rounds() {
+        assert_eq!(run_with(&["generate", "--prime-rounds", "12"], ""), 0);
     }
 
     #[test]
-    fn test_6_is_not_prime() {
-        assert!(!is_prime(6), "6 should not be prime");
+    fn test_run_generate_bad_prime_rounds() {
+        assert_eq!(run_with(&["generate", "--prime-rounds", "0"], ""), 1);
+        assert_eq!(run_with(&["generate", "--prime-rounds", "13"], ""), 1);
+        assert_eq!(run_with(&["generate", "--prime-rounds", "many"], ""), 1);
     }
 
+    // ****************************************************************
+    // factor_modulus(), crack_key() and keyspace_attack() functions
+    // ****************************************************************
+
     #[test]
-    fn test_1000_is_not_prime() {
-        assert!(!is_prime(1000), "1000 should not be prime");
+    fn test_run_keyspace_attack() {
+        let (code, output) = run_with_output(&["keyspace-attack", "20"], "");
+        assert_eq!(code, 0);
+        assert!(output.contains("\nCracked in "));
+        assert!(output.ends_with("Trial division needs up to 2^10 divisions for this size of key\n"));
+        assert_eq!(run_with(&["keyspace-attack", "7"], ""), 1);
+        assert_eq!(run_with(&["keyspace-attack", "33"], ""), 1);
     }
-    
+
+    // ****************************************************************
+    // KeyPair struct
+    // ****************************************************************
+
+    #[test]
+    fn test_run_generate_profile_keygen() {
+        let (code, output) = run_with_output(&["generate", "--profile-keygen"], "");
+        assert_eq!(code, 0);
+        assert!(output.starts_with("generate_two_primes: "));
+        assert!(output.contains("\nchoose_private_exponent: "));
+        assert!(output.contains("\ncompute_public_exponent: "));
+        assert!(output.contains("\nPrivate key: "));
+    }
+
     #[test]
-    fn test_1223_is_prime() {
-        assert!(is_prime(1223), "1223 should be prime");
+    fn test_run_generate_verbose() {
+        let (code, output) = run_with_output(&["generate", "--verbose", "--seed", "3"], "");
+        assert_eq!(code, 0);
+        let c = generate_key_components(&mut StdRng::seed_from_u64(3), Totient::Carmichael, DEFAULT_PRIME_ROUNDS);
+        assert!(output.starts_with(&format!(
+            "Prime p: {}\nPrime q: {}\nModulus (p * q): {}\n\
+             Carmichael's totient (lcm(p - 1, q - 1)): {}\n\
+             Private exponent (random, coprime to the totient): {}\n\
+             Public exponent (inverse of the private exponent modulo the totient): {}\n\
+             Private key: {}, {}\n",
+            c.p, c.q, c.modulus, c.totient, c.private_exp, c.public_exp, c.modulus, c.private_exp)));
+
+        let (code, output) = run_with_output(&["generate", "--verbose", "--algo", "euler"], "");
+        assert_eq!(code, 0);
+        assert!(output.contains("\nEuler's totient ((p - 1) * (q - 1)): "));
+        assert_eq!(run_with(&["generate", "--verbose", "--json"], ""), 1);
+        assert_eq!(run_with(&["generate", "--verbose", "--bits", "64"], ""), 1);
     }
 
+    #[test]
+    fn test_run_classify() {
+        let (code, output) = run_with_output(&["classify", "4228098967"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, "trivially crackable (32-bit modulus)\n");
+        assert_eq!(run_with(&["classify", "big"], ""), 1);
+        assert_eq!(run_with(&["classify"], ""), 1);
+    }
+
+    #[test]
+    fn test_run_generate_with_algo() {
+        assert_eq!(run_with(&["generate", "--algo", "euler"], ""), 0);
+        assert_eq!(run_with(&["generate", "--algo", "carmichael"], ""), 0);
+        assert_eq!(run_with(&["generate", "--algo", "fermat"], ""), 1);
+    }
     
     // ****************************************************************
-    // get_random_prime() function
+    // Big key functions
     // ****************************************************************
 
     #[test]
-    fn test_gets_random_prime() {
-        let mut rng = rand::thread_rng();
-        let p = get_random_prime(&mut rng);
-        assert!(is_prime(p));
+    fn test_run_big_keys_round_trip() {
+        let (code, output) = run_with_output(&["generate", "--bits", "128", "--no-rng-note"], "");
+        assert_eq!(code, 0);
+        let lines: Vec<&str> = output.lines().collect();
+        let private: Vec<&str> = lines[0].trim_start_matches("Private key: ").split(", ").collect();
+        let public: Vec<&str> = lines[1].trim_start_matches("Public key: ").split(", ").collect();
+        assert!(lines[2].starts_with("Key strength: very weak (128-bit modulus)"));
+
+        let (code, output) = run_with_output(&["sign", "meow", private[0], private[1]], "");
+        assert_eq!(code, 0);
+        let sig = output.trim_start_matches("Signature: ").trim_end().to_string();
+        assert_eq!(run_with(&["verify", "meow", &sig, public[0], public[1]], ""), 0);
+        assert_eq!(run_with(&["verify", "woof", &sig, public[0], public[1]], ""), 1);
+    }
+
+    #[test]
+    fn test_run_generate_bad_bits() {
+        assert_eq!(run_with(&["generate", "--bits", "8"], ""), 1);
+        assert_eq!(run_with(&["generate", "--bits", "8192"], ""), 1);
+        assert_eq!(run_with(&["generate", "--bits", "64", "--qr"], ""), 1);
     }
 
     // ****************************************************************
-    // coprimes() function
+    // Blind signature functions
     // ****************************************************************
 
     #[test]
-    fn test_8_5_are_coprime() {
-        assert!(is_coprime(8, 5), "8, 5 should be coprime");
+    fn test_run_blind_sign_unblind() {
+        let (code, output) = run_with_output(&["blind", "dog", "3233", "413", "5"], "");
+        assert_eq!(code, 0);
+        let blinded = output.trim_start_matches("Blinded hash: ").trim_end().to_string();
+
+        let (code, output) = run_with_output(&["sign-blinded", &blinded, "3233", "17"], "");
+        assert_eq!(code, 0);
+        let blind_sig = output.trim_start_matches("Blind signature: ").trim_end().to_string();
+
+        let (code, output) = run_with_output(&["unblind", &blind_sig, "3233", "5"], "");
+        assert_eq!(code, 0);
+        let sig = sign_message("dog".to_string(), 3233, 17);
+        assert_eq!(output, format!("Signature: {}\n", sig));
+        assert_eq!(run_with(&["verify", "dog", &sig.to_string(), "3233", "413"], ""), 0);
     }
 
     #[test]
-    fn test_8_6_are_not_coprime() {
-        assert!(!is_coprime(8, 6), "8, 6 should not be coprime");
+    fn test_run_blind_bad_factor() {
+        // 61 is a factor of 3233, so it has no inverse
+        assert_eq!(run_with(&["blind", "dog", "3233", "413", "61"], ""), 1);
+        assert_eq!(run_with(&["blind", "dog", "3233", "413", "1"], ""), 1);
+        assert_eq!(run_with(&["unblind", "5", "3233", "3233"], ""), 1);
     }
 
-    // TODO 1 test
+    #[test]
+    fn test_run_sign_blinded_zero_modulus() {
+        let (code, output) = run_with_output(&["sign-blinded", "5", "0", "3"], "");
+        assert_eq!(code, 1);
+        assert!(output.starts_with("Error: the modulus must be at least 2\n"));
+        assert_eq!(run_with(&["sign-blinded", "5", "1", "3"], ""), 1);
+    }
 
+    // ****************************************************************
+    // encode_signature() and decode_signature() functions
+    // ****************************************************************
+
+    #[test]
+    fn test_encode_signature_known_values() {
+        assert_eq!(encode_signature(0, Encoding::Base58), "1");
+        assert_eq!(encode_signature(57, Encoding::Base58), "z");
+        assert_eq!(encode_signature(58, Encoding::Base58), "21");
+        assert_eq!(encode_signature(0, Encoding::Base64Url), "AAAAAA");
+        assert_eq!(encode_signature(u32::MAX, Encoding::Base64Url), "_____w");
+        assert_eq!(encode_signature(11318728, Encoding::Decimal), "11318728");
+    }
 
-    // Generate 10 times.  At each iteration, the resultant values
-    // should both be prime and distinct from each other (i.e. not equal).
-    // This is kind of a poor man's stochastic testing. =)
     #[test]
-    fn test_generate_two_primes() {
+    fn test_encode_decode_signature_round_trips() {
         let mut rng = rand::thread_rng();
-        for _ in 0..10 {
-            let (p, q) = generate_two_primes(&mut rng);
-            assert!(p != q);
-            assert!(is_prime(p));
-            assert!(is_prime(q));
+        let mut values = vec![0, 1, 57, 58, 63, 64, u32::MAX - 1, u32::MAX];
+        values.extend((0..1000).map(|_| rng.gen::<u32>()));
+        for encoding in [Encoding::Decimal, Encoding::Base58, Encoding::Base64Url, Encoding::Hex].iter() {
+            for &v in values.iter() {
+                let s = encode_signature(v, *encoding);
+                assert_eq!(decode_signature(&s, *encoding), Ok(v));
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_signature_invalid() {
+        assert!(decode_signature("", Encoding::Base58).is_err());
+        assert!(decode_signature("0", Encoding::Base58).is_err());
+        // Bigger than u32::MAX
+        assert!(decode_signature("zzzzzzz", Encoding::Base58).is_err());
+        assert!(decode_signature("AAAAA", Encoding::Base64Url).is_err());
+        assert!(decode_signature("AAAA+A", Encoding::Base64Url).is_err());
+        assert!(decode_signature("AAAAAB", Encoding::Base64Url).is_err());
+        assert!(decode_signature("0x", Encoding::Hex).is_err());
+        assert!(decode_signature("+ff", Encoding::Hex).is_err());
+        assert!(decode_signature("0xfg", Encoding::Hex).is_err());
+        assert!(decode_signature("0x100000000", Encoding::Hex).is_err());
+        assert!(decode_signature("0x", Encoding::Decimal).is_err());
+    }
+
+    #[test]
+    fn test_encode_decode_hex_signature() {
+        assert_eq!(encode_signature(11318728, Encoding::Hex), "0xacb5c8");
+        assert_eq!(encode_signature(0, Encoding::Hex), "0x0");
+        assert_eq!(encode_signature(u32::MAX, Encoding::Hex), "0xffffffff");
+        for s in ["0xacb5c8", "0XACB5C8", "acb5c8", "00acb5c8"].iter() {
+            assert_eq!(decode_signature(s, Encoding::Hex), Ok(11318728));
         }
+        // A leading 0x is spotted even without --hex
+        assert_eq!(decode_signature("0xacb5c8", Encoding::Decimal), Ok(11318728));
+        assert!(decode_signature("acb5c8", Encoding::Decimal).is_err());
     }
 
-    // TODO 2 tests
+    // ****************************************************************
+    // encode_public_key() and decode_public_key() functions
+    // ****************************************************************
 
     #[test]
-    fn test_choose_private_exponent() {
-        let c = 70429;
+    fn test_public_key_payload_round_trips() {
         let mut rng = rand::thread_rng();
-        for _ in 0..10 {
-            let p = choose_private_exponent(c, &mut rng);
-            assert!(is_coprime(p, c));
-            assert!(p > 1);
-            assert!(p < c);
+        for _ in 0..20 {
+            let KeyPair { modulus: m, public_exp: d, .. } = generate_key_pair(&mut rng);
+            assert_eq!(decode_public_key(&encode_public_key(m, d)), Ok((m, d)));
+        }
+        assert_eq!(decode_public_key(&encode_public_key(0, u32::MAX)), Ok((0, u32::MAX)));
+    }
+
+    #[test]
+    fn test_public_key_payload_known_value() {
+        assert_eq!(encode_public_key(58, 57), "pk:21.z");
+    }
+
+    #[test]
+    fn test_decode_public_key_invalid() {
+        assert!(decode_public_key("21.z").is_err());
+        assert!(decode_public_key("pk:21").is_err());
+        assert!(decode_public_key("pk:21.").is_err());
+        assert!(decode_public_key("pk:0.z").is_err());
+    }
+
+    #[test]
+    fn test_run_generate_qr() {
+        let (code, output) = run_with_output(&["generate", "--qr"], "");
+        assert_eq!(code, 0);
+        let lines: Vec<&str> = output.lines().collect();
+        let public: Vec<u32> = lines[1].trim_start_matches("Public key: ")
+            .split(", ")
+            .map(|x| x.parse().unwrap())
+            .collect();
+        let payload = lines[3].trim_start_matches("Public key (QR payload): ");
+        assert_eq!(decode_public_key(payload), Ok((public[0], public[1])));
+    }
+
+    // ****************************************************************
+    // run() function
+    // ****************************************************************
 
+    // Run the whole program with the given arguments (not including the
+    // program name) and standard input, returning the exit code.
+
+    fn run_with(args: &[&str], input: &str) -> i32 {
+        run_with_output(args, input).0
+    }
+
+    // Same as run_with(), but also returns everything which was printed.
+
+    fn run_with_output(args: &[&str], input: &str) -> (i32, String) {
+        let mut full_args = vec!["public_key".to_string()];
+        full_args.extend(args.iter().map(|a| a.to_string()));
+        let mut output = Vec::new();
+        let code = run(full_args, input.as_bytes(), &mut output);
+        (code, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_run_generate_sign_verify() {
+        assert_eq!(run_with(&["generate"], ""), 0);
+
+        let mut rng = rand::thread_rng();
+        let KeyPair { modulus: m, private_exp: e, public_exp: d } = generate_key_pair(&mut rng);
+        let (m_s, e_s, d_s) = (m.to_string(), e.to_string(), d.to_string());
+        assert_eq!(run_with(&["sign", "meow", &m_s, &e_s], ""), 0);
+
+        let sig = sign_message("meow".to_string(), m, e).to_string();
+        assert_eq!(run_with(&["verify", "meow", &sig, &m_s, &d_s], ""), 0);
+    }
+
+    #[test]
+    fn test_run_verify_reads_message_from_input() {
+        assert_eq!(run_with(&["verify", "-", "11318728", "4228098967", "26379711"],
+                            "dog\n"),
+                   0);
+    }
+
+    #[test]
+    fn test_print_keys() {
+        let mut output = Vec::new();
+        print_keys(4228098967, 97643729, 26379711, &mut output);
+        assert_eq!(String::from_utf8(output).unwrap(),
+                   "Private key: 4228098967, 97643729\nPublic key: 4228098967, 26379711\n");
+    }
+
+    #[test]
+    fn test_run_generate_output() {
+        let (code, output) = run_with_output(&["generate"], "");
+        assert_eq!(code, 0);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("Private key: "));
+        assert!(lines[1].starts_with("Public key: "));
+        assert!(lines[2].starts_with("Key strength: trivially crackable ("));
+        assert_eq!(lines[3], CSPRNG_NOTE);
+    }
+
+    #[test]
+    fn test_run_generate_no_rng_note() {
+        let (code, output) = run_with_output(&["generate", "--no-rng-note"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output.lines().count(), 3);
+        assert!(!output.contains("CSPRNG"));
+    }
+
+    #[test]
+    fn test_run_generate_seed_is_reproducible() {
+        let (code, first) = run_with_output(&["generate", "--seed", "18446744073709551615"], "");
+        assert_eq!(code, 0);
+        let (_, second) = run_with_output(&["generate", "--seed", "18446744073709551615"], "");
+        assert_eq!(first, second);
+        assert!(first.lines().last().unwrap().starts_with("Note: these keys came from seed 18446744073709551615"));
+        let (_, other) = run_with_output(&["generate", "--seed", "42"], "");
+        assert_ne!(first.lines().next(), other.lines().next());
+
+        let (_, quiet) = run_with_output(&["generate", "--seed", "42", "--no-rng-note"], "");
+        assert_eq!(quiet.lines().count(), 3);
+        let (_, big) = run_with_output(&["generate", "--seed", "42", "--bits", "64"], "");
+        assert_eq!(big, run_with_output(&["generate", "--seed", "42", "--bits", "64"], "").1);
+    }
+
+    #[test]
+    fn test_run_generate_seed_matches_library() {
+        let (_, output) = run_with_output(&["generate", "--seed", "7", "--no-rng-note"], "");
+        let keys = generate_key_pair_with(&mut StdRng::seed_from_u64(7), Totient::Carmichael, DEFAULT_PRIME_ROUNDS);
+        assert!(output.starts_with(&format!("Private key: {}, {}\nPublic key: {}, {}\n",
+                                            keys.modulus, keys.private_exp, keys.modulus, keys.public_exp)));
+    }
+
+    #[test]
+    fn test_run_generate_bad_seed() {
+        assert_eq!(run_with(&["generate", "--seed", "-1"], ""), 1);
+        assert_eq!(run_with(&["generate", "--seed", "x"], ""), 1);
+        assert_eq!(run_with(&["generate", "--seed", "1", "--insecure-rng", "1"], ""), 1);
+    }
+
+    #[test]
+    fn test_run_generate_threads() {
+        let (code, output) = run_with_output(&["generate", "--threads", "4", "--no-rng-note"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output.lines().count(), 3);
+        assert_eq!(run_with(&["generate", "--threads", "0"], ""), 1);
+        assert_eq!(run_with(&["generate", "--threads", "65"], ""), 1);
+        assert_eq!(run_with(&["generate", "--threads", "2", "--seed", "1"], ""), 1);
+    }
+
+    // Read the numbers back out of a JSON object printed by generate --json,
+    // as (name, number) pairs in order.  This only handles what
+    // format_keys_json() writes - an object of plain integers.
+
+    fn parse_json_numbers(json: &str) -> Vec<(String, String)> {
+        json.trim().strip_prefix('{').unwrap().strip_suffix('}').unwrap()
+            .split(',')
+            .map(|field| {
+                let (name, value) = field.split_once(':').unwrap();
+                assert!(value.bytes().all(|c| c.is_ascii_digit()), "{}", value);
+                (name.trim_matches('"').to_string(), value.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_format_keys_json() {
+        assert_eq!(format_keys_json(3233, 17, 413),
+                   r#"{"modulus":3233,"private_exponent":17,"public_exponent":413}"#);
+    }
+
+    #[test]
+    fn test_run_generate_json() {
+        let (code, output) = run_with_output(&["generate", "--json", "--seed", "5"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output.lines().count(), 1);
+        let keys = generate_key_pair_with(&mut StdRng::seed_from_u64(5), Totient::Carmichael, DEFAULT_PRIME_ROUNDS);
+        let fields = parse_json_numbers(&output);
+        assert_eq!(fields, vec![("modulus".to_string(), keys.modulus.to_string()),
+                                ("private_exponent".to_string(), keys.private_exp.to_string()),
+                                ("public_exponent".to_string(), keys.public_exp.to_string())]);
+
+        // The numbers are keys which really work
+        let sig = sign_message("dog".to_string(), fields[0].1.parse().unwrap(), fields[1].1.parse().unwrap());
+        assert!(verify_signature("dog".to_string(), sig, fields[0].1.parse().unwrap(), fields[2].1.parse().unwrap()));
+    }
+
+    #[test]
+    fn test_run_generate_json_big_keys() {
+        let (code, output) = run_with_output(&["generate", "--json", "--bits", "128"], "");
+        assert_eq!(code, 0);
+        let fields = parse_json_numbers(&output);
+        assert_eq!(fields.len(), 3);
+        assert!(fields[0].1.parse::<BigUint>().unwrap().bits() == 128);
+        assert_eq!(run_with(&["generate", "--json", "--qr"], ""), 1);
+    }
+
+    #[test]
+    fn test_run_generate_insecure_rng_is_reproducible() {
+        let (code, first) = run_with_output(&["generate", "--insecure-rng", "1234"], "");
+        assert_eq!(code, 0);
+        let (_, second) = run_with_output(&["generate", "--insecure-rng", "1234"], "");
+        assert_eq!(first, second);
+        assert!(first.lines().last().unwrap().starts_with("Warning: these keys came from a generator seeded with 1234"));
+        let (_, other) = run_with_output(&["generate", "--insecure-rng", "1235"], "");
+        assert_ne!(first.lines().next(), other.lines().next());
+    }
+
+    #[test]
+    fn test_insecure_rng_keys_can_be_found() {
+        // Knowing only the public key, try every seed until one gives it
+        let keygen = |seed: u16| generate_key_pair_with(&mut StdRng::seed_from_u64(seed as u64),
+                                                        Totient::Carmichael, DEFAULT_PRIME_ROUNDS);
+        let victim = keygen(1234);
+        let found = (0..=u16::MAX).find(|seed| keygen(*seed).public_key() == victim.public_key());
+        assert_eq!(found.map(keygen), Some(victim));
+    }
+
+    #[test]
+    fn test_run_generate_bad_insecure_seed() {
+        assert_eq!(run_with(&["generate", "--insecure-rng", "65536"], ""), 1);
+        assert_eq!(run_with(&["generate", "--insecure-rng", "-1"], ""), 1);
+    }
+
+    #[test]
+    fn test_run_verify_output() {
+        let (code, output) = run_with_output(&["verify", "dog", "11318728", "4228098967", "26379711"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, "Signature verified!\n");
+
+        let (code, output) = run_with_output(&["verify", "dog", "0", "4228098967", "26379711"], "");
+        assert_eq!(code, 1);
+        let expected = get_hash(&"dog".to_string()) % 4228098967;
+        assert_eq!(output, format!("SIGNATURE INVALID!\n\
+                                    Reason: the signature is of the hash 0, but the message's hash is {}\n", expected));
+    }
+
+    #[test]
+    fn test_run_verify_reasons() {
+        let (code, output) = run_with_output(&["verify", "dog", "5", "0", "413"], "");
+        assert_eq!(code, 1);
+        assert_eq!(output, "SIGNATURE INVALID!\nReason: the modulus must be at least 2\n");
+        let (code, output) = run_with_output(&["verify", "dog", "4000", "3233", "413"], "");
+        assert_eq!(code, 1);
+        assert_eq!(output, "SIGNATURE INVALID!\nReason: the signature must be smaller than the modulus\n");
+    }
+
+    #[test]
+    fn test_run_verify_big_exponent_tiny_modulus() {
+        for n in ["0", "1"].iter() {
+            let (code, output) = run_with_output(&["verify", "hello", "5", n, "99999999999"], "");
+            assert_eq!(code, 1);
+            assert_eq!(output, "SIGNATURE INVALID!\n");
         }
+    }
 
+    #[test]
+    fn test_run_verify_warns_about_exponent() {
+        let sig = sign_message("dog".to_string(), 3233, 17).to_string();
+        for e in ["0", "1", "13"].iter() {
+            let (code, output) = run_with_output(&["verify", "dog", &sig, "3233", e], "");
+            assert_eq!(code, 1);
+            assert!(output.starts_with(&format!("Warning: public exponent {} ", e)), "{}", output);
+            assert!(output.contains("SIGNATURE INVALID!\n"));
+        }
+        let (_, output) = run_with_output(&["verify", "dog", &sig, "3233", "413"], "");
+        assert_eq!(output, "Signature verified!\n");
     }
 
-    // TODO 3 tests
-    // fn compute_public_exponent(e: u32, n: u32) -> u32 {
+    #[test]
+    fn test_run_bad_arguments_prints_usage() {
+        let (code, output) = run_with_output(&["frobnicate"], "");
+        assert_eq!(code, 1);
+        assert!(output.starts_with("Error: Unrecognized first argument\nUsage:\n"));
+    }
 
     #[test]
-    fn test_compute_public_exponent_1() {
-        let e: u32 = 600010331;
-        let n: u32 = 654955584;
-        let r = compute_public_exponent(e, n);
-        assert!(r == 4070099);
+    fn test_run_verify_invalid_signature() {
+        assert_eq!(run_with(&["verify", "dog", "0", "4228098967", "26379711"], ""), 1);
+    }
+
+    // The arguments `a` (including the program name) as Strings, as
+    // args_good() expects them.
+
+    fn args(a: &[&str]) -> Vec<String> {
+        a.iter().map(|s| s.to_string()).collect()
     }
 
     #[test]
-    fn test_compute_public_exponent_big() {
-        let e: u32 = 54741371;
-        let n: u32 = 314700540;
-        let r = compute_public_exponent(e, n);
-        assert!(r == 151583711);
+    fn test_check_number() {
+        assert_eq!(check_number("123", "modulus", NumberSize::U32), Ok(()));
+        assert_eq!(check_number("4294967295", "modulus", NumberSize::U32), Ok(()));
+        assert_eq!(check_number("abc", "modulus", NumberSize::U32),
+                   Err("modulus must be a non-negative integer".to_string()));
+        assert_eq!(check_number("", "modulus", NumberSize::U32),
+                   Err("modulus must be a non-negative integer".to_string()));
+        assert_eq!(check_number("-5", "exponent", NumberSize::Any),
+                   Err("exponent must be a non-negative integer".to_string()));
+        assert_eq!(check_number("4294967296", "modulus", NumberSize::U32),
+                   Err("modulus must be at most 4294967295".to_string()));
+        assert_eq!(check_number("123456789012345678901234567890", "modulus", NumberSize::Any), Ok(()));
     }
 
-    // TODO 4 tests
+    #[test]
+    fn test_args_good_checks_numbers() {
+        assert!(args_good(&args(&["p", "sign", "meow", "3233", "17"])).is_ok());
+        assert_eq!(args_good(&args(&["p", "sign", "meow", "abc", "123"])).err(),
+                   Some("modulus must be a non-negative integer".to_string()));
+        assert_eq!(args_good(&args(&["p", "verify", "meow", "5", "3233", ""])).err(),
+                   Some("exponent must be a non-negative integer".to_string()));
+        assert_eq!(args_good(&args(&["p", "blind", "meow", "3233", "413", "99999999999"])).err(),
+                   Some("blinding factor must be at most 4294967295".to_string()));
+        assert_eq!(args_good(&args(&["p", "aggregate-verify", "meow", "5", "3233", "413", "6", "x", "413"])).err(),
+                   Some("modulus must be a non-negative integer".to_string()));
+        assert!(args_good(&args(&["p", "keyspace", "-1"])).is_err());
+    }
 
     #[test]
-    fn test_generate_key_pair_hash_500() {
-        let h = 500;
-        let mut rng = rand::thread_rng();
-        for _ in 0..10 {
-            let (m, e, d) = generate_key_pair(&mut rng);
-            let r1 = raise_power_modulo(h, d, m);
-            let r2 = raise_power_modulo(r1, e, m);
-            assert!(r2 == h % m);
+    fn test_args_good_checks_modulus() {
+        let at_least_2 = Some("the modulus must be at least 2".to_string());
+        for bad in [&["sign", "meow", "0", "17"][..], &["sign", "meow", "001", "17"][..],
+                    &["sign-blinded", "5", "0", "3"][..], &["unblind", "5", "1", "3"][..],
+                    &["decrypt", "1", "0", "3"][..], &["sign-batch", "-", "1", "3"][..],
+                    &["crack", "0", "3"][..], &["classify", "1"][..]].iter() {
+            let mut full = vec!["p"];
+            full.extend_from_slice(bad);
+            assert_eq!(args_good(&args(&full)).err(), at_least_2, "{:?}", bad);
         }
+        assert!(args_good(&args(&["p", "sign", "meow", "2", "17"])).is_ok());
+        assert!(args_good(&args(&["p", "sign", "meow", "100000000000000000000", "17"])).is_ok());
+        // Verifying says why instead
+        assert!(args_good(&args(&["p", "verify", "meow", "5", "0", "413"])).is_ok());
+        assert!(args_good(&args(&["p", "aggregate-verify", "foo", "1", "0", "3"])).is_ok());
+    }
 
+    #[test]
+    fn test_run_malformed_number() {
+        let (code, output) = run_with_output(&["sign", "meow", "abc", "123"], "");
+        assert_eq!(code, 1);
+        assert!(output.starts_with("Error: modulus must be a non-negative integer\nUsage:"));
+        for bad in [&["sign-blinded", "5", "3233", ""][..], &["unblind", "5", "99999999999", "3"][..],
+                    &["encrypt", "hi", "3233", "4e2"][..], &["keyspace-attack", "x"][..]].iter() {
+            assert_eq!(run_with(bad, ""), 1, "{:?}", bad);
+        }
     }
 
     #[test]
-    fn test_generate_key_pair_hash_99999999() {
-        let h = 99999999;
-        let mut rng = rand::thread_rng();
+    fn test_run_bad_arguments() {
+        assert_eq!(run_with(&[], ""), 1);
+        assert_eq!(run_with(&["generate", "now"], ""), 1);
+        assert_eq!(run_with(&["frobnicate"], ""), 1);
+    }
+
+    #[test]
+    fn test_run_sign_verify_zero_modulus() {
+        let (code, output) = run_with_output(&["sign", "dog", "0", "17"], "");
+        assert_eq!(code, 1);
+        assert!(output.starts_with("Error: the modulus must be at least 2\n"));
+        assert_eq!(run_with(&["verify", "dog", "5", "0", "413"], ""), 1);
+    }
+
+    // A key file name in the temp directory which no other test uses
+
+    fn temp_key_file(name: &str) -> String {
+        let path = env::temp_dir().join(format!("public_key_{}_{}.txt", name, std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_run_generate_out_then_sign_with_key() {
+        let file = temp_key_file("generate_out");
+        let (code, output) = run_with_output(&["generate", "--out", &file, "--no-rng-note"], "");
+        assert_eq!(code, 0);
+        assert!(output.ends_with(&format!("Wrote keys to {}\n", file)));
+        let keys = load_key_file(&file).unwrap();
+        assert!(output.starts_with(&format!("Private key: {}, {}\n", keys.modulus, keys.private_exp)));
+
+        let (code, output) = run_with_output(&["sign", "meow", "--key", &file], "");
+        assert_eq!(code, 0);
+        let sig = output.trim_start_matches("Signature: ").trim_end().to_string();
+        assert_eq!(sig.parse::<u32>().unwrap(), sign_message("meow".to_string(), keys.modulus, keys.private_exp));
+        assert_eq!(run_with(&["verify", "meow", &sig, "--key", &file], ""), 0);
+        assert_eq!(run_with(&["verify", "woof", &sig, "--key", &file], ""), 1);
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_run_sign_with_truncated_key_file() {
+        let file = temp_key_file("truncated");
+        let contents = format_key_file(&KeyPair { modulus: 3233, private_exp: 17, public_exp: 413 });
+        std::fs::write(&file, &contents[..contents.len() - 5]).unwrap();
+        let (code, output) = run_with_output(&["sign", "meow", "--key", &file], "");
+        assert_eq!(code, 1);
+        assert_eq!(output, "Error: key file is truncated - missing end\n");
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(run_with(&["sign", "meow", "--key", &file], ""), 1);
+    }
+
+    #[test]
+    fn test_run_sign_verify_file() {
+        let file = temp_key_file("sign_file");
+        for contents in [&b""[..], &[0xff, 0xfe, 0x00, 0xc3, 0x28][..], b"a document\nwith lines\n"].iter() {
+            std::fs::write(&file, contents).unwrap();
+            let (code, output) = run_with_output(&["sign", "--file", &file, "3233", "17"], "");
+            assert_eq!(code, 0);
+            let sig = output.trim_start_matches("Signature: ").trim_end().to_string();
+            assert_eq!(sig.parse::<u32>().unwrap(), sign_data(*contents, 3233, 17));
+            assert_eq!(run_with(&["verify", "--file", &file, &sig, "3233", "413"], ""), 0);
+            assert_eq!(run_with(&["verify", "--file", &file, &sig, "3233", "413", "--nonce", "1"], ""), 1);
+        }
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_run_sign_missing_file() {
+        let file = temp_key_file("missing");
+        let (code, output) = run_with_output(&["sign", "--file", &file, "3233", "17"], "");
+        assert_eq!(code, 1);
+        assert!(output.starts_with(&format!("Error: could not read {}", file)));
+    }
+
+    #[test]
+    fn test_run_sign_verify_sha256() {
+        let (code, output) = run_with_output(&["sign", "dog", "3233", "17", "--hash", "sha256"], "");
+        assert_eq!(code, 0);
+        let sig = sign_hash(get_hash_sha256(b"dog"), 3233, 17);
+        assert_eq!(output, format!("Signature: {}\nHash function: sha256 (verify with --hash sha256)\n", sig));
+        let sig = sig.to_string();
+        assert_eq!(run_with(&["verify", "dog", &sig, "3233", "413", "--hash", "sha256"], ""), 0);
+        // The same signature does not verify with the default hash function
+        assert_eq!(run_with(&["verify", "dog", &sig, "3233", "413"], ""), 1);
+        assert_eq!(run_with(&["verify", "dog", &sig, "3233", "413", "--hash", "md5"], ""), 1);
+    }
+
+    #[test]
+    fn test_run_sign_blind() {
+        let (code, plain) = run_with_output(&["sign", "dog", "3233", "17"], "");
+        assert_eq!(code, 0);
         for _ in 0..10 {
-            let (m, e, d) = generate_key_pair(&mut rng);
-            let r1 = raise_power_modulo(h, d, m);
-            let r2 = raise_power_modulo(r1, e, m);
-            assert!(r2 == h % m);
+            let (code, blinded) = run_with_output(&["sign", "dog", "3233", "17", "--blind", "413"], "");
+            assert_eq!(code, 0);
+            assert_eq!(blinded, plain);
         }
+        let sig = plain.trim_start_matches("Signature: ").trim_end();
+        assert_eq!(run_with(&["verify", "dog", sig, "3233", "413"], ""), 0);
+    }
 
+    #[test]
+    fn test_run_sign_blind_errors() {
+        let (code, output) = run_with_output(&["sign", "dog", "3233", "17", "--blind", "7"], "");
+        assert_eq!(code, 1);
+        assert_eq!(output, "Error: the public exponent does not match the private key\n");
+        assert_eq!(run_with(&["sign", "dog", "3233", "17", "--blind", "e"], ""), 1);
+        let (code, output) = run_with_output(&["sign", "dog", "42289960510273", "17", "--blind", "413"], "");
+        assert_eq!(code, 1);
+        assert_eq!(output, "Error: --blind only works with keys which fit in 32 bits\n");
     }
-    
-    // TODO 5 tests
 
     #[test]
-    fn test_sign_message_foo() {
-        let msg: String = "foo".to_string();
-        let sig = sign_message(msg, 262373123, 120571543);
-        assert!(sig == 111862601);
+    fn test_run_sign_show_hash() {
+        assert_eq!(run_with(&["sign", "--show-hash", "meow", "1240214083", "97643729"], ""), 0);
     }
 
     #[test]
-    fn test_sign_message_bar() {
-        let msg: String = "bar".to_string();
-        let sig = sign_message(msg, 3360057163, 423721031);
-        assert!(sig == 2318946848);
+    fn test_self_test() {
+        for seed in 0..20 {
+            let mut output = Vec::new();
+            assert_eq!(self_test(&mut StdRng::seed_from_u64(seed), &mut output), Ok(()));
+            let output = String::from_utf8(output).unwrap();
+            assert_eq!(output.lines().count(), 4);
+            assert!(output.lines().all(|l| l.starts_with("PASS: ")));
+        }
     }
 
     #[test]
-    fn test_sign_message_meow() {
-        let msg: String = "meow".to_string();
-        let sig = sign_message(msg, 1240214083, 97643729);
-        assert!(sig == 866459596);
+    fn test_run_selftest() {
+        let (code, output) = run_with_output(&["selftest"], "");
+        assert_eq!(code, 0);
+        assert!(output.ends_with("PASS: reject a tampered signature\nAll stages passed\n"));
+        assert_eq!(run_with(&["selftest", "now"], ""), 1);
     }
 
-    
-    // TODO 6 tests
+    #[test]
+    fn test_run_crack() {
+        let (code, output) = run_with_output(&["crack", "3233", "413"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, "Factored 3233 = 53 * 61 with 27 trial divisions\n\
+                            Carmichael's totient: lcm(53 - 1, 61 - 1) = 780\n\
+                            Private key: 3233, 17\n");
+    }
 
-    // This signature is correct
     #[test]
-    fn test_verify_signature_dog_correct() {
-        assert!(verify_signature("dog".to_string(),
-                               11318728,
-                               4228098967,
-                               26379711));
+    fn test_run_crack_generated_key() {
+        for algo in ["carmichael", "euler"].iter() {
+            let (_, output) = run_with_output(&["generate", "--algo", algo, "--json"], "");
+            let keys: Vec<u32> = output.trim().split(|c: char| !c.is_ascii_digit())
+                .filter(|s| !s.is_empty())
+                .map(|s| s.parse().unwrap())
+                .collect();
+            let (n, e) = (keys[0].to_string(), keys[2].to_string());
+            let (code, output) = run_with_output(&["crack", &n, &e], "");
+            assert_eq!(code, 0);
+            // The recovered private exponent might not be the one which was
+            // generated (with Euler's totient), but it signs just the same
+            let d = output.lines().last().unwrap().rsplit(", ").next().unwrap();
+            let (_, sig) = run_with_output(&["sign", "meow", &n, d], "");
+            let sig = sig.trim_start_matches("Signature: ").trim_end();
+            assert_eq!(run_with(&["verify", "meow", sig, &n, &e], ""), 0);
+        }
     }
 
-    // This signature is incorrect
     #[test]
-    fn test_verify_signature_dog_incorrect() {
-        assert!(!verify_signature("dog".to_string(),
-                               0,
-                               4228098967,
-                               26379711));
+    fn test_run_crack_prime_modulus() {
+        let (code, output) = run_with_output(&["crack", "65537", "3"], "");
+        assert_eq!(code, 1);
+        assert!(output.starts_with("Error: 65537 has no factors"));
+        assert_eq!(run_with(&["crack", "3233", "60"], ""), 1);
+    }
+
+    #[test]
+    fn test_run_keyspace() {
+        assert_eq!(run_with(&["keyspace"], ""), 0);
+        assert_eq!(run_with(&["keyspace", "2048"], ""), 0);
+        assert_eq!(run_with(&["keyspace", "2"], ""), 1);
+        assert_eq!(run_with(&["keyspace", "32", "64"], ""), 1);
+    }
+
+    #[test]
+    fn test_run_check_coprime() {
+        let (code, output) = run_with_output(&["check-coprime", "8", "5"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, "gcd(8, 5) = 1\n8 and 5 are coprime\n");
+
+        let (code, output) = run_with_output(&["check-coprime", "8", "6"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, "gcd(8, 6) = 2\n8 and 6 are not coprime\n");
+
+        let (_, output) = run_with_output(&["check-coprime", "3120", "17"], "");
+        assert_eq!(output, "gcd(3120, 17) = 1\n3120 and 17 are coprime\n");
+
+        let (_, output) = run_with_output(&["check-coprime", "0", "12"], "");
+        assert_eq!(output, "gcd(0, 12) = 12\n0 and 12 are not coprime\n");
+    }
+
+    #[test]
+    fn test_run_check_coprime_bad_arguments() {
+        assert_eq!(run_with(&["check-coprime", "8", "x"], ""), 1);
+        assert_eq!(run_with(&["check-coprime", "8"], ""), 1);
+    }
+
+    #[test]
+    fn test_run_sign_batch() {
+        let (code, output) = run_with_output(&["sign-batch", "-", "3233", "17"], "dog\n\ncat\r\n  \nmeow");
+        assert_eq!(code, 0);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        for (line, msg) in lines.iter().zip(["dog", "cat", "meow"].iter()) {
+            assert_eq!(*line, format!("{}\t{}", msg, sign_message(msg.to_string(), 3233, 17)));
+        }
+        assert_eq!(lines[3], "Signed 3 messages");
+    }
+
+    #[test]
+    fn test_run_sign_batch_file() {
+        let file = temp_key_file("sign_batch");
+        std::fs::write(&file, "pay Alice 5\npay Bob 7\n").unwrap();
+        let (code, output) = run_with_output(&["sign-batch", &file, "3233", "17"], "");
+        assert_eq!(code, 0);
+        for line in output.lines().take(2) {
+            let (msg, sig) = line.split_once('\t').unwrap();
+            assert_eq!(run_with(&["verify", msg, sig, "3233", "413"], ""), 0);
+        }
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(run_with(&["sign-batch", &file, "3233", "17"], ""), 1);
+        assert_eq!(run_with(&["sign-batch", "-", "3233"], ""), 1);
+    }
+
+    #[test]
+    fn test_run_encrypt_decrypt() {
+        let (code, output) = run_with_output(&["encrypt", "hello", "3233", "413"], "");
+        assert_eq!(code, 0);
+        let cipher = output.trim_end();
+        assert_eq!(cipher.split(' ').count(), 5);
+        let (code, output) = run_with_output(&["decrypt", cipher, "3233", "17"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, "hello\n");
     }
-    
-}
 
+    #[test]
+    fn test_run_encrypt_decrypt_bad_arguments() {
+        assert_eq!(run_with(&["encrypt", "hello", "3233"], ""), 1);
+        assert_eq!(run_with(&["encrypt", "hello", "200", "3"], ""), 1);
+        assert_eq!(run_with(&["decrypt", "12 x 7", "3233", "17"], ""), 1);
+        let (code, output) = run_with_output(&["decrypt", "1", "200", "3"], "");
+        assert_eq!((code, output.as_str()), (1, "Error: the modulus must be bigger than 255\n"));
+        let (code, output) = run_with_output(&["decrypt", "1", "0", "3"], "");
+        assert_eq!(code, 1);
+        assert!(output.starts_with("Error: the modulus must be at least 2\n"));
+    }
 
+    #[test]
+    fn test_run_aggregate_verify() {
+        assert_eq!(run_with(&["aggregate-verify", "dog", "11318728", "4228098967", "26379711"], ""), 0);
+        assert_eq!(run_with(&["aggregate-verify", "dog",
+                              "11318728", "4228098967", "26379711",
+                              "11318729", "4228098967", "26379711"], ""), 1);
+        assert_eq!(run_with(&["aggregate-verify", "dog", "11318728", "4228098967"], ""), 1);
+    }
+
+    #[test]
+    fn test_run_aggregate_verify_modulus_below_2() {
+        let (code, output) = run_with_output(&["aggregate-verify", "foo", "1", "0", "3"], "");
+        assert_eq!(code, 1);
+        assert!(output.contains("SIGNATURE INVALID for signer 1"));
+    }
+
+    #[test]
+    fn test_run_verify_encoded_signature() {
+        let b58 = encode_signature(11318728, Encoding::Base58);
+        assert_eq!(run_with(&["verify", "dog", &b58, "4228098967", "26379711", "--encode", "base58"], ""), 0);
+        assert_eq!(run_with(&["verify", "dog", &b58, "4228098967", "26379711"], ""), 1);
+        assert_eq!(run_with(&["sign", "dog", "1240214083", "97643729", "--encode", "base64url"], ""), 0);
+        assert_eq!(run_with(&["sign", "dog", "1240214083", "97643729", "--encode", "base32"], ""), 1);
+    }
+
+    #[test]
+    fn test_run_sign_verify_hex() {
+        let (code, output) = run_with_output(&["sign", "dog", "3233", "17", "--hex"], "");
+        assert_eq!(code, 0);
+        let sig = sign_message("dog".to_string(), 3233, 17);
+        assert_eq!(output, format!("Signature: {:#x}\n", sig));
+        let hex = output.trim_start_matches("Signature: ").trim_end();
+        assert_eq!(run_with(&["verify", "dog", hex, "3233", "413", "--hex"], ""), 0);
+        assert_eq!(run_with(&["verify", "dog", hex, "3233", "413"], ""), 0);
+        assert_eq!(run_with(&["verify", "dog", &hex[2..], "3233", "413", "--hex"], ""), 0);
+        assert_eq!(run_with(&["verify", "cat", hex, "3233", "413", "--hex"], ""), 1);
+        assert_eq!(run_with(&["sign", "dog", "3233", "17", "--hex", "--encode", "base58"], ""), 1);
+        assert_eq!(run_with(&["sign", "dog", "3233", "17", "--hex", "--encode", "hex"], ""), 0);
+    }
+
+    #[test]
+    fn test_run_sign_verify_with_nonce() {
+        assert_eq!(run_with(&["sign", "dog", "1240214083", "97643729", "--nonce", "7"], ""), 0);
+        assert_eq!(run_with(&["sign", "dog", "1240214083", "97643729", "--timestamp"], ""), 0);
+        assert_eq!(run_with(&["sign", "dog", "1240214083", "97643729", "--nonce", "x"], ""), 1);
+    }
+
+}
@@ -0,0 +1,51 @@
+// Property-based tests for signing and verifying: instead of a handful of
+// fixed messages and keys, proptest tries lots of random ones.  Each key is
+// generated from a random seed, so a failing case can be reproduced with
+// `generate --seed <seed>`.
+
+use proptest::prelude::*;
+use public_key_solution::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+fn keys_from_seed(seed: u64) -> KeyPair {
+    generate_key_pair(&mut StdRng::seed_from_u64(seed))
+}
+
+proptest! {
+    // Generating a key takes a little while, so do not try too many
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn signed_message_verifies(seed in any::<u64>(), msg in "\\PC{0,20}") {
+        let keys = keys_from_seed(seed);
+        let sig = sign_message(msg.clone(), keys.modulus, keys.private_exp);
+        prop_assert!(sig < keys.modulus);
+        prop_assert!(verify_signature(msg, sig, keys.modulus, keys.public_exp));
+    }
+
+    #[test]
+    fn flipped_signature_bit_does_not_verify(seed in any::<u64>(), msg in "\\PC{0,20}") {
+        // Signing is a permutation of the numbers below the (odd) modulus,
+        // and flipping a bit always changes the signature modulo it, so no
+        // other signature can verify
+        let keys = keys_from_seed(seed);
+        let sig = sign_message(msg.clone(), keys.modulus, keys.private_exp);
+        for bit in 0..32 {
+            let flipped = sig ^ (1 << bit);
+            prop_assert!(!verify_signature(msg.clone(), flipped, keys.modulus, keys.public_exp),
+                         "flipping bit {} of {} still verifies", bit, sig);
+        }
+    }
+
+    #[test]
+    fn signature_of_other_message_does_not_verify(seed in any::<u64>(), msg in "\\PC{0,20}",
+                                                  other in "\\PC{0,20}") {
+        let keys = keys_from_seed(seed);
+        let sig = sign_message(msg.clone(), keys.modulus, keys.private_exp);
+        // Different messages can still have the same hash modulo n, and
+        // then the signatures are the same too
+        let same_hash = get_hash(&msg) % keys.modulus == get_hash(&other) % keys.modulus;
+        prop_assert_eq!(verify_signature(other, sig, keys.modulus, keys.public_exp), same_hash);
+    }
+}
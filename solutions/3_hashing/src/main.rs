@@ -41,8 +41,8 @@
 //! XORed with all of the other blocks which are left-shifted an incrementing number of
 //! times.  See the `twiddle()` function description for details.
 //!
-//! This `twiddle()` function is called 1,024 times, scrambling the block's bits more and
-//! more - but always in a deterministic way.
+//! This `twiddle()` function is called 1,024 times (by default - see `--rounds`), scrambling
+//! the block's bits more and more - but always in a deterministic way.
 //!
 //! There is also a `finalize()` function after all of this is processed, which for BillHash
 //! will simply perform a bitwise complement on the last compress value before returning.
@@ -54,7 +54,13 @@
 //!     +->     +->     +->
 //!  IV --> c() --> c() --> c() --> finalize() --> hash
 //! ```
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
 
 /// The size of the blocks (chunks of data) our hash function
 const BLOCK_SIZE: usize = 8;
@@ -62,11 +68,211 @@ const BLOCK_SIZE: usize = 8;
 /// The initialization vector (IV) of the BillHash function.
 const INITIALIZATION_VECTOR: u64 = 0x0123_4567_89AB_CDEF;
 
+/// The constant which `twiddle_v2()` adds to each block, byte by byte: the
+/// first 64 bits of the fractional part of the golden ratio
+/// (0x9E37_79B9_7F4A_7C15), as little-endian bytes.  Any constant would do,
+/// but using a well-known one shows that nothing has been hidden in it.
+const ROUND_CONSTANT: [u8; BLOCK_SIZE] = [0x15, 0x7C, 0x4A, 0x7F, 0xB9, 0x79, 0x37, 0x9E];
+
+/// How many times `transform()` runs `twiddle()` on each block, unless
+/// `--rounds` says otherwise.
+const DEFAULT_ROUNDS: u32 = 1024;
+
+/// Inputs with less than this many bits of (estimated) entropy are flagged
+/// as being easy to brute-force.
+const LOW_ENTROPY_BITS: f64 = 40.0;
+
+/// How long `--hashrate` spends measuring the hash rate.
+const HASH_RATE_SAMPLE: Duration = Duration::from_millis(500);
+
+/// The mining difficulties (number of leading 0 bits) which `--hashrate`
+/// estimates the mining time for.
+const MINING_DIFFICULTIES: [u32; 3] = [8, 16, 24];
+
+/// How many of the low bits of each hash value `--count-collisions` also
+/// checks for collisions, unless `--low-bits` says otherwise.
+const DEFAULT_COLLISION_BITS: u32 = 16;
+
+/// How many of the low bits of each hash value `collide` looks for a
+/// collision in, unless `--low-bits` says otherwise.  A collision on the
+/// full 64 bits would take about 2^32 hashes, and remembering every one of
+/// them, so this is 32 bits (about 2^16 hashes) instead.
+const DEFAULT_COLLIDE_BITS: u32 = 32;
+
+/// The most low bits `collide` will look for a collision in.  Each extra bit
+/// makes the search take about 1.4 times as long and remember 1.4 times as
+/// many hashes, so much past this it would run out of memory (or patience).
+const MAX_COLLIDE_BITS: u32 = 40;
+
+/// The characters which `collide` makes its random inputs out of.
+const COLLIDE_CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+
+/// The longest random input which `collide` tries.
+const COLLIDE_MAX_LEN: usize = 8;
+
+/// The different ways that `strengthen()` can pad the input out to a
+/// multiple of BLOCK_SIZE.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Padding {
+    /// Pad with 0s.  This is the original BillHash padding, and the default.
+    Zeros,
+    /// Add a single 0x80 byte (a 1 bit followed by 0 bits) and then pad with
+    /// 0s, like MD5 and the SHA family do.  Since the 0x80 marker is always
+    /// added, padding can never be confused with actual 0s at the end of the
+    /// input.  This gives different hash values than the original BillHash!
+    Marker,
+    /// Pad like `Marker`, and then compress one more block holding the
+    /// length of the input in bytes (as a little-endian u64).  This is the
+    /// "Merkle-Damgard strengthening" which MD5 and the SHA family use, and
+    /// means that inputs of different lengths are always hashed differently
+    /// right up to the last block.  This also gives different hash values!
+    Length,
+}
+
+/// The different versions of `twiddle()` which `transform()` can use.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TwiddleVersion {
+    /// The original `twiddle()`, which maps a block of 0s to itself.  This
+    /// is the default, and gives the original BillHash hash values.
+    V1,
+    /// `twiddle_v2()`, which also adds a round constant after twiddling, so
+    /// that a block of 0s does not stall.  This gives different hash values!
+    V2,
+}
+
+/// The different ways that `format_hash()` can write out a hash value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    /// Hexadecimal with a leading 0x, e.g. 0x45aaec6cd9f47e66.  The default.
+    Hex,
+    /// Decimal, e.g. 5020084686915141222
+    Dec,
+    /// The base64 encoding (with the standard alphabet and = padding) of the
+    /// same 8 little-endian bytes which `Bytes` writes out
+    Base64,
+    /// The 8 bytes of the hash value, least significant byte first, written
+    /// out as they are with nothing before or after them
+    Bytes,
+}
+
+/// Options which change how BillHash works.  The default options give the
+/// original BillHash hash values.
+#[derive(Clone, Copy, Debug)]
+struct HashOptions {
+    padding: Padding,
+    /// How many times to twiddle each block (see `transform()`)
+    rounds: u32,
+    /// Which `twiddle()` to use
+    twiddle: TwiddleVersion,
+}
+
+impl Default for HashOptions {
+    fn default() -> Self {
+        HashOptions {
+            padding: Padding::Zeros,
+            rounds: DEFAULT_ROUNDS,
+            twiddle: TwiddleVersion::V1,
+        }
+    }
+}
+
+
+/// Simple function to tell the user about appropriate usage.
+fn print_usage(output: &mut impl Write) {
+    writeln!(output, "Please enter one and only one argument").unwrap();
+    writeln!(output, "(use - to hash everything read from standard input)").unwrap();
+    writeln!(output, "Options:").unwrap();
+    writeln!(output, "  --entropy - estimate the entropy of the input before hashing it").unwrap();
+    writeln!(output, "  --dir <path> - hash every file under a directory instead of a string").unwrap();
+    writeln!(output, "  --padding zeros|marker|length - how to pad the input (default zeros)").unwrap();
+    writeln!(output, "    (marker pads with 0x80 then 0s, and length also adds a block with the input's length;").unwrap();
+    writeln!(output, "    both change all hash values)").unwrap();
+    writeln!(output, "  --rounds <n> - how many times to twiddle each block (default 1024)").unwrap();
+    writeln!(output, "    (fewer rounds mix the bits less, and change all hash values)").unwrap();
+    writeln!(output, "  --twiddle v1|v2 - which twiddle function to use (default v1)").unwrap();
+    writeln!(output, "    (v2 adds a round constant so blocks of 0s do not stall, which changes all hash values)").unwrap();
+    writeln!(output, "  --format hex|dec|base64|bytes - how to write out hash values (default hex)").unwrap();
+    writeln!(output, "    (bytes writes the 8 raw bytes of the hash, least significant first, and nothing else)").unwrap();
+    writeln!(output, "  hmac <key> <message> - work out the HMAC of message with key, instead of hashing").unwrap();
+    writeln!(output, "  --file <path> - hash the raw bytes of a file (which do not have to be valid UTF-8)").unwrap();
+    writeln!(output, "  --iterations <n> - hash the input n times and report how long it took").unwrap();
+    writeln!(output, "  --hashrate - measure hashes per second and estimate mining times (no input needed)").unwrap();
+    writeln!(output, "  --crack <hash> <charset> <max_len> - brute-force an input of up to max_len characters").unwrap();
+    writeln!(output, "    from charset which has the given hash value (e.g. --crack 0x45aaec6cd9f47e66 abil 4)").unwrap();
+    writeln!(output, "  --count-collisions <file> - hash every line of a file and report any collisions").unwrap();
+    writeln!(output, "  --low-bits <n> - also count collisions in the low n bits (default 16)").unwrap();
+    writeln!(output, "  avalanche <input> - flip each bit of input and report how many hash value bits change").unwrap();
+    writeln!(output, "  collide - hash random inputs until two of them collide, instead of hashing").unwrap();
+    writeln!(output, "    (only the low bits are compared - 32 unless --low-bits says otherwise, at most 40)").unwrap();
+}
+
+/// Remove the flag `name` from `args` if it is present, returning whether
+/// or not it was found.
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().position(|a| a == name) {
+        Some(i) => {
+            args.remove(i);
+            true
+        },
+        None => false
+    }
+}
+
+/// Remove the option `name` and the value following it from `args`,
+/// returning the value if the option was given.  It is an error for the
+/// option to be the last argument, since then it has no value.
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>, String> {
+    match args.iter().position(|a| a == name) {
+        Some(i) => {
+            if i + 1 >= args.len() {
+                return Err(format!("{} requires a value", name));
+            }
+            let val = args.remove(i + 1);
+            args.remove(i);
+            Ok(Some(val))
+        },
+        None => Ok(None)
+    }
+}
+
+/// Pull the options which take values out of `args`, returning the directory
+/// to hash (if any) and the options for the hash function itself.
+fn parse_options(args: &mut Vec<String>) -> Result<(Option<String>, HashOptions), String> {
+    let mut opts = HashOptions::default();
+    let dir = take_option(args, "--dir")?;
+
+    if let Some(padding) = take_option(args, "--padding")? {
+        opts.padding = match padding.as_ref() {
+            "zeros" => Padding::Zeros,
+            "marker" => Padding::Marker,
+            "length" => Padding::Length,
+            _ => return Err(format!("Unknown padding scheme: {}", padding)),
+        };
+    }
+    if let Some(twiddle) = take_option(args, "--twiddle")? {
+        opts.twiddle = match twiddle.as_ref() {
+            "v1" => TwiddleVersion::V1,
+            "v2" => TwiddleVersion::V2,
+            _ => return Err(format!("Unknown twiddle version: {}", twiddle)),
+        };
+    }
+    if let Some(rounds) = take_option(args, "--rounds")? {
+        opts.rounds = rounds.parse::<u32>()
+            .map_err(|_| format!("Invalid number of rounds: {}", rounds))?;
+    }
 
-/// Simple function to tell the user about appropriate usage and exit with exit code 1.
-fn print_usage_and_exit() {
-    println!("Please enter one and only one argument");
-    std::process::exit(1);
+    Ok((dir, opts))
+}
+
+/// Parse the value given to `--format`.
+fn parse_format(format: &str) -> Result<OutputFormat, String> {
+    match format {
+        "hex" => Ok(OutputFormat::Hex),
+        "dec" => Ok(OutputFormat::Dec),
+        "base64" => Ok(OutputFormat::Base64),
+        "bytes" => Ok(OutputFormat::Bytes),
+        _ => Err(format!("Unknown output format: {}", format)),
+    }
 }
 
 /// Given a string, convert it to a vector of u8s
@@ -75,23 +281,26 @@ fn convert_string_to_u8s(s: String) -> Vec<u8> {
 }
 
 /// "Wrapper function" which returns the string to hash, getting it from the
-/// command line arguments.  It also does some simple housekeeping to ensure
-/// that a single argument was passed in, and exits if not.
-
-fn get_to_hash() -> String {
-    let mut args = Vec::new();
-    for argument in env::args() {
-        args.push(argument);
-    }
+/// command line arguments `args`.  It also does some simple housekeeping to ensure
+/// that a single argument was passed in, and returns None if not.
+/// If that argument is "-", then the string to hash is read from `input`
+/// instead, exactly as given (so a trailing newline is part of the string).
+fn get_to_hash(args: &[String], input: &mut impl Read) -> Option<String> {
 
     // ignore "0 arg", i.e. the executable name itself
     let args_len = args.len() - 1;
 
     if args_len != 1 {
-        print_usage_and_exit();
+        return None;
     }
 
-    args[1].clone()
+    if args[1] == "-" {
+        let mut to_hash = String::new();
+        input.read_to_string(&mut to_hash).unwrap();
+        Some(to_hash)
+    } else {
+        Some(args[1].clone())
+    }
 
 }
 
@@ -117,6 +326,18 @@ fn strengthen(data: Vec<u8>) -> Vec<u8> {
     }
 }
 
+/// Add the padding marker to the end of `data` if the padding scheme uses
+/// one (see `Padding`), ready for `strengthen()` to pad it with 0s as usual.
+/// Note that data which was already a multiple of BLOCK_SIZE long will now
+/// get a whole extra block of padding.
+fn add_padding_marker(data: Vec<u8>, padding: Padding) -> Vec<u8> {
+    let mut to_return = data;
+    if padding != Padding::Zeros {
+        to_return.push(0x80);
+    }
+    to_return
+}
+
 /// The twiddle method "twiddles" the bits of the input array `arr` by XORing the
 /// values of every other element in the array with itself, with different sized
 /// left shifts.
@@ -125,16 +346,23 @@ fn strengthen(data: Vec<u8>) -> Vec<u8> {
 /// ```
 /// for each element in the array
 ///   iv = elem
-///   iv = iv XOR arr[loc + 1] LEFTSHIFT ((loc + 7) % BLOCK_SIZE)
-///   iv = iv XOR arr[loc + 2] LEFTSHIFT ((loc + 6) % BLOCK_SIZE)
-///   iv = iv XOR arr[loc + 3] LEFTSHIFT ((loc + 5) % BLOCK_SIZE)
-///   iv = iv XOR arr[loc + 4] LEFTSHIFT ((loc + 4) % BLOCK_SIZE)
-///   iv = iv XOR arr[loc + 5] LEFTSHIFT ((loc + 3) % BLOCK_SIZE)
-///   iv = iv XOR arr[loc + 6] LEFTSHIFT ((loc + 2) % BLOCK_SIZE)
-///   iv = iv XOR arr[loc + 7] LEFTSHIFT ((loc + 1) % BLOCK_SIZE)
+///   iv = iv XOR arr[loc + 1] LEFTSHIFT ((loc + 7) % 8)
+///   iv = iv XOR arr[loc + 2] LEFTSHIFT ((loc + 6) % 8)
+///   iv = iv XOR arr[loc + 3] LEFTSHIFT ((loc + 5) % 8)
+///   iv = iv XOR arr[loc + 4] LEFTSHIFT ((loc + 4) % 8)
+///   iv = iv XOR arr[loc + 5] RIGHTSHIFT ((loc + 3) % 8)
+///   iv = iv XOR arr[loc + 6] RIGHTSHIFT ((loc + 2) % 8)
+///   iv = iv XOR arr[loc + 7] RIGHTSHIFT ((loc + 1) % 8)
 /// end
 /// return iv
 /// ```
+/// (with all of the array indexes wrapping around at the end of the block).
+///
+/// This works for blocks of any size `N`, not just BLOCK_SIZE: each element
+/// is XORed with all of the `N - 1` others, the first half shifted left and
+/// the rest shifted right.  The shifts are always taken modulo 8, the number
+/// of bits in a byte, so for 8-byte blocks this is exactly the list above.
+///
 /// Note that this is a problematic method if the input array is entirely 0'sE,
 /// since the shifts will only add more 0's and the XORs will never produce a
 /// positive bit, meaning that [0; 8] -> [0; 8], and further twiddling will only
@@ -146,38 +374,76 @@ fn strengthen(data: Vec<u8>) -> Vec<u8> {
 /// block, which means that the distribution is slightly uneven (with 0 being
 /// slightly more likely to occur than other values).
 
-fn twiddle(arr: &mut [u8; BLOCK_SIZE]) {
+fn twiddle<const N: usize>(arr: &mut [u8; N]) {
 
-    for j in 0..BLOCK_SIZE {
-        arr[j] ^=
-            (arr[(j + 1) % BLOCK_SIZE]) << ((j + 7) % BLOCK_SIZE)
-            ^ (arr[(j + 2) % BLOCK_SIZE]) << ((j + 6) % BLOCK_SIZE)
-            ^ (arr[(j + 3) % BLOCK_SIZE]) << ((j + 5) % BLOCK_SIZE)
-            ^ (arr[(j + 4) % BLOCK_SIZE]) << ((j + 4) % BLOCK_SIZE)
-            ^ (arr[(j + 5) % BLOCK_SIZE]) >> ((j + 3) % BLOCK_SIZE)
-            ^ (arr[(j + 6) % BLOCK_SIZE]) >> ((j + 2) % BLOCK_SIZE)
-            ^ (arr[(j + 7) % BLOCK_SIZE]) >> ((j + 1) % BLOCK_SIZE);
+    for j in 0..N {
+        arr[j] ^= twiddle_mix(arr, j);
     }
 }
 
-/// The transform method accepts a compress value and an array of eight bytes.
-/// It XORs the array with the compress value (expressed as little-endian bytes)
-/// and then runs the twiddle function on it 1,024 times.
-/// The byte array is finally interpreted as a little-endian u64 and returned.
-
-fn transform(cv: u64, arr: [u8; BLOCK_SIZE]) -> u64 {
-    let mut to_return: [u8; BLOCK_SIZE] = [0; BLOCK_SIZE];
-    let cv_arr: [u8; BLOCK_SIZE] = cv.to_le_bytes();
+/// The value which `twiddle()` XORs `arr[j]` with, worked out from all of the
+/// *other* elements of the array.
+fn twiddle_mix<const N: usize>(arr: &[u8; N], j: usize) -> u8 {
+    let mut mix = 0;
+    for k in 1..N {
+        let elem = arr[(j + k) % N];
+        let shift = (j + N - k) % 8;
+        mix ^= if k <= N / 2 { elem << shift } else { elem >> shift };
+    }
+    mix
+}
 
-    // XOR the bytes in initial array against the CV's bytes
-    for j in 0..BLOCK_SIZE {
-        to_return[j] = arr[j] ^ cv_arr[j];
+/// Version 2 of `twiddle()`, which fixes the stall on a block of 0s.  After
+/// twiddling, each byte has the matching byte of `ROUND_CONSTANT` added to it
+/// (wrapping around at 256).  A block of 0s now becomes the round constant
+/// rather than staying 0.  Addition carries bits from one position into the
+/// next, which the XORs and shifts in `twiddle()` never do, so this also
+/// mixes the bits in a different (non-linear) way.
+/// Like `twiddle()`, this can still be undone: subtract the round constant,
+/// then call `twiddle_inverse()`.  Blocks bigger than the round constant
+/// use it over again.
+fn twiddle_v2<const N: usize>(arr: &mut [u8; N]) {
+    twiddle(arr);
+    for (b, c) in arr.iter_mut().zip(ROUND_CONSTANT.iter().cycle()) {
+        *b = b.wrapping_add(*c);
     }
+}
+
+/// Exactly undo a call to `twiddle()`, so that `twiddle_inverse(twiddle(arr))`
+/// gives back the original `arr`.
+///
+/// At first glance, it looks like `twiddle()` cannot be undone, since the
+/// shifts throw bits away.  But notice that each step only changes a single
+/// element, `arr[j]`, by XORing it with a value calculated from the *other*
+/// elements.  XORing the same value again removes it (`x ^ y ^ y == x`).
+/// So if we go through the steps backwards, starting with the last element,
+/// every other element is back to the value it had when `arr[j]` was changed,
+/// and we can recalculate exactly the same value to XOR away.
+/// The bits lost by the shifts never needed to be recovered - they are only
+/// ever used to calculate the value, never stored in place of the data.
+///
+/// This means that `twiddle()` on its own is no protection at all - anybody
+/// can run it backwards.  The hash is only hard to reverse because of the
+/// XOR with the compress value in `transform()` and the chaining of blocks.
+///
+/// BillHash itself never needs this function, so `#[allow(dead_code)]` stops
+/// the compiler from warning us that it is unused outside of the tests.
+#[allow(dead_code)]
+fn twiddle_inverse<const N: usize>(arr: &mut [u8; N]) {
 
-    // For these new bytes, run the twiddle function on them 1,024 times
-    for _j in 0..1024 {
-        twiddle(&mut to_return);
+    for j in (0..N).rev() {
+        arr[j] ^= twiddle_mix(arr, j);
     }
+}
+
+/// The transform method accepts a compress value and an array of eight bytes.
+/// It XORs the array with the compress value (expressed as little-endian bytes)
+/// and then runs the twiddle function (`twiddle()` or `twiddle_v2()`, depending
+/// on `version`) on it `rounds` times (normally 1,024).
+/// The byte array is finally interpreted as a little-endian u64 and returned.
+
+fn transform(cv: u64, arr: [u8; BLOCK_SIZE], rounds: u32, version: TwiddleVersion) -> u64 {
+    let to_return = transform_block(cv.to_le_bytes(), arr, rounds, version);
 
     // Return the twiddled bytes as a single u64 value by interpreting the bytes
     // as a little-endian bytes
@@ -185,20 +451,42 @@ fn transform(cv: u64, arr: [u8; BLOCK_SIZE]) -> u64 {
 
 }
 
+/// `transform()` for a block of any size `N`, with the compress value also
+/// given as `N` bytes.
+fn transform_block<const N: usize>(cv: [u8; N], arr: [u8; N], rounds: u32,
+                                   version: TwiddleVersion) -> [u8; N] {
+    let mut to_return: [u8; N] = [0; N];
+
+    // XOR the bytes in initial array against the CV's bytes
+    for j in 0..N {
+        to_return[j] = arr[j] ^ cv[j];
+    }
+
+    // For these new bytes, run the twiddle function on them `rounds` times
+    let twiddle_fn = match version {
+        TwiddleVersion::V1 => twiddle,
+        TwiddleVersion::V2 => twiddle_v2,
+    };
+    for _j in 0..rounds {
+        twiddle_fn(&mut to_return);
+    }
+    to_return
+}
+
 /// The compress function accepts a previous compress value and the data to operate
 /// on.  It will then be converted from the `Vec<u8>` to an array (since we now know
 /// that the size of the data is BLOCK_SIZE).  After that, it is transformed to give us a
 /// compress value, which is then returned.
 /// On the first block, the cv will equal the INITIALIZATION_VALUE.
-
-fn compress(cv: u64, data: Vec<u8>) -> u64 {
+/// `rounds` and `version` are passed on to `transform()`.
+fn compress(cv: u64, data: Vec<u8>, rounds: u32, version: TwiddleVersion) -> u64 {
     let mut new_data = data;
     let mut a: [u8; BLOCK_SIZE] = [0; BLOCK_SIZE];
     for j in 0..BLOCK_SIZE {
         a[j] = new_data.pop().unwrap();
     }
 
-    transform(cv, a)
+    transform(cv, a, rounds, version)
 }
 
 /// Given a vector of u8s, split it into a vector of vectors of u8s.
@@ -243,6 +531,110 @@ fn finalize(to_finalize: u64) -> u64 {
     to_finalize ^ 0xFFFF_FFFF_FFFF_FFFF
 }
 
+/// Calculates a BillHash value a piece at a time, so that the whole input
+/// never has to be in memory at once - e.g. for hashing a huge file.
+/// Feeding in the data with any number of `update()` calls gives the same
+/// hash value as hashing all of it at once.
+///
+/// Only whole blocks can be compressed, so any bytes left over after an
+/// `update()` are kept in `buffer` until the next one fills up the block.
+///
+/// The blocks are normally BLOCK_SIZE bytes, which gives the usual u64 hash
+/// values, but a `Hasher<N>` works on blocks of `N` bytes instead - e.g.
+/// `Hasher::<16>` gives 128-bit hash values.  Everything which is usually a
+/// u64 (the initialization vector, the compress value and the length block)
+/// is then `N` bytes: the 8 bytes of the u64 repeated over and over for the
+/// IV, or followed by 0s for the length.
+struct Hasher<const N: usize = BLOCK_SIZE> {
+    opts: HashOptions,
+    /// The compress value after all of the blocks so far
+    cv: [u8; N],
+    /// The bytes which do not make up a whole block yet
+    buffer: Vec<u8>,
+    /// How many bytes have been hashed in total
+    len: u64,
+}
+
+impl Hasher {
+    /// Start hashing with the default options.  The program always passes
+    /// options in, so `#[allow(dead_code)]` stops the compiler from warning
+    /// that this is only used by the tests.
+    #[allow(dead_code)]
+    fn new() -> Hasher {
+        Hasher::with_options(HashOptions::default())
+    }
+
+    /// Pad out and compress whatever is left, and return the hash value.
+    fn finalize(self) -> u64 {
+        u64::from_le_bytes(self.finalize_bytes())
+    }
+}
+
+impl<const N: usize> Hasher<N> {
+    /// Start hashing with the given options.
+    fn with_options(opts: HashOptions) -> Hasher<N> {
+        let iv = INITIALIZATION_VECTOR.to_le_bytes();
+        let mut cv = [0; N];
+        for (b, i) in cv.iter_mut().zip(iv.iter().cycle()) {
+            *b = *i;
+        }
+        Hasher {
+            opts,
+            cv,
+            buffer: Vec::with_capacity(N),
+            len: 0,
+        }
+    }
+
+    /// Hash the next piece of the input.
+    fn update(&mut self, data: &[u8]) {
+        self.len += data.len() as u64;
+        self.buffer.extend_from_slice(data);
+        let whole = self.buffer.len() - self.buffer.len() % N;
+        for j in (0..whole).step_by(N) {
+            self.cv = self.compress_block(&self.buffer[j..j + N]);
+        }
+        self.buffer.drain(..whole);
+    }
+
+    /// The same as `compress()`: the block's bytes go in backwards.
+    fn compress_block(&self, block: &[u8]) -> [u8; N] {
+        let mut a = [0; N];
+        for (b, x) in a.iter_mut().zip(block.iter().rev()) {
+            *b = *x;
+        }
+        transform_block(self.cv, a, self.opts.rounds, self.opts.twiddle)
+    }
+
+    /// Pad out and compress whatever is left, and return the hash value as
+    /// `N` little-endian bytes.
+    /// Like `strengthen()`, the empty input is hashed as one block of 0s, but
+    /// an input which was a whole number of blocks gets no more (unless the
+    /// padding scheme adds a marker).  With `Padding::Length`, the length
+    /// block is compressed last of all.
+    fn finalize_bytes(mut self) -> [u8; N] {
+        let mut rest = add_padding_marker(std::mem::take(&mut self.buffer), self.opts.padding);
+        if !rest.is_empty() || self.len == 0 {
+            // Pad with 0s, like strengthen() but for N-byte blocks
+            rest.resize(rest.len().div_ceil(N).max(1) * N, 0);
+            for block in rest.chunks(N) {
+                self.cv = self.compress_block(block);
+            }
+        }
+        if self.opts.padding == Padding::Length {
+            let mut len = self.len.to_le_bytes().to_vec();
+            len.resize(N.max(len.len()), 0);
+            self.cv = self.compress_block(&len[..N]);
+        }
+        // Like finalize(), complement every bit
+        let mut hash_val = self.cv;
+        for b in hash_val.iter_mut() {
+            *b = !*b;
+        }
+        hash_val
+    }
+}
+
 /// Run the BillHash function on the input string and return the hash value.
 /// This involves:
 /// Step 1: Convert the string to a vector of u8s (`Vec<u8>`)
@@ -255,26 +647,822 @@ fn finalize(to_finalize: u64) -> u64 {
 ///         block should be the INITIALIZATION_VECTOR (as there are no previous blocks).
 /// Step 5: The final compress value from the last block is run through the `finalize` function.
 ///         This is the hash value of the string.
-fn bill_hash(to_hash: String) -> u64 {
+///
+/// Each block is twiddled `rounds` times - `DEFAULT_ROUNDS` gives the
+/// original BillHash values.
+///
+/// The program itself always calls `bill_hash_with()`, so `#[allow(dead_code)]`
+/// stops the compiler from warning that this is only used by the tests.
+#[allow(dead_code)]
+fn bill_hash(to_hash: String, rounds: u32) -> u64 {
+    bill_hash_with(to_hash, &HashOptions { rounds, ..HashOptions::default() })
+}
 
-    let blocks = split(convert_string_to_u8s(to_hash));
-    let mut cv: u64 = INITIALIZATION_VECTOR;
+/// Run the BillHash function on the input string with the given options,
+/// which can change how the hash is calculated (see `HashOptions`).
+/// A string is just hashed as its UTF-8 bytes - see `bill_hash_bytes_with()`.
+fn bill_hash_with(to_hash: String, opts: &HashOptions) -> u64 {
+    bill_hash_bytes_with(&convert_string_to_u8s(to_hash), opts)
+}
 
-    for block in blocks {
-        cv = compress(cv, block);
+/// Run the BillHash function on any bytes at all, not just valid UTF-8.
+///
+/// The program itself always calls `bill_hash_bytes_with()`, so
+/// `#[allow(dead_code)]` stops the compiler from warning that this is only
+/// used by the tests.
+#[allow(dead_code)]
+fn bill_hash_bytes(data: &[u8]) -> u64 {
+    bill_hash_bytes_with(data, &HashOptions::default())
+}
+
+/// Run the BillHash function on any bytes with the given options.  This is
+/// what every other way of hashing ends up calling.
+/// The steps above are done by a `Hasher`, all in one go.
+fn bill_hash_bytes_with(data: &[u8], opts: &HashOptions) -> u64 {
+    let mut hasher = Hasher::with_options(*opts);
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// The byte which the key is XORed with for the inner hash of `bill_hmac()`.
+const HMAC_INNER_PAD: u8 = 0x36;
+
+/// The byte which the key is XORed with for the outer hash of `bill_hmac()`.
+const HMAC_OUTER_PAD: u8 = 0x5C;
+
+/// A keyed hash of `message`, using the standard HMAC construction on top of
+/// BillHash:
+/// ```text
+/// HMAC(K, m) = H((K' XOR opad) || H((K' XOR ipad) || m))
+/// ```
+/// where `||` joins two byte strings together and `K'` is the key made into
+/// exactly one block: keys longer than BLOCK_SIZE are hashed down to their
+/// 8-byte (little-endian) hash value first, and shorter ones are padded out
+/// with 0s.  Unlike just hashing the key and the message together, someone
+/// who knows one HMAC value cannot use it to work out the HMAC value of a
+/// longer message without knowing the key.
+///
+/// The program itself always calls `bill_hmac_with()`, so `#[allow(dead_code)]`
+/// stops the compiler from warning that this is only used by the tests.
+#[allow(dead_code)]
+fn bill_hmac(key: &[u8], message: &[u8]) -> u64 {
+    bill_hmac_with(key, message, &HashOptions::default())
+}
+
+/// The HMAC of `message` like `bill_hmac()`, but with every hash worked out
+/// using the given options.
+fn bill_hmac_with(key: &[u8], message: &[u8], opts: &HashOptions) -> u64 {
+    let mut block_key = if key.len() > BLOCK_SIZE {
+        bill_hash_bytes_with(key, opts).to_le_bytes().to_vec()
+    } else {
+        key.to_vec()
+    };
+    block_key.resize(BLOCK_SIZE, 0);
+
+    let mut inner: Vec<u8> = block_key.iter().map(|b| b ^ HMAC_INNER_PAD).collect();
+    inner.extend_from_slice(message);
+    let inner_hash = bill_hash_bytes_with(&inner, opts);
+
+    let mut outer: Vec<u8> = block_key.iter().map(|b| b ^ HMAC_OUTER_PAD).collect();
+    outer.extend_from_slice(&inner_hash.to_le_bytes());
+    bill_hash_bytes_with(&outer, opts)
+}
+
+/// How many bytes `hash_reader()` reads at a time.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Hash everything read from `reader` as raw bytes, a chunk at a time, so
+/// that even a file too big to fit in memory can be hashed.
+fn hash_reader(mut reader: impl Read, opts: &HashOptions) -> io::Result<u64> {
+    let mut hasher = Hasher::with_options(*opts);
+    let mut buf = vec![0; READ_CHUNK_SIZE];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize())
+}
+
+/// Combine a list of hash values into a single "root" hash value, by running
+/// BillHash over the bytes of all of the hashes, one after the other.
+/// Changing any of the hashes (or their order) changes the root, so a single
+/// value can be used to check that nothing in the list has changed.
+/// The blocks are twiddled the same way as the hashes themselves were (see
+/// `HashOptions`).
+fn combine_hashes(hashes: &[u64], opts: &HashOptions) -> u64 {
+    let mut data = Vec::new();
+    for h in hashes {
+        data.extend_from_slice(&h.to_le_bytes());
     }
 
+    let mut cv: u64 = INITIALIZATION_VECTOR;
+    for block in split(data) {
+        cv = compress(cv, block, opts.rounds, opts.twiddle);
+    }
     finalize(cv)
 }
 
+/// Recursively hash every file under the directory `dir`, adding a
+/// `(path, hash)` pair to `manifest` for each one.  Paths are relative to
+/// the directory originally passed in, which is tracked by `prefix`.
+/// Symbolic links are skipped, so that we can never get stuck in a loop.
+/// Files are hashed as raw bytes (which do not have to be valid UTF-8), so
+/// each one has the same hash value as `--file` gives it.
+fn hash_dir(dir: &Path,
+            prefix: &str,
+            opts: &HashOptions,
+            manifest: &mut Vec<(String, u64)>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+
+        if file_type.is_symlink() {
+            continue;
+        } else if file_type.is_dir() {
+            hash_dir(&entry.path(), &format!("{}/", name), opts, manifest)?;
+        } else {
+            let contents = fs::read(entry.path())?;
+            let hash_val = bill_hash_bytes_with(&contents, opts);
+            manifest.push((name, hash_val));
+        }
+    }
+    Ok(())
+}
+
+/// Hash every file under the directory `dir`, returning a manifest of
+/// `(path, hash)` pairs sorted by path, and the root hash of the directory
+/// (see `combine_hashes()`).  An empty directory has an empty manifest.
+fn hash_dir_tree(dir: &Path, opts: &HashOptions) -> io::Result<(Vec<(String, u64)>, u64)> {
+    let mut manifest = Vec::new();
+    hash_dir(dir, "", opts, &mut manifest)?;
+    manifest.sort();
+
+    let hashes: Vec<u64> = manifest.iter().map(|(_, h)| *h).collect();
+    let root = combine_hashes(&hashes, opts);
+    Ok((manifest, root))
+}
+
+/// The characters which base64 uses for each 6-bit value.
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode `data` as base64, padding the end out with = so that the length is
+/// always a multiple of 4.
+fn base64_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for group in data.chunks(3) {
+        // Put the (up to) three bytes together into 24 bits, then take them
+        // out again 6 bits at a time
+        let mut bits = 0u32;
+        for (j, b) in group.iter().enumerate() {
+            bits |= (*b as u32) << (16 - 8 * j);
+        }
+        for j in 0..4 {
+            if j <= group.len() {
+                encoded.push(BASE64_ALPHABET[(bits >> (18 - 6 * j) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Write out `hash_val` in the given format.  This returns bytes rather than
+/// a String since `OutputFormat::Bytes` is not text.
+fn format_hash(hash_val: u64, format: OutputFormat) -> Vec<u8> {
+    match format {
+        OutputFormat::Hex => format!("{:#016x}", hash_val).into_bytes(),
+        OutputFormat::Dec => hash_val.to_string().into_bytes(),
+        OutputFormat::Base64 => base64_encode(&hash_val.to_le_bytes()).into_bytes(),
+        OutputFormat::Bytes => hash_val.to_le_bytes().to_vec(),
+    }
+}
+
+/// Print the hash value of the input, after `label`, in the given format.
+/// The raw bytes are written out on their own, so that they can be piped
+/// somewhere else.
+fn print_hash_value(label: &str, hash_val: u64, format: OutputFormat, output: &mut impl Write) {
+    if format == OutputFormat::Bytes {
+        output.write_all(&format_hash(hash_val, format)).unwrap();
+    } else {
+        writeln!(output, "{}: {}", label,
+                 String::from_utf8(format_hash(hash_val, format)).unwrap()).unwrap();
+    }
+}
+
+/// Estimate the Shannon entropy of `data`, in bits per byte.  This is
+/// calculated from how often each byte value appears in the data:
+/// `H = -sum(p * log2(p))` over the probability `p` of each byte value.
+///
+/// The result will be between 0 (every byte is the same, e.g. "aaaa") and
+/// 8 (every byte value appears equally often).  Note that this is only an
+/// estimate from the frequencies of individual bytes - "abababab" looks
+/// like it has 1 bit of entropy per byte even though it is very predictable.
+/// Multiplying by the length of the data gives a rough idea of how many
+/// guesses it would take to brute-force it - if an attacker can find the
+/// input by guessing, it does not matter how good the hash function is!
+fn shannon_entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for b in data {
+        counts[*b as usize] += 1;
+    }
+
+    let len = data.len() as f64;
+    let mut entropy = 0.0;
+    for count in counts.iter().filter(|c| **c > 0) {
+        let p = *count as f64 / len;
+        entropy -= p * p.log2();
+    }
+    entropy
+}
+
+/// Print the estimated entropy of `data`, along with a warning if it is
+/// low enough that the input would be easy to brute-force.
+fn print_entropy(data: &[u8], output: &mut impl Write) {
+    let per_byte = shannon_entropy(data);
+    let total = per_byte * data.len() as f64;
+    writeln!(output, "Entropy: {:.2} bits per byte ({:.2} bits total)", per_byte, total).unwrap();
+    if total < LOW_ENTROPY_BITS {
+        writeln!(output, "Warning: very low entropy - this input would be easy to brute-force!").unwrap();
+    }
+}
+
+/// Hash `to_hash` `iterations` times, returning how long it took in total.
+/// Every hash value is XORed into an accumulator (which is also returned)
+/// and passed through `black_box()`, so the optimizer cannot decide that
+/// the work is unused and skip it - or notice that the same input is hashed
+/// every time and only hash it once.
+fn benchmark(to_hash: &str, iterations: u64, opts: &HashOptions) -> (Duration, u64) {
+    let mut acc = 0;
+    let start = Instant::now();
+    for _ in 0..iterations {
+        let input = std::hint::black_box(to_hash.to_string());
+        acc ^= bill_hash_with(input, opts);
+    }
+    (start.elapsed(), std::hint::black_box(acc))
+}
+
+/// Measure how many BillHashes per second this machine can do, by hashing
+/// in batches of 1000 until at least `sample` time has passed.
+fn measure_hash_rate(sample: Duration) -> f64 {
+    let opts = HashOptions::default();
+    let mut count: u64 = 0;
+    let mut elapsed = Duration::from_secs(0);
+    while elapsed < sample {
+        let (e, _) = benchmark(&count.to_string(), 1000, &opts);
+        elapsed += e;
+        count += 1000;
+    }
+    count as f64 / elapsed.as_secs_f64()
+}
+
+/// Estimate how many seconds it would take to mine a block at the given
+/// difficulty (the hash value must start with `difficulty` 0 bits) when
+/// hashing at `hash_rate` hashes per second.
+/// Every hash has a 1 in 2^difficulty chance of starting with enough 0s, so
+/// on average 2^difficulty hashes are needed.  Of course, you might get
+/// lucky (or unlucky!) - this is only the expected time.
+fn expected_mining_seconds(difficulty: u32, hash_rate: f64) -> f64 {
+    2f64.powi(difficulty as i32) / hash_rate
+}
+
+/// Measure the hash rate and print it along with the expected mining time
+/// for each of MINING_DIFFICULTIES.
+fn print_hash_rate(output: &mut impl Write) {
+    let rate = measure_hash_rate(HASH_RATE_SAMPLE);
+    writeln!(output, "Hash rate: {:.0} hashes/sec", rate).unwrap();
+    for difficulty in MINING_DIFFICULTIES.iter() {
+        writeln!(output, "Difficulty {}: expected {:.3} seconds to mine",
+                 difficulty, expected_mining_seconds(*difficulty, rate)).unwrap();
+    }
+}
+
+/// The collisions found by `count_collisions()`.
+#[derive(Debug, PartialEq)]
+struct CollisionReport {
+    /// How many distinct inputs were hashed
+    inputs: usize,
+    /// Every hash value shared by more than one input, with those inputs
+    full: Vec<(u64, Vec<String>)>,
+    /// How many inputs had the same low bits as an earlier input
+    low: usize,
+}
+
+/// Hash every distinct input in `lines`, and find which ones collide - both
+/// on the full 64-bit hash value and on just its low `low_bits` bits.
+/// Collisions on fewer bits are much more common; by the birthday paradox,
+/// about 2^(n/2) inputs are enough to expect one on n bits.
+/// A line repeated in the input is only hashed once, since an input always
+/// "collides" with itself.
+fn count_collisions(lines: &[&str], low_bits: u32, opts: &HashOptions) -> CollisionReport {
+    let mask = (1u64 << low_bits) - 1;
+    let mut seen = HashSet::new();
+    let mut by_hash: HashMap<u64, Vec<String>> = HashMap::new();
+    let mut low_seen = HashSet::new();
+    let mut low = 0;
+
+    for line in lines {
+        if !seen.insert(*line) {
+            continue;
+        }
+        let hash_val = bill_hash_with(line.to_string(), opts);
+        by_hash.entry(hash_val).or_default().push(line.to_string());
+        if !low_seen.insert(hash_val & mask) {
+            low += 1;
+        }
+    }
+
+    let mut full: Vec<(u64, Vec<String>)> = by_hash.into_iter()
+        .filter(|(_, inputs)| inputs.len() > 1)
+        .collect();
+    full.sort();
+    CollisionReport { inputs: seen.len(), full, low }
+}
+
+/// Print a `CollisionReport`, including the inputs in every full collision.
+fn print_collisions(report: &CollisionReport, low_bits: u32, output: &mut impl Write) {
+    writeln!(output, "Hashed {} distinct inputs", report.inputs).unwrap();
+    let full: usize = report.full.iter().map(|(_, inputs)| inputs.len() - 1).sum();
+    writeln!(output, "Full 64-bit collisions: {}", full).unwrap();
+    for (hash_val, inputs) in report.full.iter() {
+        writeln!(output, "  {:#016x}: {:?}", hash_val, inputs).unwrap();
+    }
+    writeln!(output, "Low {}-bit collisions: {}", low_bits, report.low).unwrap();
+}
+
+/// The number of bits which are different between `a` and `b`.
+fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// How much the hash value changed when each bit of an input was flipped,
+/// found by `avalanche()`.
+#[derive(Debug, PartialEq)]
+struct AvalancheReport {
+    /// How many input bits were flipped (one at a time)
+    flips: usize,
+    /// The average number of hash value bits which changed
+    average: f64,
+    /// The fewest hash value bits which changed for any one flip
+    min: u32,
+    /// The most hash value bits which changed for any one flip
+    max: u32,
+}
+
+/// Measure the "avalanche effect": flip each bit of `input` in turn, and see
+/// how many bits of the hash value change compared to the hash value of
+/// `input` itself.  For a good hash function, each output bit changes half
+/// of the time, so about 32 of the 64 bits should change on average - if
+/// far fewer change, then `twiddle()` is not mixing the bits enough.
+/// Returns None if `input` is empty, since then there are no bits to flip.
+fn avalanche(input: &[u8], opts: &HashOptions) -> Option<AvalancheReport> {
+    if input.is_empty() {
+        return None;
+    }
+    let original = bill_hash_bytes_with(input, opts);
+    let mut flipped = input.to_vec();
+    let mut distances = Vec::with_capacity(input.len() * 8);
+    for j in 0..input.len() * 8 {
+        flipped[j / 8] ^= 1 << (j % 8);
+        distances.push(hamming_distance(original, bill_hash_bytes_with(&flipped, opts)));
+        flipped[j / 8] ^= 1 << (j % 8);
+    }
+    let total: u32 = distances.iter().sum();
+    Some(AvalancheReport {
+        flips: distances.len(),
+        average: total as f64 / distances.len() as f64,
+        min: *distances.iter().min().unwrap(),
+        max: *distances.iter().max().unwrap(),
+    })
+}
+
+/// Print an `AvalancheReport`.
+fn print_avalanche(report: &AvalancheReport, output: &mut impl Write) {
+    writeln!(output, "Flipped each of the {} input bits in turn", report.flips).unwrap();
+    writeln!(output, "Output bits changed: {:.2} of 64 on average (ideally 32), min {}, max {}",
+             report.average, report.min, report.max).unwrap();
+}
+
+/// A very simple pseudo-random number generator (xorshift64*), so that
+/// `find_collision()` does not need any crates.  This is nowhere near good
+/// enough for cryptography, but it is plenty random enough to pick inputs,
+/// and the same seed always gives the same numbers.
+struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    /// Start a generator from `seed`.  The state must never be 0, or every
+    /// number after it would be 0 as well.
+    fn new(seed: u64) -> XorShift {
+        XorShift { state: seed | 1 }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    /// A random string of 1 up to COLLIDE_MAX_LEN characters from
+    /// COLLIDE_CHARSET.
+    fn next_input(&mut self) -> String {
+        let len = 1 + (self.next_u64() % COLLIDE_MAX_LEN as u64) as usize;
+        (0..len)
+            .map(|_| COLLIDE_CHARSET[(self.next_u64() % COLLIDE_CHARSET.len() as u64) as usize] as char)
+            .collect()
+    }
+}
+
+/// Two different inputs found by `find_collision()` which have the same
+/// hash value (after masking).
+#[derive(Debug, PartialEq)]
+struct Collision {
+    first: String,
+    second: String,
+    /// The hash value which both inputs share, after masking
+    hash_val: u64,
+    /// How many inputs were hashed to find the collision
+    attempts: u64,
+}
+
+/// A birthday attack: hash random short inputs, remembering the hash value
+/// of each one, until two different inputs have the same hash value.  Only
+/// the bits of the hash value which are set in `mask` are compared, so that
+/// the search can be made easier.
+/// By the birthday paradox, this takes about 2^(n/2) attempts for an n-bit
+/// hash value - far fewer than the 2^n it takes to find an input with one
+/// particular hash value, like `crack()` does.  Random inputs can come up
+/// twice, but an input does not count as colliding with itself.
+fn find_collision(mask: u64, seed: u64, opts: &HashOptions) -> Collision {
+    let mut rng = XorShift::new(seed);
+    let mut seen: HashMap<u64, String> = HashMap::new();
+    let mut attempts = 0;
+    loop {
+        let input = rng.next_input();
+        attempts += 1;
+        let hash_val = bill_hash_with(input.clone(), opts) & mask;
+        match seen.get(&hash_val) {
+            Some(first) if *first != input => {
+                return Collision { first: first.clone(), second: input, hash_val, attempts };
+            },
+            Some(_) => {},
+            None => {
+                seen.insert(hash_val, input);
+            }
+        }
+    }
+}
+
+/// Print a `Collision`, along with the full hash values of both inputs.
+fn print_collision(collision: &Collision, low_bits: u32, opts: &HashOptions,
+                   output: &mut impl Write) {
+    writeln!(output, "Found a collision on the low {} bits after {} attempts: {:#x}",
+             low_bits, collision.attempts, collision.hash_val).unwrap();
+    for input in [&collision.first, &collision.second].iter() {
+        writeln!(output, "  {:?}: {:#016x}", input, bill_hash_with(input.to_string(), opts)).unwrap();
+    }
+}
+
+/// The number of different inputs of 1 up to `max_len` characters which can
+/// be made from `charset_len` different characters, or None if there are
+/// too many to count.
+fn search_space(charset_len: usize, max_len: usize) -> Option<u64> {
+    let mut total: u64 = 0;
+    let mut per_len: u64 = 1;
+    for _ in 0..max_len {
+        per_len = per_len.checked_mul(charset_len as u64)?;
+        total = total.checked_add(per_len)?;
+    }
+    Some(total)
+}
+
+/// Brute-force search for an input with the hash value `target`, trying
+/// every string of 1 up to `max_len` characters from `charset` (shortest
+/// first).  Every 10% of the way through the search space, the progress is
+/// printed along with how much longer the rest of the search would take at
+/// the hash rate so far.
+/// Each extra character multiplies the search space by the size of the
+/// charset, so this very quickly becomes hopeless - which is exactly why
+/// long passwords from a big set of characters are hard to brute-force.
+fn crack(target: u64, charset: &[char], max_len: usize, opts: &HashOptions,
+         output: &mut impl Write) -> Option<String> {
+    let total = search_space(charset.len(), max_len)?;
+    let start = Instant::now();
+    let mut tried: u64 = 0;
+    let mut last_decile = 0;
+
+    for len in 1..=max_len {
+        // Count through every string of this length like an odometer, with
+        // each digit an index into the charset
+        let mut digits = vec![0; len];
+        loop {
+            let candidate: String = digits.iter().map(|d| charset[*d]).collect();
+            if bill_hash_with(candidate.clone(), opts) == target {
+                return Some(candidate);
+            }
+            tried += 1;
+
+            let decile = tried * 10 / total;
+            if decile > last_decile && tried < total {
+                last_decile = decile;
+                let rate = tried as f64 / start.elapsed().as_secs_f64();
+                writeln!(output, "Progress: {}% ({} of {}), ETA {:.1} seconds",
+                         decile * 10, tried, total, (total - tried) as f64 / rate).unwrap();
+            }
+
+            // Move on to the next string, stopping once every digit wraps
+            let mut j = len;
+            while j > 0 {
+                j -= 1;
+                digits[j] += 1;
+                if digits[j] < charset.len() {
+                    break;
+                }
+                digits[j] = 0;
+            }
+            if digits.iter().all(|d| *d == 0) {
+                break;
+            }
+        }
+    }
+    None
+}
+
+/// Run `--crack` with its three arguments - the hash value (in hex), the
+/// characters to use and the maximum length - returning the exit code.
+fn run_crack(args: &[String], opts: &HashOptions, output: &mut impl Write) -> i32 {
+    let hex = args[0].trim_start_matches("0x").trim_start_matches("0X");
+    let target = match u64::from_str_radix(hex, 16) {
+        Ok(t) => t,
+        Err(_) => {
+            writeln!(output, "Error: Invalid hash value: {}", args[0]).unwrap();
+            return 1;
+        }
+    };
+    let mut charset: Vec<char> = args[1].chars().collect();
+    charset.sort_unstable();
+    charset.dedup();
+    let max_len = match args[2].parse::<usize>() {
+        Ok(n) if n > 0 => n,
+        _ => {
+            writeln!(output, "Error: Invalid maximum length: {}", args[2]).unwrap();
+            return 1;
+        }
+    };
+    let total = match search_space(charset.len(), max_len) {
+        Some(total) if total > 0 => total,
+        _ => {
+            writeln!(output, "Error: The charset must not be empty, and the search space must fit in 64 bits").unwrap();
+            return 1;
+        }
+    };
+
+    writeln!(output, "Searching {} inputs", total).unwrap();
+    match crack(target, &charset, max_len, opts, output) {
+        Some(found) => {
+            writeln!(output, "Found: {:?}", found).unwrap();
+            0
+        },
+        None => {
+            writeln!(output, "Not found").unwrap();
+            1
+        }
+    }
+}
+
+/// Run the program with the command-line arguments `args` (including the
+/// program name), reading from `input` if asked to hash standard input and
+/// writing the result to `output`.  Returns the exit code of the program.
+/// Keeping this separate from main() lets the tests drive the whole program
+/// without having to spawn a new process.
+fn run(mut args: Vec<String>, mut input: impl Read, mut output: impl Write) -> i32 {
+
+    let entropy = take_flag(&mut args, "--entropy");
+    let crack_hash = take_flag(&mut args, "--crack");
+    if take_flag(&mut args, "--hashrate") {
+        print_hash_rate(&mut output);
+        return 0;
+    }
+    let (dir, opts) = match parse_options(&mut args) {
+        Ok(o) => o,
+        Err(e) => {
+            writeln!(output, "Error: {}", e).unwrap();
+            print_usage(&mut output);
+            return 1;
+        }
+    };
+    let iterations = match take_option(&mut args, "--iterations") {
+        Ok(None) => None,
+        Ok(Some(n)) => match n.parse::<u64>() {
+            Ok(n) if n > 0 => Some(n),
+            _ => {
+                writeln!(output, "Error: Invalid number of iterations: {}", n).unwrap();
+                print_usage(&mut output);
+                return 1;
+            }
+        },
+        Err(e) => {
+            writeln!(output, "Error: {}", e).unwrap();
+            print_usage(&mut output);
+            return 1;
+        }
+    };
+
+    let low_bits = match take_option(&mut args, "--low-bits") {
+        Ok(None) => None,
+        Ok(Some(n)) => match n.parse::<u32>() {
+            Ok(n) if (1..64).contains(&n) => Some(n),
+            _ => {
+                writeln!(output, "Error: Invalid number of low bits: {}", n).unwrap();
+                print_usage(&mut output);
+                return 1;
+            }
+        },
+        Err(e) => {
+            writeln!(output, "Error: {}", e).unwrap();
+            print_usage(&mut output);
+            return 1;
+        }
+    };
+    let corpus = match take_option(&mut args, "--count-collisions") {
+        Ok(c) => c,
+        Err(e) => {
+            writeln!(output, "Error: {}", e).unwrap();
+            print_usage(&mut output);
+            return 1;
+        }
+    };
+    let format = match take_option(&mut args, "--format") {
+        Ok(None) => OutputFormat::Hex,
+        Ok(Some(f)) => match parse_format(&f) {
+            Ok(f) => f,
+            Err(e) => {
+                writeln!(output, "Error: {}", e).unwrap();
+                print_usage(&mut output);
+                return 1;
+            }
+        },
+        Err(e) => {
+            writeln!(output, "Error: {}", e).unwrap();
+            print_usage(&mut output);
+            return 1;
+        }
+    };
+    let file = match take_option(&mut args, "--file") {
+        Ok(f) => f,
+        Err(e) => {
+            writeln!(output, "Error: {}", e).unwrap();
+            print_usage(&mut output);
+            return 1;
+        }
+    };
+
+    // Brute-force an input with a given hash value
+    if crack_hash {
+        if args.len() != 4 {
+            print_usage(&mut output);
+            return 1;
+        }
+        return run_crack(&args[1..], &opts, &mut output);
+    }
+
+    // Work out the HMAC of a message with a key
+    if args.len() == 4 && args[1] == "hmac" {
+        let hmac = bill_hmac_with(args[2].as_bytes(), args[3].as_bytes(), &opts);
+        print_hash_value("HMAC value", hmac, format, &mut output);
+        return 0;
+    }
+
+    // Measure how many output bits change when each input bit is flipped
+    if args.len() == 3 && args[1] == "avalanche" {
+        return match avalanche(args[2].as_bytes(), &opts) {
+            Some(report) => {
+                print_avalanche(&report, &mut output);
+                0
+            },
+            None => {
+                writeln!(output, "Error: avalanche needs a non-empty input").unwrap();
+                1
+            }
+        };
+    }
+
+    // Look for a collision between random inputs
+    if args.len() == 2 && args[1] == "collide" {
+        let low_bits = low_bits.unwrap_or(DEFAULT_COLLIDE_BITS);
+        if low_bits > MAX_COLLIDE_BITS {
+            writeln!(output, "Error: collide can only look at up to {} low bits, not {}",
+                     MAX_COLLIDE_BITS, low_bits).unwrap();
+            return 1;
+        }
+        let seed = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        let collision = find_collision((1u64 << low_bits) - 1, seed, &opts);
+        print_collision(&collision, low_bits, &opts, &mut output);
+        return 0;
+    }
+
+    // Hash every line of a file, looking for collisions
+    if let Some(corpus) = corpus {
+        return match fs::read_to_string(&corpus) {
+            Ok(contents) => {
+                let lines: Vec<&str> = contents.lines().collect();
+                let low_bits = low_bits.unwrap_or(DEFAULT_COLLISION_BITS);
+                let report = count_collisions(&lines, low_bits, &opts);
+                print_collisions(&report, low_bits, &mut output);
+                0
+            },
+            Err(e) => {
+                writeln!(output, "Error reading {}: {}", corpus, e).unwrap();
+                1
+            }
+        };
+    }
+
+    // Hash the raw bytes of a file, which do not have to be valid UTF-8
+    if let Some(file) = file {
+        if args.len() != 1 {
+            print_usage(&mut output);
+            return 1;
+        }
+        return match fs::File::open(&file).and_then(|f| hash_reader(f, &opts)) {
+            Ok(hash_val) => {
+                print_hash_value("Hash value", hash_val, format, &mut output);
+                0
+            },
+            Err(e) => {
+                writeln!(output, "Error reading {}: {}", file, e).unwrap();
+                1
+            }
+        };
+    }
+
+    // Hash a whole directory tree, printing out the manifest and root
+    if let Some(dir) = dir {
+        if format == OutputFormat::Bytes {
+            writeln!(output, "Error: --format bytes only works when hashing a single input").unwrap();
+            print_usage(&mut output);
+            return 1;
+        }
+        return match hash_dir_tree(Path::new(&dir), &opts) {
+            Ok((manifest, root)) => {
+                for (path, hash_val) in manifest {
+                    writeln!(output, "{}: {}", path,
+                             String::from_utf8(format_hash(hash_val, format)).unwrap()).unwrap();
+                }
+                writeln!(output, "Root hash: {}",
+                         String::from_utf8(format_hash(root, format)).unwrap()).unwrap();
+                0
+            },
+            Err(e) => {
+                writeln!(output, "Error reading {}: {}", dir, e).unwrap();
+                1
+            }
+        };
+    }
+
+    match get_to_hash(&args, &mut input) {
+        Some(to_hash) => {
+            if entropy {
+                print_entropy(to_hash.as_bytes(), &mut output);
+            }
+            if let Some(n) = iterations {
+                let (elapsed, _) = benchmark(&to_hash, n, &opts);
+                let per_call = Duration::from_nanos((elapsed.as_nanos() / n as u128) as u64);
+                writeln!(output, "Hashed {} times in {:?} ({:?} per call)",
+                         n, elapsed, per_call).unwrap();
+            }
+            let hash_val = bill_hash_with(to_hash, &opts);
+            print_hash_value("Hash value", hash_val, format, &mut output);
+            0
+        },
+        None => {
+            print_usage(&mut output);
+            1
+        }
+    }
+
+}
+
 /// Main function.
 /// Reads a hash as the first argument from the command line and prints its
 /// BillHash value.
 
 fn main() {
 
-    let hash_val = bill_hash(get_to_hash());
-    println!("Hash value: {:#016x}", hash_val);
+    let args = env::args().collect();
+    let code = run(args, io::stdin(), io::stdout());
+    std::process::exit(code);
 
 }
 
@@ -316,6 +1504,29 @@ mod tests {
         assert_eq!(strengthen(to_test), expected);
     }
 
+    #[test]
+    fn test_zeros_padding_adds_no_marker() {
+        assert_eq!(add_padding_marker(vec![1, 2, 3], Padding::Zeros), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_strengthen_with_marker() {
+        let strengthen_marker = |data| strengthen(add_padding_marker(data, Padding::Marker));
+        assert_eq!(strengthen_marker(Vec::new()),
+                   [0x80, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(strengthen_marker(vec![1, 2, 3]),
+                   [1, 2, 3, 0x80, 0, 0, 0, 0]);
+        assert_eq!(strengthen_marker(vec![1, 2, 3, 4, 5, 6, 7]),
+                   [1, 2, 3, 4, 5, 6, 7, 0x80]);
+        assert_eq!(strengthen_marker(vec![0; 8]),
+                   [0, 0, 0, 0, 0, 0, 0, 0, 0x80, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_length_padding_adds_marker() {
+        assert_eq!(add_padding_marker(vec![1, 2, 3], Padding::Length), [1, 2, 3, 0x80]);
+    }
+
     // ****************************************************************
     // split() function
     // ****************************************************************
@@ -411,21 +1622,111 @@ mod tests {
     }
 
     // ****************************************************************
-    // transform() function
+    // twiddle_v2() function
     // ****************************************************************
 
     #[test]
-    fn test_transform_iv_0() {
-        let cv = INITIALIZATION_VECTOR;
-        let to_test = [0; 8];
-        assert_eq!(transform(cv, to_test), 0x2C71C76D48A512E5);
+    fn test_twiddle_v2_all_0s_does_not_stall() {
+        let mut to_test = [0; 8];
+        twiddle_v2(&mut to_test);
+        assert_eq!(to_test, ROUND_CONSTANT);
+        for _j in 0..1024 {
+            twiddle_v2(&mut to_test);
+            assert_ne!(to_test, [0; 8]);
+        }
     }
 
     #[test]
-    fn test_transform_iv_incr() {
-        let cv = INITIALIZATION_VECTOR;
+    fn test_twiddle_v2_is_twiddle_plus_constant() {
+        let mut v1 = [1, 2, 3, 0, 0, 0xFF, 0xAA, 0xCC];
+        let mut v2 = v1;
+        twiddle(&mut v1);
+        twiddle_v2(&mut v2);
+        assert_eq!(v1, [146, 214, 22, 81, 89, 204, 146, 134]);
+        assert_eq!(v2, [0xA7, 0x52, 0x60, 0xD0, 0x12, 0x45, 0xC9, 0x24]);
+
+        // And it can be undone
+        for (b, c) in v2.iter_mut().zip(ROUND_CONSTANT.iter()) {
+            *b = b.wrapping_sub(*c);
+        }
+        twiddle_inverse(&mut v2);
+        assert_eq!(v2, [1, 2, 3, 0, 0, 0xFF, 0xAA, 0xCC]);
+    }
+
+    // ****************************************************************
+    // twiddle_inverse() function
+    // ****************************************************************
+
+    #[test]
+    fn test_twiddle_inverse_known_outputs() {
+        let mut to_test = [34, 43, 7, 158, 28, 133, 208, 242];
+        twiddle_inverse(&mut to_test);
+        assert_eq!(to_test, [0, 1, 2, 3, 4, 5, 6, 7]);
+
+        let mut to_test = [241, 220, 214, 142, 248, 177, 55, 128];
+        twiddle_inverse(&mut to_test);
+        assert_eq!(to_test, [1; 8]);
+    }
+
+    #[test]
+    fn test_twiddle_then_inverse_round_trips() {
+        let inputs = [[0; 8],
+                      [1; 8],
+                      [0xFF; 8],
+                      [0, 1, 2, 3, 4, 5, 6, 7],
+                      [1, 2, 3, 0, 0, 0xFF, 0xAA, 0xCC],
+                      [0x80, 0, 0, 0, 0, 0, 0, 0x01]];
+        for input in inputs.iter() {
+            let mut to_test = *input;
+            twiddle(&mut to_test);
+            twiddle_inverse(&mut to_test);
+            assert_eq!(to_test, *input);
+        }
+    }
+
+    #[test]
+    fn test_twiddle_inverse_undoes_many_rounds() {
+        let input = [9, 8, 7, 6, 5, 4, 3, 2];
+        let mut to_test = input;
+        for _j in 0..1024 {
+            twiddle(&mut to_test);
+        }
+        for _j in 0..1024 {
+            twiddle_inverse(&mut to_test);
+        }
+        assert_eq!(to_test, input);
+    }
+
+    #[test]
+    fn test_twiddle_16_byte_block() {
+        let mut to_test = [0; 16];
+        for (j, b) in to_test.iter_mut().enumerate() {
+            *b = j as u8;
+        }
+        let input = to_test;
+        twiddle(&mut to_test);
+        assert_eq!(to_test, [19, 70, 0, 78, 134, 97, 180, 213,
+                             180, 70, 86, 126, 12, 188, 86, 144]);
+        twiddle_inverse(&mut to_test);
+        assert_eq!(to_test, input);
+    }
+
+    // ****************************************************************
+    // transform() function
+    // ****************************************************************
+
+    #[test]
+    fn test_transform_iv_0() {
+        let cv = INITIALIZATION_VECTOR;
+        let to_test = [0; 8];
+        assert_eq!(transform(cv, to_test, DEFAULT_ROUNDS, TwiddleVersion::V1), 0x2C71C76D48A512E5);
+    }
+
+    #[test]
+    fn test_transform_iv_incr() {
+        let cv = INITIALIZATION_VECTOR;
         let to_test = [0, 1, 2, 3, 4, 5, 6, 7];
-        assert_eq!(transform(cv, to_test), 0xDF73E8863D5E2E4);
+        assert_eq!(transform(cv, to_test, DEFAULT_ROUNDS, TwiddleVersion::V1), 0xDF73E8863D5E2E4);
 
     }
 
@@ -433,7 +1734,7 @@ mod tests {
     fn test_transform_iv_all_1s() {
         let cv = INITIALIZATION_VECTOR;
         let to_test = [1; 8];
-        assert_eq!(transform(cv, to_test), 0xC44441A4484800A3);
+        assert_eq!(transform(cv, to_test, DEFAULT_ROUNDS, TwiddleVersion::V1), 0xC44441A4484800A3);
 
     }
 
@@ -441,7 +1742,7 @@ mod tests {
     fn test_transform_iv_all_ffs() {
         let cv = INITIALIZATION_VECTOR;
         let to_test = [0xFF; 8];
-        assert_eq!(transform(cv, to_test), 0xDB47BA4E73CAF7F5);
+        assert_eq!(transform(cv, to_test, DEFAULT_ROUNDS, TwiddleVersion::V1), 0xDB47BA4E73CAF7F5);
 
     }
 
@@ -453,28 +1754,28 @@ mod tests {
     fn test_compress_0_0() {
         let cv = 0;
         let to_test = vec![0; 8];
-        assert_eq!(compress(cv, to_test), 0x0);
+        assert_eq!(compress(cv, to_test, DEFAULT_ROUNDS, TwiddleVersion::V1), 0x0);
     }
 
     #[test]
     fn test_compress_0_ffs() {
         let cv = 0;
         let to_test = vec![0xFF; 8];
-        assert_eq!(compress(cv, to_test), 0xF7367D233B6FE510);
+        assert_eq!(compress(cv, to_test, DEFAULT_ROUNDS, TwiddleVersion::V1), 0xF7367D233B6FE510);
     }
 
     #[test]
     fn test_compress_iv_0() {
         let cv = INITIALIZATION_VECTOR;
         let to_test = vec![0; 8];
-        assert_eq!(compress(cv, to_test), 0x2C71C76D48A512E5);
+        assert_eq!(compress(cv, to_test, DEFAULT_ROUNDS, TwiddleVersion::V1), 0x2C71C76D48A512E5);
     }
 
     #[test]
     fn test_compress_iv_incr() {
         let cv = INITIALIZATION_VECTOR;
         let to_test = vec![0, 1, 2, 3, 4, 5, 6, 7];
-        assert_eq!(compress(cv, to_test), 0x43FC4E68B1A699B8);
+        assert_eq!(compress(cv, to_test, DEFAULT_ROUNDS, TwiddleVersion::V1), 0x43FC4E68B1A699B8);
     }
 
     // ****************************************************************
@@ -518,35 +1819,819 @@ mod tests {
 
     #[test]
     fn test_hash_empty() {
-        assert_eq!(bill_hash("".to_string()), 0xd38e3892b75aed1a);
+        assert_eq!(bill_hash("".to_string(), DEFAULT_ROUNDS), 0xd38e3892b75aed1a);
     }
 
     #[test]
     fn test_hash_very_small() {
-        assert_eq!(bill_hash("b".to_string()), 0x7DACF192C75DB1DB);
+        assert_eq!(bill_hash("b".to_string(), DEFAULT_ROUNDS), 0x7DACF192C75DB1DB);
 
     }
 
     #[test]
     fn test_hash_bill() {
-        assert_eq!(bill_hash("bill".to_string()), 0x45AAEC6CD9F47E66);
+        assert_eq!(bill_hash("bill".to_string(), DEFAULT_ROUNDS), 0x45AAEC6CD9F47E66);
 
     }
 
     #[test]
     fn test_hash_hash() {
-        assert_eq!(bill_hash("hash".to_string()), 0xFE75BD197EA432C9);
+        assert_eq!(bill_hash("hash".to_string(), DEFAULT_ROUNDS), 0xFE75BD197EA432C9);
 
     }
 
     #[test]
     fn test_hash_long_entry() {
         let long_string = "It was the best of times, it was the worst of times, it was the age of wisdom, it was the age of foolishness, it was the epoch of belief, it was the epoch of incredulity, it was the season of Light, it was the season of Darkness, it was the spring of hope, it was the winter of despair, we had everything before us, we had nothing before us, we were all going direct to heaven, we were all going direct the other way - in short, the period was so far like the present period, that some of its noisiest authorities insisted on its being received, for good or for evil, in the superlative degree of comparison only.".to_string();
-        assert_eq!(bill_hash(long_string), 0x7391BAE2DB358FF4);
+        assert_eq!(bill_hash(long_string, DEFAULT_ROUNDS), 0x7391BAE2DB358FF4);
+
+    }
 
+    // With the default padding, the empty string and a block of 0 bytes
+    // both strengthen to the same block, so they collide.
+    #[test]
+    fn test_hash_zero_padding_collides() {
+        let zeros = "\0".repeat(BLOCK_SIZE);
+        assert_eq!(bill_hash("".to_string(), DEFAULT_ROUNDS), bill_hash(zeros, DEFAULT_ROUNDS));
+    }
+
+    #[test]
+    fn test_hash_marker_padding_does_not_collide() {
+        let opts = HashOptions { padding: Padding::Marker, ..HashOptions::default() };
+        let zeros = "\0".repeat(BLOCK_SIZE);
+        assert_ne!(bill_hash_with("".to_string(), &opts), bill_hash_with(zeros.clone(), &opts));
+        assert_ne!(bill_hash_with("a".to_string(), &opts), bill_hash_with("a\0".to_string(), &opts));
+    }
+
+    #[test]
+    fn test_hash_length_padding_trailing_zeros() {
+        let opts = HashOptions { padding: Padding::Length, ..HashOptions::default() };
+        let inputs = ["", "\0", "ab", "ab\0", "ab\0\0", "ab\0\0\0\0\0\0", "ab\0\0\0\0\0\0\0\0"];
+        let hashes: HashSet<u64> = inputs.iter().map(|s| bill_hash_with(s.to_string(), &opts)).collect();
+        assert_eq!(hashes.len(), inputs.len());
+        // Without it, they collide
+        assert_eq!(bill_hash("ab".to_string(), DEFAULT_ROUNDS), bill_hash("ab\0".to_string(), DEFAULT_ROUNDS));
+    }
+
+    #[test]
+    fn test_hash_length_padding_is_marker_plus_length_block() {
+        let marker = HashOptions { padding: Padding::Marker, ..HashOptions::default() };
+        let length = HashOptions { padding: Padding::Length, ..HashOptions::default() };
+        for s in ["", "bill", "billhash"].iter() {
+            let h = bill_hash_with(s.to_string(), &length);
+            assert_ne!(h, bill_hash_with(s.to_string(), &marker));
+            // finalize() only complements the compress value, so the marker
+            // hash can be turned back into the compress value before the
+            // length block
+            let cv = finalize(bill_hash_with(s.to_string(), &marker));
+            let len = (s.len() as u64).to_le_bytes().to_vec();
+            assert_eq!(h, finalize(compress(cv, len, DEFAULT_ROUNDS, TwiddleVersion::V1)));
+        }
+    }
+
+    #[test]
+    fn test_hash_default_options_preserve_vectors() {
+        let opts = HashOptions::default();
+        assert_eq!(bill_hash_with("".to_string(), &opts), 0xd38e3892b75aed1a);
+        assert_eq!(bill_hash_with("bill".to_string(), &opts), 0x45AAEC6CD9F47E66);
+        assert_eq!(bill_hash_with("hash".to_string(), &opts), 0xFE75BD197EA432C9);
+    }
+
+    #[test]
+    fn test_hash_twiddle_v2() {
+        let v2 = HashOptions { twiddle: TwiddleVersion::V2, ..HashOptions::default() };
+        assert_ne!(bill_hash_with("bill".to_string(), &v2), 0x45AAEC6CD9F47E66);
+        assert_ne!(bill_hash_with("".to_string(), &v2), bill_hash_with("a".to_string(), &v2));
+        // A compress value which XORs with the block to 0 no longer
+        // compresses to 0
+        assert_eq!(compress(0, vec![0; 8], DEFAULT_ROUNDS, TwiddleVersion::V1), 0);
+        assert_ne!(compress(0, vec![0; 8], DEFAULT_ROUNDS, TwiddleVersion::V2), 0);
+    }
+
+    #[test]
+    fn test_hash_rounds_change_digest() {
+        for s in ["", "bill", "hash"].iter() {
+            assert_ne!(bill_hash(s.to_string(), DEFAULT_ROUNDS), bill_hash(s.to_string(), 16));
+            assert_ne!(bill_hash(s.to_string(), 16), bill_hash(s.to_string(), 17));
+        }
+    }
+
+    // With no rounds, transform() only XORs the block with the compress
+    // value, so one block hashes to (IV ^ block) complemented
+    #[test]
+    fn test_hash_zero_rounds_does_not_mix() {
+        let block = u64::from_le_bytes(*b"billhash");
+        assert_eq!(transform(INITIALIZATION_VECTOR, *b"billhash", 0, TwiddleVersion::V1), INITIALIZATION_VECTOR ^ block);
+        // "a" and "b" differ in two bits, and so do their hashes
+        assert_eq!((bill_hash("a".to_string(), 0) ^ bill_hash("b".to_string(), 0)).count_ones(), 2);
     }
 
+    // ****************************************************************
+    // bill_hash_bytes() and hash_reader() functions
+    // ****************************************************************
+
+    #[test]
+    fn test_hash_bytes_invalid_utf8() {
+        let data = [0xFF, 0x00, 0xFE, 0x80, 0x00, 0xC3, 0x28, 0xFF, 0x00];
+        assert!(String::from_utf8(data.to_vec()).is_err());
+        let hash_val = bill_hash_bytes(&data);
+        // Replacing the invalid bytes (as from_utf8_lossy() does) would
+        // give a different hash value
+        let lossy = String::from_utf8_lossy(&data).into_owned();
+        assert_ne!(hash_val, bill_hash(lossy, DEFAULT_ROUNDS));
+        assert_ne!(hash_val, bill_hash_bytes(&data[..8]));
+        assert_eq!(hash_val, hash_reader(&data[..], &HashOptions::default()).unwrap());
+    }
 
+    #[test]
+    fn test_hash_bytes_matches_strings() {
+        for s in ["", "bill", "hash"].iter() {
+            assert_eq!(bill_hash_bytes(s.as_bytes()), bill_hash(s.to_string(), DEFAULT_ROUNDS));
+        }
+    }
+
+    #[test]
+    fn test_hash_reader_several_reads() {
+        // Each read() of a chain only returns the bytes from one part
+        let data: Vec<u8> = (0..100).map(|j| (j * 7 % 256) as u8).collect();
+        let reader = (&data[..5]).chain(&data[5..13]).chain(&data[13..]);
+        assert_eq!(hash_reader(reader, &HashOptions::default()).unwrap(), bill_hash_bytes(&data));
+    }
+
+    // ****************************************************************
+    // Hasher struct
+    // ****************************************************************
 
+    // Hash `data` with `opts`, feeding it to a Hasher `chunk` bytes at a time
+    fn hash_in_chunks(data: &[u8], chunk: usize, opts: HashOptions) -> u64 {
+        let mut hasher = Hasher::with_options(opts);
+        for piece in data.chunks(chunk) {
+            hasher.update(piece);
+        }
+        hasher.finalize()
+    }
+
+    #[test]
+    fn test_hasher_known_values() {
+        let mut hasher = Hasher::new();
+        hasher.update(b"bi");
+        hasher.update(b"");
+        hasher.update(b"ll");
+        assert_eq!(hasher.finalize(), 0x45AAEC6CD9F47E66);
+        assert_eq!(Hasher::new().finalize(), 0xd38e3892b75aed1a);
+    }
+
+    #[test]
+    fn test_hasher_chunks_match_one_go() {
+        let data: Vec<u8> = (0..200u32).map(|j| (j * 37 % 256) as u8).collect();
+        let marker = HashOptions { padding: Padding::Marker, ..HashOptions::default() };
+        let length = HashOptions { padding: Padding::Length, ..HashOptions::default() };
+        let v2 = HashOptions { twiddle: TwiddleVersion::V2, rounds: 16, ..HashOptions::default() };
+        for opts in [HashOptions::default(), marker, length, v2].iter() {
+            // Every length, including exact multiples of BLOCK_SIZE
+            for len in [0, 1, 7, 8, 9, 16, 17, 100, 200].iter() {
+                let data = &data[..*len];
+                let expected = hash_in_chunks(data, data.len().max(1), *opts);
+                for chunk in [1, 2, 3, 7, 8, 9, 64].iter() {
+                    assert_eq!(hash_in_chunks(data, *chunk, *opts), expected,
+                               "len {} in chunks of {}", len, chunk);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_hasher_matches_bill_hash() {
+        for s in ["", "b", "bill", "billhash", "billhashes", "\0\0\0\0\0\0\0\0"].iter() {
+            assert_eq!(hash_in_chunks(s.as_bytes(), 3, HashOptions::default()),
+                       bill_hash(s.to_string(), DEFAULT_ROUNDS));
+        }
+    }
+
+    // ****************************************************************
+    // Hasher with 16-byte blocks
+    // ****************************************************************
+
+    // Hash `data` with 16-byte blocks, `chunk` bytes at a time, and return the
+    // 128-bit hash value
+    fn hash_16_in_chunks(data: &[u8], chunk: usize, opts: HashOptions) -> u128 {
+        let mut hasher = Hasher::<16>::with_options(opts);
+        for piece in data.chunks(chunk) {
+            hasher.update(piece);
+        }
+        u128::from_le_bytes(hasher.finalize_bytes())
+    }
+
+    #[test]
+    fn test_hasher_16_known_values() {
+        let opts = HashOptions::default();
+        assert_eq!(hash_16_in_chunks(b"", 1, opts), 0xb8ad849bc01e114860f3b263bbacb29a);
+        assert_eq!(hash_16_in_chunks(b"bill", 4, opts), 0x058016ff274867f2ddbcd0c29cb5dc34);
+        assert_eq!(hash_16_in_chunks(b"billhash", 8, opts), 0x8ce1dd5d304250055ce2c85853cc166f);
+        assert_eq!(hash_16_in_chunks(b"billhashbillhash", 16, opts),
+                   0x68ab1d213d62a85ab727cb1706cef193);
+        assert_eq!(hash_16_in_chunks(b"billhashbillhashx", 17, opts),
+                   0x3d309bc46fa9406200c93f294dbb3af9);
+    }
+
+    #[test]
+    fn test_hasher_16_is_reproducible() {
+        let data: Vec<u8> = (0..100u32).map(|j| (j * 53 % 256) as u8).collect();
+        let length = HashOptions { padding: Padding::Length, ..HashOptions::default() };
+        let v2 = HashOptions { twiddle: TwiddleVersion::V2, rounds: 16, ..HashOptions::default() };
+        for opts in [HashOptions::default(), length, v2].iter() {
+            for len in [0, 1, 15, 16, 17, 32, 100].iter() {
+                let data = &data[..*len];
+                let expected = hash_16_in_chunks(data, data.len().max(1), *opts);
+                for chunk in [1, 5, 16, 33].iter() {
+                    assert_eq!(hash_16_in_chunks(data, *chunk, *opts), expected,
+                               "len {} in chunks of {}", len, chunk);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_hasher_16_differs_from_8() {
+        // The first 8 bytes of a 16-byte hash are not just the 8-byte hash
+        let hash_16 = hash_16_in_chunks(b"bill", 4, HashOptions::default());
+        assert_ne!(hash_16 as u64, hash_in_chunks(b"bill", 4, HashOptions::default()));
+    }
+
+    // ****************************************************************
+    // shannon_entropy() function
+    // ****************************************************************
+
+    #[test]
+    fn test_entropy_uniform_bytes() {
+        let data: Vec<u8> = (0..=255).collect();
+        assert!((shannon_entropy(&data) - 8.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_entropy_repeated_char() {
+        let data = vec![b'a'; 100];
+        assert!(shannon_entropy(&data).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_entropy_two_symbols() {
+        assert!((shannon_entropy(b"abab") - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_entropy_empty() {
+        assert_eq!(shannon_entropy(&[]), 0.0);
+    }
+
+    // ****************************************************************
+    // hash_dir_tree() function
+    // ****************************************************************
+
+    // Create a fresh, empty directory for a test to work in
+    fn make_test_dir(name: &str) -> std::path::PathBuf {
+        let dir = env::temp_dir().join(format!("bill_hash_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_hash_dir_tree() {
+        let dir = make_test_dir("tree");
+        fs::write(dir.join("a.txt"), "bill").unwrap();
+        fs::create_dir(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub").join("b.txt"), "hash").unwrap();
+        fs::create_dir(dir.join("empty")).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("a.txt"), dir.join("link.txt")).unwrap();
+
+        let (manifest, root) = hash_dir_tree(&dir, &HashOptions::default()).unwrap();
+        assert_eq!(manifest, vec![("a.txt".to_string(), 0x45AAEC6CD9F47E66),
+                                  ("sub/b.txt".to_string(), 0xFE75BD197EA432C9)]);
+        assert_eq!(root, combine_hashes(&[0x45AAEC6CD9F47E66, 0xFE75BD197EA432C9], &HashOptions::default()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_hash_dir_tree_raw_bytes() {
+        // Not valid UTF-8, so --dir must hash the bytes just like --file does
+        let dir = make_test_dir("raw_bytes");
+        let data = [0xFF, 0x00, 0xFE, 0x80, 0x00, 0xC3, 0x28, 0xFF, 0x00];
+        fs::write(dir.join("binary"), &data[..]).unwrap();
+        let (manifest, _) = hash_dir_tree(&dir, &HashOptions::default()).unwrap();
+        assert_eq!(manifest, vec![("binary".to_string(), bill_hash_bytes(&data))]);
+        let (_, output) = run_with(&["--file", dir.join("binary").to_str().unwrap()], "");
+        assert_eq!(output, format!("Hash value: {:#016x}\n", manifest[0].1));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_hash_dir_tree_empty() {
+        let dir = make_test_dir("empty");
+        let (manifest, root) = hash_dir_tree(&dir, &HashOptions::default()).unwrap();
+        assert!(manifest.is_empty());
+        assert_eq!(root, combine_hashes(&[], &HashOptions::default()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_combine_hashes_depends_on_order() {
+        assert_ne!(combine_hashes(&[1, 2], &HashOptions::default()), combine_hashes(&[2, 1], &HashOptions::default()));
+    }
+
+    // ****************************************************************
+    // benchmark and hash rate functions
+    // ****************************************************************
+
+    #[test]
+    fn test_benchmark_small_iteration_count() {
+        let opts = HashOptions::default();
+        let (elapsed, acc) = benchmark("bill", 101, &opts);
+        assert!(elapsed > Duration::from_nanos(0));
+        // An odd number of identical hashes XORed together is the hash itself
+        assert_eq!(acc, 0x45aaec6cd9f47e66);
+    }
+
+    #[test]
+    fn test_measure_hash_rate_positive() {
+        assert!(measure_hash_rate(Duration::from_millis(10)) > 0.0);
+    }
+
+    #[test]
+    fn test_expected_mining_seconds() {
+        assert_eq!(expected_mining_seconds(8, 256.0), 1.0);
+        assert_eq!(expected_mining_seconds(16, 1024.0), 64.0);
+        assert_eq!(expected_mining_seconds(0, 2.0), 0.5);
+    }
+
+    // ****************************************************************
+    // count_collisions() function
+    // ****************************************************************
+
+    #[test]
+    fn test_count_collisions_full() {
+        // With zero padding, trailing NULs make no difference to the hash
+        let lines = ["abc", "abc\0", "abc\0\0", "bill", "hash"];
+        let report = count_collisions(&lines, DEFAULT_COLLISION_BITS, &HashOptions::default());
+        assert_eq!(report.inputs, 5);
+        assert_eq!(report.full, vec![(bill_hash("abc".to_string(), DEFAULT_ROUNDS),
+                                      vec!["abc".to_string(), "abc\0".to_string(),
+                                           "abc\0\0".to_string()])]);
+        // Every full collision is a low-bit collision too
+        assert_eq!(report.low, 2);
+    }
+
+    #[test]
+    fn test_count_collisions_low_bits() {
+        // Three different hash values can only have two different low bits,
+        // so at least one of them must collide there
+        let lines = ["bill", "hash", "billcoin"];
+        let report = count_collisions(&lines, 1, &HashOptions::default());
+        assert!(report.full.is_empty());
+        assert!(report.low >= 1);
+    }
+
+    #[test]
+    fn test_count_collisions_ignores_repeated_lines() {
+        let report = count_collisions(&["bill", "bill"], 63, &HashOptions::default());
+        assert_eq!(report, CollisionReport { inputs: 1, full: vec![], low: 0 });
+    }
+
+    #[test]
+    fn test_count_collisions_marker_padding() {
+        // Marker padding fixes the trailing NUL collision
+        let opts = HashOptions { padding: Padding::Marker, ..HashOptions::default() };
+        let report = count_collisions(&["abc", "abc\0"], DEFAULT_COLLISION_BITS, &opts);
+        assert!(report.full.is_empty());
+    }
+
+    // ****************************************************************
+    // bill_hmac() function
+    // ****************************************************************
+
+    #[test]
+    fn test_hmac_empty_key() {
+        assert_eq!(bill_hmac(b"", b""), 0xbe394d1158e93f20);
+        assert_eq!(bill_hmac(b"", b"bill"), 0x3ab834acdf6d8493);
+        // Short keys are padded out with 0s
+        assert_eq!(bill_hmac(&[0; BLOCK_SIZE], b"bill"), 0x3ab834acdf6d8493);
+    }
+
+    #[test]
+    fn test_hmac_block_length_key() {
+        assert_eq!(bill_hmac(b"billhash", b"bill"), 0xcc20699628f4e8f2);
+        // The key is used as it is, not hashed down
+        let hashed_key = bill_hash_bytes(b"billhash").to_le_bytes();
+        assert_ne!(bill_hmac(&hashed_key, b"bill"), 0xcc20699628f4e8f2);
+    }
+
+    #[test]
+    fn test_hmac_over_length_key() {
+        assert_eq!(bill_hmac(b"billhashes", b"bill"), 0x5db456841944653b);
+        // Keys longer than a block are hashed down to one first
+        let hashed_key = bill_hash_bytes(b"billhashes").to_le_bytes();
+        assert_eq!(bill_hmac(&hashed_key, b"bill"), 0x5db456841944653b);
+    }
+
+    #[test]
+    fn test_hmac_is_not_plain_hash() {
+        assert_ne!(bill_hmac(b"", b"bill"), bill_hash_bytes(b"bill"));
+        assert_ne!(bill_hmac(b"key", b"bill"), bill_hmac(b"kez", b"bill"));
+    }
+
+    #[test]
+    fn test_hmac_with_options() {
+        let opts = HashOptions { rounds: 3, ..HashOptions::default() };
+        assert_eq!(bill_hmac_with(b"billhashes", b"bill", &HashOptions::default()),
+                   bill_hmac(b"billhashes", b"bill"));
+        assert_ne!(bill_hmac_with(b"billhashes", b"bill", &opts), bill_hmac(b"billhashes", b"bill"));
+        // Every hash uses the options, including hashing down a long key
+        let hashed_key = bill_hash_bytes_with(b"billhashes", &opts).to_le_bytes();
+        assert_eq!(bill_hmac_with(&hashed_key, b"bill", &opts), bill_hmac_with(b"billhashes", b"bill", &opts));
+    }
+
+    // ****************************************************************
+    // format_hash() function
+    // ****************************************************************
+
+    // The hash value of "bill"
+    const BILL_HASH: u64 = 0x45AAEC6CD9F47E66;
+
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64_encode(&[0xFF; 3]), "////");
+    }
+
+    #[test]
+    fn test_format_hash_hex() {
+        assert_eq!(format_hash(BILL_HASH, OutputFormat::Hex), b"0x45aaec6cd9f47e66");
+    }
+
+    #[test]
+    fn test_format_hash_dec() {
+        assert_eq!(format_hash(BILL_HASH, OutputFormat::Dec), b"5020084686915141222");
+        assert_eq!(format_hash(0, OutputFormat::Dec), b"0");
+    }
+
+    #[test]
+    fn test_format_hash_base64() {
+        assert_eq!(format_hash(BILL_HASH, OutputFormat::Base64), b"Zn702WzsqkU=");
+    }
+
+    #[test]
+    fn test_format_hash_bytes() {
+        assert_eq!(format_hash(BILL_HASH, OutputFormat::Bytes),
+                   [0x66, 0x7E, 0xF4, 0xD9, 0x6C, 0xEC, 0xAA, 0x45]);
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(parse_format("base64"), Ok(OutputFormat::Base64));
+        assert_eq!(parse_format("octal"), Err("Unknown output format: octal".to_string()));
+    }
+
+    // ****************************************************************
+    // avalanche() function
+    // ****************************************************************
+
+    #[test]
+    fn test_hamming_distance() {
+        assert_eq!(hamming_distance(0, 0), 0);
+        assert_eq!(hamming_distance(0, u64::MAX), 64);
+        assert_eq!(hamming_distance(0b1011, 0b0001), 2);
+        assert_eq!(hamming_distance(0x8000_0000_0000_0001, 1), 1);
+        assert_eq!(hamming_distance(0xF0F0_F0F0_F0F0_F0F0, 0x0F0F_0F0F_0F0F_0F0F), 64);
+        assert_eq!(hamming_distance(0x45AAEC6CD9F47E66, 0x45AAEC6CD9F47E66), 0);
+    }
+
+    #[test]
+    fn test_avalanche_empty_input() {
+        assert_eq!(avalanche(b"", &HashOptions::default()), None);
+    }
+
+    #[test]
+    fn test_avalanche_default_options() {
+        let report = avalanche(b"bill", &HashOptions::default()).unwrap();
+        assert_eq!(report.flips, 32);
+        assert!(report.min <= report.max && report.max <= 64);
+        assert!(report.average >= report.min as f64 && report.average <= report.max as f64);
+        // The full 1024 rounds of twiddling mix the bits well
+        assert!(report.average > 24.0 && report.average < 40.0);
+    }
+
+    #[test]
+    fn test_avalanche_no_rounds() {
+        // Without any twiddling, BillHash only XORs the blocks together, so
+        // flipping one input bit flips exactly one bit of the hash value
+        let opts = HashOptions { rounds: 0, ..HashOptions::default() };
+        let report = avalanche(b"billhashes", &opts).unwrap();
+        assert_eq!(report, AvalancheReport { flips: 80, average: 1.0, min: 1, max: 1 });
+    }
+
+    // ****************************************************************
+    // find_collision() function
+    // ****************************************************************
+
+    #[test]
+    fn test_xorshift_inputs() {
+        let mut rng = XorShift::new(1);
+        for _j in 0..100 {
+            let input = rng.next_input();
+            assert!((1..=COLLIDE_MAX_LEN).contains(&input.len()));
+            assert!(input.bytes().all(|b| COLLIDE_CHARSET.contains(&b)));
+        }
+        // A seed of 0 still gives random numbers
+        assert_ne!(XorShift::new(0).next_u64(), 0);
+    }
+
+    #[test]
+    fn test_find_collision_with_mask() {
+        let opts = HashOptions::default();
+        let collision = find_collision(0xFF, 42, &opts);
+        assert_ne!(collision.first, collision.second);
+        assert_eq!(bill_hash_with(collision.first.clone(), &opts) & 0xFF, collision.hash_val);
+        assert_eq!(bill_hash_with(collision.second.clone(), &opts) & 0xFF, collision.hash_val);
+        // There are only 256 different 8-bit values (and with this seed, no
+        // input comes up twice before the collision)
+        assert!(collision.attempts >= 2 && collision.attempts <= 257);
+    }
+
+    #[test]
+    fn test_find_collision_is_deterministic() {
+        let opts = HashOptions::default();
+        assert_eq!(find_collision(0xFFF, 7, &opts), find_collision(0xFFF, 7, &opts));
+    }
+
+    #[test]
+    fn test_print_collision() {
+        let collision = Collision { first: "a".to_string(), second: "b".to_string(),
+                                    hash_val: 0x3, attempts: 5 };
+        let opts = HashOptions::default();
+        let mut output = Vec::new();
+        print_collision(&collision, 2, &opts, &mut output);
+        assert_eq!(String::from_utf8(output).unwrap(),
+                   format!("Found a collision on the low 2 bits after 5 attempts: 0x3\n  \
+                            \"a\": {:#016x}\n  \"b\": {:#016x}\n",
+                           bill_hash("a".to_string(), DEFAULT_ROUNDS),
+                           bill_hash("b".to_string(), DEFAULT_ROUNDS)));
+    }
+
+    // ****************************************************************
+    // crack() function
+    // ****************************************************************
+
+    #[test]
+    fn test_search_space() {
+        assert_eq!(search_space(2, 3), Some(2 + 4 + 8));
+        assert_eq!(search_space(26, 1), Some(26));
+        assert_eq!(search_space(0, 5), Some(0));
+        assert_eq!(search_space(256, 9), None);
+    }
+
+    #[test]
+    fn test_crack_finds_short_password() {
+        let target = bill_hash("cab".to_string(), DEFAULT_ROUNDS);
+        let mut output = Vec::new();
+        let found = crack(target, &['a', 'b', 'c'], 4, &HashOptions::default(), &mut output);
+        assert_eq!(found, Some("cab".to_string()));
+    }
+
+    #[test]
+    fn test_crack_reports_progress() {
+        // Not in the search space at all, so every input is tried
+        let target = bill_hash("d".to_string(), DEFAULT_ROUNDS);
+        let mut output = Vec::new();
+        assert_eq!(crack(target, &['a', 'b'], 6, &HashOptions::default(), &mut output), None);
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.lines().count(), 9);
+        assert!(output.starts_with("Progress: 10% (13 of 126), ETA "));
+    }
+
+    #[test]
+    fn test_run_crack() {
+        let (code, output) = run_with(&["--crack", "0x45aaec6cd9f47e66", "bil", "4"], "");
+        assert_eq!(code, 0);
+        assert!(output.starts_with("Searching 120 inputs\n"));
+        assert!(output.ends_with("Found: \"bill\"\n"));
+
+        let (code, output) = run_with(&["--crack", "0x45aaec6cd9f47e66", "bil", "3"], "");
+        assert_eq!(code, 1);
+        assert!(output.ends_with("Not found\n"));
+
+        assert_eq!(run_with(&["--crack", "xyz", "bil", "3"], "").0, 1);
+        assert_eq!(run_with(&["--crack", "0x1", "bil", "0"], "").0, 1);
+        assert_eq!(run_with(&["--crack", "0x1", "bil"], "").0, 1);
+    }
+
+    // ****************************************************************
+    // run() function
+    // ****************************************************************
+
+    // Run the whole program with the given arguments (not including the
+    // program name) and input, returning the exit code and the output.
+    fn run_with(args: &[&str], input: &str) -> (i32, String) {
+        let mut full_args = vec!["bill_hash".to_string()];
+        full_args.extend(args.iter().map(|a| a.to_string()));
+        let mut output = Vec::new();
+        let code = run(full_args, input.as_bytes(), &mut output);
+        (code, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_run_length_padding() {
+        let length = HashOptions { padding: Padding::Length, ..HashOptions::default() };
+        let (code, output) = run_with(&["bill", "--padding", "length"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, format!("Hash value: {:#016x}\n", bill_hash_with("bill".to_string(), &length)));
+    }
+
+    #[test]
+    fn test_run_hash_argument() {
+        let (code, output) = run_with(&["bill"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, "Hash value: 0x45aaec6cd9f47e66\n");
+    }
+
+    #[test]
+    fn test_run_hash_rounds() {
+        let (code, output) = run_with(&["bill", "--rounds", "1024"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, "Hash value: 0x45aaec6cd9f47e66\n");
+        let (code, output) = run_with(&["bill", "--rounds", "8"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, format!("Hash value: {:#016x}\n", bill_hash("bill".to_string(), 8)));
+        let (code, output) = run_with(&["bill", "--rounds", "many"], "");
+        assert_eq!(code, 1);
+        assert!(output.starts_with("Error: Invalid number of rounds: many\n"));
+    }
+
+    #[test]
+    fn test_run_hash_twiddle() {
+        let (code, output) = run_with(&["bill", "--twiddle", "v1"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, "Hash value: 0x45aaec6cd9f47e66\n");
+        let v2 = HashOptions { twiddle: TwiddleVersion::V2, ..HashOptions::default() };
+        let (code, output) = run_with(&["bill", "--twiddle", "v2"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, format!("Hash value: {:#016x}\n", bill_hash_with("bill".to_string(), &v2)));
+        let (code, output) = run_with(&["bill", "--twiddle", "v3"], "");
+        assert_eq!(code, 1);
+        assert!(output.starts_with("Error: Unknown twiddle version: v3\n"));
+    }
+
+    #[test]
+    fn test_run_hash_format() {
+        let (code, output) = run_with(&["bill", "--format", "hex"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, "Hash value: 0x45aaec6cd9f47e66\n");
+        let (code, output) = run_with(&["bill", "--format", "dec"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, "Hash value: 5020084686915141222\n");
+        let (code, output) = run_with(&["--format", "base64", "bill"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, "Hash value: Zn702WzsqkU=\n");
+        let (code, output) = run_with(&["bill", "--format", "octal"], "");
+        assert_eq!(code, 1);
+        assert!(output.starts_with("Error: Unknown output format: octal\n"));
+        let (code, output) = run_with(&["--dir", ".", "--format", "bytes"], "");
+        assert_eq!(code, 1);
+        assert!(output.starts_with("Error: --format bytes only works when hashing a single input\n"));
+    }
+
+    #[test]
+    fn test_run_hash_format_bytes() {
+        // The raw bytes are not UTF-8, so run_with() cannot be used
+        let args = vec!["bill_hash".to_string(), "--format".to_string(),
+                        "bytes".to_string(), "bill".to_string()];
+        let mut output = Vec::new();
+        assert_eq!(run(args, "".as_bytes(), &mut output), 0);
+        assert_eq!(output, BILL_HASH.to_le_bytes());
+    }
+
+    #[test]
+    fn test_run_hmac() {
+        let (code, output) = run_with(&["hmac", "billhashes", "bill"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, "HMAC value: 0x5db456841944653b\n");
+        let (code, output) = run_with(&["hmac", "", "bill", "--format", "dec"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, format!("HMAC value: {}\n", 0x3ab834acdf6d8493u64));
+        let (code, output) = run_with(&["hmac", "billhashes", "bill", "--rounds", "3"], "");
+        assert_eq!(code, 0);
+        let opts = HashOptions { rounds: 3, ..HashOptions::default() };
+        assert_eq!(output, format!("HMAC value: {:#016x}\n", bill_hmac_with(b"billhashes", b"bill", &opts)));
+        // On its own, "hmac" is just a string to hash
+        let (code, output) = run_with(&["hmac"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, format!("Hash value: {:#016x}\n", bill_hash_bytes(b"hmac")));
+    }
+
+    #[test]
+    fn test_run_collide() {
+        let (code, output) = run_with(&["collide", "--low-bits", "12"], "");
+        assert_eq!(code, 0);
+        assert!(output.starts_with("Found a collision on the low 12 bits after "));
+        assert_eq!(output.lines().count(), 3);
+        let (code, output) = run_with(&["collide", "--low-bits", "41"], "");
+        assert_eq!(code, 1);
+        assert_eq!(output, "Error: collide can only look at up to 40 low bits, not 41\n");
+    }
+
+    #[test]
+    fn test_run_avalanche() {
+        let (code, output) = run_with(&["avalanche", "bill", "--rounds", "0"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, "Flipped each of the 32 input bits in turn\n\
+                            Output bits changed: 1.00 of 64 on average (ideally 32), min 1, max 1\n");
+        let (code, output) = run_with(&["avalanche", ""], "");
+        assert_eq!(code, 1);
+        assert_eq!(output, "Error: avalanche needs a non-empty input\n");
+    }
+
+    #[test]
+    fn test_run_hash_stdin() {
+        let (code, output) = run_with(&["-"], "hash");
+        assert_eq!(code, 0);
+        assert_eq!(output, "Hash value: 0xfe75bd197ea432c9\n");
+    }
+
+    #[test]
+    fn test_run_iterations() {
+        let (code, output) = run_with(&["bill", "--iterations", "10"], "");
+        assert_eq!(code, 0);
+        assert!(output.starts_with("Hashed 10 times in "));
+        assert!(output.ends_with("Hash value: 0x45aaec6cd9f47e66\n"));
+
+        let (code, _) = run_with(&["bill", "--iterations", "0"], "");
+        assert_eq!(code, 1);
+    }
+
+    #[test]
+    fn test_run_count_collisions() {
+        let dir = make_test_dir("collisions");
+        let corpus = dir.join("words.txt");
+        fs::write(&corpus, "abc\nbill\nabc\0\n").unwrap();
+        let (code, output) = run_with(&["--count-collisions", corpus.to_str().unwrap(),
+                                        "--low-bits", "8"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, format!("Hashed 3 distinct inputs\n\
+                                    Full 64-bit collisions: 1\n  \
+                                    {:#016x}: [\"abc\", \"abc\\0\"]\n\
+                                    Low 8-bit collisions: 1\n",
+                                   bill_hash("abc".to_string(), DEFAULT_ROUNDS)));
+
+        let (code, _) = run_with(&["--count-collisions", corpus.to_str().unwrap(),
+                                   "--low-bits", "64"], "");
+        assert_eq!(code, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_hash_file() {
+        let dir = make_test_dir("file");
+        let path = dir.join("binary");
+        let data = [0xFF, 0x00, 0xFE, 0x80, 0x00, 0xC3, 0x28, 0xFF, 0x00];
+        fs::write(&path, &data[..]).unwrap();
+        let (code, output) = run_with(&["--file", path.to_str().unwrap()], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, format!("Hash value: {:#016x}\n", bill_hash_bytes(&data)));
+
+        // A text file hashes the same as its contents as a string
+        fs::write(&path, "bill").unwrap();
+        let (_, output) = run_with(&["--file", path.to_str().unwrap()], "");
+        assert_eq!(output, "Hash value: 0x45aaec6cd9f47e66\n");
+
+        let (code, output) = run_with(&["--file", dir.join("missing").to_str().unwrap()], "");
+        assert_eq!(code, 1);
+        assert!(output.starts_with("Error reading "));
+        assert_eq!(run_with(&["--file", path.to_str().unwrap(), "bill"], "").0, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_wrong_number_of_arguments() {
+        let (code, output) = run_with(&[], "");
+        assert_eq!(code, 1);
+        assert!(output.starts_with("Please enter one and only one argument"));
+
+        let (code, _) = run_with(&["bill", "hash"], "");
+        assert_eq!(code, 1);
+    }
+
+    #[test]
+    fn test_run_entropy_warns_on_low_entropy() {
+        let (code, output) = run_with(&["--entropy", "aaaa"], "");
+        assert_eq!(code, 0);
+        assert!(output.starts_with("Entropy: 0.00 bits per byte (0.00 bits total)\n"));
+        assert!(output.contains("Warning: very low entropy"));
+        assert!(output.contains("Hash value: "));
+    }
 
 }
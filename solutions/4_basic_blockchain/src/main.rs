@@ -26,6 +26,7 @@ use std::collections::HashMap;
 use std::env;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
+use std::fs;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io;
@@ -38,6 +39,9 @@ type Address = u64;
 type Amount = u64;
 type Digest = u64;
 
+// The (optional) header row of a blockchain CSV file, naming each column
+const CSV_HEADER: &str = "index,from,amount,to,prev_hash";
+
 // For simplicity, every block will have exactly one transaction - for efficiency,
 // on a real blockchain, you will generally see 0..n transactions in a block.
 // A transaction consists of a "to" address, a "from" address, and amount sent
@@ -45,7 +49,7 @@ type Digest = u64;
 // The Debug trait just lets us easily print it out using println!
 // The Hash trait allows us to hash a struct of this type
 
-#[derive(Debug, Hash)]
+#[derive(Debug, Hash, PartialEq)]
 pub struct Block {
     pub to_addr: Address,
     pub from_addr: Address,
@@ -53,6 +57,136 @@ pub struct Block {
     pub prev_hash: Digest
 }
 
+// A whole blockchain.  Adding blocks with push() fills in the prev_hash of
+// each one, so a Chain built that way always has a valid hash chain - it
+// is only the balances which can make it invalid.
+// Pushed blocks are linked with the default hash (get_hash()) and a
+// genesis prev_hash of 0x0.
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Chain {
+    pub blocks: Vec<Block>,
+}
+
+impl Chain {
+    pub fn new() -> Chain {
+        Chain { blocks: Vec::new() }
+    }
+
+    // Add a block sending `amount` billcoins from `from` to `to` onto the
+    // end of the chain, linked to the block before it.
+    pub fn push(&mut self, from: Address, to: Address, amount: Amount) {
+        let prev_hash = self.blocks.last().map(get_hash).unwrap_or(0);
+        self.blocks.push(Block {
+            to_addr: to,
+            from_addr: from,
+            amount,
+            prev_hash
+        });
+    }
+
+    // Check that the chain is valid with the default verification rules
+    pub fn verify(&self) -> Result<(), String> {
+        self.balances().map(|_| ())
+    }
+
+    // The balance of every account, if the chain is valid
+    pub fn balances(&self) -> Result<HashMap<Address, Amount>, String> {
+        verify_blockchain(&self.blocks, &VerifyOptions::default())
+    }
+}
+
+// Optional policy checks which are applied while verifying a blockchain.
+// These are not part of the rules of Billcoin itself, but are useful for
+// catching mistakes (e.g. fat-fingering an extra 0 onto an amount) in
+// hand-written chains.  By default, every check is turned off.
+
+#[derive(Debug, Default)]
+pub struct VerifyOptions {
+    // Reject any transaction sending less than this amount
+    pub min_amount: Option<Amount>,
+    // Reject any transaction sending more than this amount
+    pub max_amount: Option<Amount>,
+    // Reject any transaction sending 0 billcoins, since it does nothing
+    pub reject_zero_amount: bool,
+    // Do not apply the amount bounds (or the zero amount check) to
+    // transactions from the magic address 0x0
+    pub exempt_mints: bool,
+    // Check the prev_hash of every block in a separate pass before doing
+    // any of the balance checks (see check_prev_hashes())
+    pub canonical: bool,
+    // Only check the prev_hash of every block, skipping all of the checks
+    // on amounts and balances.  No balances are returned.
+    pub hash_only: bool,
+    // The prev_hash expected for the first (genesis) block.  This is
+    // normally 0x0, but a chain can choose any fixed value.
+    pub genesis: Digest,
+    // The most billcoins which can ever be minted from the magic address
+    // 0x0 in total, for modelling a fixed-supply coin
+    pub max_supply: Option<Amount>,
+    // If given, every block is hashed with this seed (see get_hash_seeded())
+    pub hash_seed: Option<u64>,
+    // Check that the index at the start of every line of a CSV file matches
+    // the position of the block in the chain
+    pub check_index: bool,
+    // Treat a chain with no blocks at all as invalid
+    pub reject_empty: bool,
+    // Amounts are written in hex (like addresses) rather than decimal, both
+    // in CSV files and when typing in blocks
+    pub hex_amounts: bool,
+    // Write a snapshot of the balances to snapshot_file every this many
+    // blocks, so that verification can be resumed from it later
+    pub snapshot_every: Option<usize>,
+    // Load the snapshot in snapshot_file and only verify the blocks after it
+    pub resume: bool,
+    // Where snapshots are written to (and resumed from)
+    pub snapshot_file: Option<String>,
+    // Keep a running total of how much every account received and sent
+    // (see Flow).  This is only done when asked for, since it is not needed
+    // to check that the chain is valid.
+    pub flows: bool,
+}
+
+// Everything verify_blockchain() needs to know to carry on verifying a
+// chain part-way through - how many blocks have been verified, the hash
+// the next block must have as its prev_hash, how many billcoins have been
+// minted, and the balance and flows of every account.
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Snapshot {
+    pub blocks: usize,
+    pub prev_hash: Digest,
+    pub total_minted: Amount,
+    pub balances: HashMap<Address, Amount>,
+    pub flows: HashMap<Address, Flow>,
+}
+
+// The total number of billcoins an account received and sent over the
+// whole chain.  Its final balance is just received - sent, but these show
+// how much activity there was, e.g. an account which received and sent
+// 1000 billcoins looks very different to one which was never used.
+// These are u128s, since the same billcoins can be sent back and forth any
+// number of times - the totals can go far past the most any account can
+// ever hold at once.
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Flow {
+    pub received: u128,
+    pub sent: u128,
+}
+
+impl Flow {
+    // The net flow into the account - negative if it sent more than
+    // it received (which can only happen for the magic address 0x0).
+    pub fn net(&self) -> i128 {
+        self.received as i128 - self.sent as i128
+    }
+}
+
+// The final balance and the flows of every account, as returned by
+// verify_blockchain_with_flows()
+type Ledger = (HashMap<Address, Amount>, HashMap<Address, Flow>);
+
 
 // Given any object, return its 64-bit hash.  This uses the default
 // Rust hashing algorithm.
@@ -64,250 +198,873 @@ fn get_hash<T: Hash>(t: &T) -> Digest {
     r 
 }
 
-// Convert a hex string (e.g. "0x1F" or "1F") to a 64-bit unsigned int.
+// Same as get_hash(), but if a seed is given it is hashed before the
+// object, so the same object gives a completely different hash for every
+// seed.  A chain built with one seed will then only verify with that same
+// seed.  DefaultHasher::new() always starts from the same fixed keys (and
+// BuildHasherDefault just calls it), so hashing the seed first is how we
+// "key" it.  With no seed, this is exactly the same as get_hash().
+
+fn get_hash_seeded<T: Hash>(t: &T, seed: Option<u64>) -> Digest {
+    match seed {
+        Some(seed) => {
+            let mut s = DefaultHasher::new();
+            seed.hash(&mut s);
+            t.hash(&mut s);
+            s.finish()
+        },
+        None => get_hash(t)
+    }
+}
+
+// Convert a hex string (e.g. "0x1F", "0X1F" or "1F") to a 64-bit unsigned
+// int.  The 0x prefix is optional, but may only appear once - "0x0x1F" is
+// almost certainly a mistake, so it is rejected rather than guessed at.
 // We use u64 instead of Address or Amount since this works for any
 // type which equates to u64.
 
-fn convert_hex(x: String) -> u64 {
-    let num = x.trim_start_matches("0x");
-    u64::from_str_radix(num, 16).unwrap()
+fn convert_hex(x: String) -> Result<u64, String> {
+    let num = x.strip_prefix("0x")
+        .or_else(|| x.strip_prefix("0X"))
+        .unwrap_or(&x);
+    if num.is_empty() {
+        return Err(format!("Invalid hex value {:?}: no digits", x));
+    }
+    if num.starts_with("0x") || num.starts_with("0X") {
+        return Err(format!("Invalid hex value {:?}: 0x prefix given more than once", x));
+    }
+    u64::from_str_radix(num, 16).map_err(|_| format!("Invalid hex value {:?}", x))
 }
 
 // Convert a decimal string (e.g. "31") to a 64-bit unsigned int.
 // We use u64 instead of Address or Amount since this works for any
 // type which equates to u64.
 
-fn convert_decimal(x: String) -> u64 {
-    u64::from_str_radix(&x, 10).unwrap()
+fn convert_decimal(x: String) -> Result<u64, String> {
+    x.parse::<u64>().map_err(|_| format!("Invalid decimal value {:?}", x))
 }
 
 
-// Print a blockchain `bc` in human-readable format.
+// Format an amount of billcoins.  If `human` is true, the digits are split
+// into groups of three with commas (e.g. 1,000,000), which is easier to
+// read but harder for other programs to parse.
 
-fn pretty_print_blockchain(bc: &Vec<Block>) {
+fn format_amount(amount: Amount, human: bool) -> String {
+    let digits = amount.to_string();
+    if !human {
+        return digits;
+    }
+    let mut formatted = String::new();
+    for (j, c) in digits.chars().enumerate() {
+        if j > 0 && (digits.len() - j).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(c);
+    }
+    formatted
+}
+
+// Print a blockchain `bc` in human-readable format.  If `human` is true,
+// amounts are printed with thousands separators.
+
+fn pretty_print_blockchain(bc: &[Block], human: bool, output: &mut impl Write) {
     for (j, b) in bc.iter().enumerate() {
-        println!("Block: {}, {:#016x} sent {} billcoins to {:#016x} (Prev Hash: {:#016x})",
+        writeln!(output, "Block: {}, {:#016x} sent {} billcoins to {:#016x} (Prev Hash: {:#016x})",
                  j,
                  b.from_addr,
-                 b.amount,
+                 format_amount(b.amount, human),
                  b.to_addr,
-                 b.prev_hash);
+                 b.prev_hash).unwrap();
     }
 }
 
+// Format block `b`, which is at index `j` in the blockchain, as a line of CSV.
+// If `hex_amounts` is true, the amount is written in hex (e.g. 0x64) so that
+// the line can be read back with --hex-amounts.
+
+fn format_block_csv(j: usize, b: &Block, hex_amounts: bool) -> String {
+    let amount = if hex_amounts {
+        format!("{:#x}", b.amount)
+    } else {
+        b.amount.to_string()
+    };
+    format!("{},{:#016x},{},{:#016x},{:#016x}",
+            j,
+            b.from_addr,
+            amount,
+            b.to_addr,
+            b.prev_hash)
+}
+
 // Print a blockchain `bc` in CSV format for easy ingestion for computers.
+// If `header` is true, a header row naming the columns is printed first,
+// which makes the file easier to understand when opened in a spreadsheet.
+// If `hex_amounts` is true, the amounts are written in hex.
 
-fn print_blockchain(bc: &Vec<Block>) {
+fn print_blockchain(bc: &[Block], header: bool, hex_amounts: bool, output: &mut impl Write) {
+    if header {
+        writeln!(output, "{}", CSV_HEADER).unwrap();
+    }
     for (j, b) in bc.iter().enumerate() {
-        println!("{},{:#016x},{},{:#016x},{:#016x}",
-                 j,
-                 b.from_addr,
-                 b.amount,
-                 b.to_addr,
-                 b.prev_hash);
-        
+        writeln!(output, "{}", format_block_csv(j, b, hex_amounts)).unwrap();
     }
 }
 
-// Print how many billcoins every address has.
+// Format block `b` at index `j` in the canonical form of a CSV line - every
+// hash and address is lowercase hex with a 0x prefix and all 16 digits, and
+// the amount is in decimal.  Unlike format_block_csv(), every line has the
+// same shape however big the values are, so canonical files diff cleanly.
 
-fn print_results(results: HashMap<Address, Amount>) {
+fn format_block_canonical(j: usize, b: &Block) -> String {
+    format!("{},0x{:016x},{},0x{:016x},0x{:016x}",
+            j,
+            b.from_addr,
+            b.amount,
+            b.to_addr,
+            b.prev_hash)
+}
+
+// Print a blockchain `bc` in canonical form, with no header and the indices
+// recomputed from each block's position.
+
+fn print_canonical_blockchain(bc: &[Block], output: &mut impl Write) {
+    for (j, b) in bc.iter().enumerate() {
+        writeln!(output, "{}", format_block_canonical(j, b)).unwrap();
+    }
+}
+
+// Print how many billcoins every address has.  If `human` is true, amounts
+// are printed with thousands separators.
+
+fn print_results(results: HashMap<Address, Amount>, human: bool, output: &mut impl Write) {
     for (address, amount) in results {
         // 0 is our "magic" source address - ignore it
         if address != 0 && amount != 0 {
-            println!("{:#016x} : {} billcoins", address, amount);
+            writeln!(output, "{:#016x} : {} billcoins", address, format_amount(amount, human)).unwrap();
         }
     }
 }
 
+// Print how many billcoins every address received and sent in total,
+// sorted by address.
+
+fn print_flows(flows: &HashMap<Address, Flow>, output: &mut impl Write) {
+    let mut addresses: Vec<&Address> = flows.keys().collect();
+    addresses.sort();
+    for address in addresses {
+        let flow = &flows[address];
+        writeln!(output, "{:#016x}: received {}, sent {}, net {}",
+                 address, flow.received, flow.sent, flow.net()).unwrap();
+    }
+}
+
 // Read blockchain from file file_name.
-// Note that we don't do much error-checking here in terms of file
-// reading - if there are any issues, we are likely to just panic.
-// This is more to keep the code simple than anything else.
+// Every field is checked before it is converted, so a bad line gives an
+// error saying which line and field is wrong instead of a panic (or, worse,
+// silently parsing as something unexpected).  Whitespace around a field is
+// ignored, but whitespace (or anything else unexpected) inside one is not.
+// The index at the start of every line is normally ignored, but if
+// `check_index` is true it must match the block's position in the chain
+// (0, 1, 2, ...) - this catches lines which were copied and pasted into the
+// wrong place in a hand-written file.
+// Only the check_index and hex_amounts options affect reading the file.
 
-fn read_file(file_name: &String) -> Vec<Block> {
-    let file = File::open(file_name).unwrap();
+fn read_file(file_name: &String, opts: &VerifyOptions) -> Result<Chain, String> {
+    let file = File::open(file_name)
+        .map_err(|e| format!("Could not open {}: {}", file_name, e))?;
     let reader = BufReader::new(file);
     let mut blocks: Vec<Block> = Vec::new();
 
     // Convert every line into a block and add to blockchain
     
-    for line in reader.lines() {
-        let line = line.unwrap();
-        let mut split = line.split(",");
-
-        // Create the block from the line and add it to the blockchain
-        // Remember that iterators are consumed, so all the nth(0)'s
-        // are reading the next element in line.
-        let b = Block {
-            from_addr: convert_hex(split.nth(1).unwrap().to_string()),
-            amount: convert_decimal(split.nth(0).unwrap().to_string()),
-            to_addr: convert_hex(split.nth(0).unwrap().to_string()),
-            prev_hash: convert_hex(split.nth(0).unwrap().to_string())
-        };
+    for (j, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| format!("Line {}: Could not read line: {}", j, e))?;
+
+        // Skip the header row, if the file has one
+        if j == 0 && line.trim() == CSV_HEADER {
+            continue;
+        }
+
+        // Create the block from the line and add it to the blockchain.
+        let b = parse_line(j, &line, blocks.len(), opts)?;
         blocks.push(b);
     }
-    blocks
+    Ok(Chain { blocks })
+}
+
+// Convert line j of a CSV file into a block, which will be at `position`
+// in the blockchain (this is only different to j if the file has a header).
+
+fn parse_line(j: usize, line: &str, position: usize, opts: &VerifyOptions) -> Result<Block, String> {
+    let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+    if fields.len() != 5 {
+        return Err(format!("Line {}: Expected 5 fields, found {}", j, fields.len()));
+    }
+
+    if opts.check_index {
+        let index = parse_field(j, "index", fields[0], false)?;
+        if index != position as u64 {
+            return Err(format!("Line {}: Index {} does not match the block's position {}",
+                               j, index, position));
+        }
+    }
+
+    Ok(Block {
+        from_addr: parse_field(j, "from", fields[1], true)?,
+        amount: parse_field(j, "amount", fields[2], opts.hex_amounts)?,
+        to_addr: parse_field(j, "to", fields[3], true)?,
+        prev_hash: parse_field(j, "prev_hash", fields[4], true)?
+    })
+}
+
+// Check that a (trimmed) CSV field only contains the characters we expect -
+// hex digits (plus the x of a 0x prefix) if `hex` is true, otherwise decimal
+// digits - and convert it.  Line j is only used for error messages.
+
+fn parse_field(j: usize, name: &str, field: &str, hex: bool) -> Result<u64, String> {
+    let bad_char = if hex {
+        field.chars().find(|c| !(c.is_ascii_hexdigit() || *c == 'x' || *c == 'X'))
+    } else {
+        field.chars().find(|c| !c.is_ascii_digit())
+    };
+    if let Some(c) = bad_char {
+        return Err(format!("Line {}: Field {} ({:?}) contains unexpected character {:?}",
+                           j, name, field, c));
+    }
+
+    if hex {
+        convert_hex(field.to_string()).map_err(|e| format!("Line {}: Field {}: {}", j, name, e))
+    } else if field.is_empty() {
+        Err(format!("Line {}: Field {} is empty", j, name))
+    } else {
+        field.parse::<u64>().map_err(|_| format!("Line {}: Field {} ({:?}) is too large", j, name, field))
+    }
+}
+
+// Independently recompute the hash of every block in the blockchain and
+// check that the prev_hash of the block after it matches, and that the
+// first block's prev_hash is `genesis` (normally 0x0).
+// verify_blockchain() also does this as it goes, but it stops at the first
+// problem it finds - so if a chain has an overspend early on, a tampered
+// block later in the chain will never be noticed.  Running this first means
+// the hash chain is checked in full, no matter what else is wrong.
+
+fn check_prev_hashes(blockchain: &[Block], genesis: Digest, hash_seed: Option<u64>) -> Result<(), String> {
+    match prev_hash_errors(blockchain, genesis, hash_seed).into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+// Same as check_prev_hashes(), but return every block whose prev_hash does
+// not match instead of just the first one.
+
+fn prev_hash_errors(blockchain: &[Block], genesis: Digest, hash_seed: Option<u64>) -> Vec<String> {
+    let mut expected_prev_hash = genesis;
+    let mut errors = Vec::new();
+    for (j, b) in blockchain.iter().enumerate() {
+        if b.prev_hash != expected_prev_hash {
+            errors.push(format!("Line {}: Prev hash was expected to be {:#016x}, not {:#016x}",
+                                j,
+                                expected_prev_hash,
+                                b.prev_hash));
+        }
+        expected_prev_hash = get_hash_seeded(b, hash_seed);
+    }
+    errors
 }
 
 // Verify that the blockchain is valid.  If it is, returns a hashmap of all
 // the accounts and how many billcoins they have.  If it is invalid,
 // returns an error specifying the problem (if known).
 
-fn verify_blockchain(blockchain: &Vec<Block>) -> Result<HashMap<Address, Amount>, String> {
+fn verify_blockchain(blockchain: &[Block], opts: &VerifyOptions) -> Result<HashMap<Address, Amount>, String> {
+    verify_blockchain_with_flows(blockchain, opts).map(|(balances, _)| balances)
+}
+
+// Same as verify_blockchain(), but also returns a hashmap of how many
+// billcoins every account received and sent in total.
+
+fn verify_blockchain_with_flows(blockchain: &[Block], opts: &VerifyOptions) -> Result<Ledger, String> {
     // TODO 1
     // Create a new HashMap<Address, Amount> and expected_prev_hash to store
-    // previous hashes to check.
-    let mut balances: HashMap<Address, Amount> = HashMap::new();
-    let mut expected_prev_hash = 0;
+    // previous hashes to check.  These are all kept in a Snapshot, which
+    // holds everything needed to carry on verifying from any block.
+    let mut state = Snapshot { prev_hash: opts.genesis, ..Default::default() };
+
+    // An empty chain is trivially valid, unless we were asked to reject it
+    if opts.reject_empty && blockchain.is_empty() {
+        return Err("Empty chain (0 blocks)".to_string());
+    }
+
+    // In hash-only mode, the hash chain is all that is checked
+    if opts.hash_only {
+        check_prev_hashes(blockchain, opts.genesis, opts.hash_seed)?;
+        return Ok((state.balances, state.flows));
+    }
+
+    // In canonical mode, the whole hash chain is checked before anything else
+    if opts.canonical {
+        check_prev_hashes(blockchain, opts.genesis, opts.hash_seed)?;
+    }
+
+    // When resuming, start from the state in the snapshot instead of from
+    // scratch, and skip over the blocks it has already verified.
+    if opts.resume {
+        state = resume_snapshot(blockchain, opts)?;
+    }
 
     // This is a special for loop which will update two variables at each
     // iteration:
     // j - contains an index (i.e., increments from 0,1,2... each iteration)
     // b - will contain the next block each iteration
     
-    for (j, b) in blockchain.iter().enumerate() {
-        // TODO 1
-        // Check to see if address has enough billcoins to actually send
-        // The only exception is address 0x0 - this is our magic source address
-        // where all billcoins come from.  Anyone can get any number of billcoins
-        // from 0x0, it has an inexhaustible supply.
-        // Otherwise, there are two possible error conditions - the address
-        // does not exist at all, or it has less than the amount of billcoins
-        // it is trying to send.  An address with 5 billcoins cannot send 10 to
-        // somebody else!
+    for (j, b) in blockchain.iter().enumerate().skip(state.blocks) {
+        verify_block(j, b, &mut state, opts)?;
 
+        // Every snapshot_every blocks, save where we have got to
+        if let (Some(every), Some(file)) = (opts.snapshot_every, &opts.snapshot_file) {
+            if state.blocks.is_multiple_of(every) {
+                write_snapshot(file, &state)?;
+            }
+        }
+        
+    }
 
-        if b.from_addr != 0 {
-            let num_billcoins_result = balances.get(&b.from_addr);
-            match num_billcoins_result {
-                Some(num_billcoins) => {
-                    if num_billcoins < &b.amount {
-                        return Err(format!("Line {}: Account {:#016x} only has {} billcoins; it cannot send {}",
-                                           j,
-                                           b.from_addr,
-                                           num_billcoins,
-                                           b.amount));
-                    }
-                },
-                None => {
-                    return Err(format!("Line {}: Account {:#016x} has 0 billcoins; it cannot send {}",
+    // TODO 6
+    
+    // Return hashmaps of balances and flows if all is correct
+
+    Ok((state.balances, state.flows))
+
+}
+
+// Read the snapshot to resume verifying `blockchain` from.  The last of the
+// blocks it has already verified must hash to the snapshot's prev_hash,
+// otherwise the snapshot is for a different chain (or the chain has been
+// changed).
+
+fn resume_snapshot(blockchain: &[Block], opts: &VerifyOptions) -> Result<Snapshot, String> {
+    let snapshot = read_snapshot(opts.snapshot_file.as_deref().unwrap_or_default())?;
+    if snapshot.blocks > blockchain.len()
+        || (snapshot.blocks > 0
+            && get_hash_seeded(&blockchain[snapshot.blocks - 1], opts.hash_seed) != snapshot.prev_hash) {
+        return Err("Snapshot does not match this blockchain".to_string());
+    }
+    Ok(snapshot)
+}
+
+// Check block `b`, at index `j` in the blockchain, against `state` (the
+// result of verifying every block before it).  If the block is valid,
+// `state` is updated to include it; if not, an error is returned and
+// `state` is left exactly as it was.  `j` is only used to say where any
+// error is, so it can also be a line number in a file.
+
+fn verify_block(j: usize, b: &Block, state: &mut Snapshot, opts: &VerifyOptions) -> Result<(), String> {
+    // TODO 1
+    // Check to see if address has enough billcoins to actually send
+    // The only exception is address 0x0 - this is our magic source address
+    // where all billcoins come from.  Anyone can get any number of billcoins
+    // from 0x0, it has an inexhaustible supply.
+    // Otherwise, there are two possible error conditions - the address
+    // does not exist at all, or it has less than the amount of billcoins
+    // it is trying to send.  An address with 5 billcoins cannot send 10 to
+    // somebody else!
+
+
+    if b.from_addr != 0 {
+        let num_billcoins_result = state.balances.get(&b.from_addr);
+        match num_billcoins_result {
+            Some(num_billcoins) => {
+                if num_billcoins < &b.amount {
+                    return Err(format!("Line {}: Account {:#016x} only has {} billcoins; it cannot send {}",
                                        j,
                                        b.from_addr,
+                                       num_billcoins,
                                        b.amount));
                 }
-                
+            },
+            None => {
+                return Err(format!("Line {}: Account {:#016x} has 0 billcoins; it cannot send {}",
+                                   j,
+                                   b.from_addr,
+                                   b.amount));
             }
             
         }
+        
+    }
+
+    // TODO 2
+
+    // Users can never send any billcoins _TO_ address 0x0 - it is only used as a source.
+    // If the to_address is 0, raise an error indicating this.
 
-        // TODO 2
+    if b.to_addr == 0 {
 
-        // Users can never send any billcoins _TO_ address 0x0 - it is only used as a source.
-        // If the to_address is 0, raise an error indicating this.
+        return Err(format!("Line {}: Account {:#016x} tried to send to address 0x00000000000000",
+                           j,
+                           b.from_addr));
+        
+    }
 
-        if b.to_addr == 0 {
+    // If amount bounds were requested, check that this transaction falls
+    // within them.  Mints from 0x0 can optionally be exempted.
 
-            return Err(format!("Line {}: Account {:#016x} tried to send to address 0x00000000000000",
+    if !(b.from_addr == 0 && opts.exempt_mints) {
+        if opts.reject_zero_amount && b.amount == 0 {
+            return Err(format!("Line {}: Account {:#016x} tried to send 0 billcoins",
                                j,
                                b.from_addr));
-            
         }
-        
-        // TODO 3
-        // Check to see if the prev_hash matches the expected previous hash
-        // The first prev_hash should always be 0x0.
-        // If not, return an error
-        if b.prev_hash != expected_prev_hash {
-            return Err(format!("Line {}: Prev hash was expected to be {:#016x}, not {:#016x}",
-                               j,
-                               expected_prev_hash,
-                               b.prev_hash));
+        if let Some(min) = opts.min_amount {
+            if b.amount < min {
+                return Err(format!("Line {}: Amount {} is below the minimum of {}",
+                                   j,
+                                   b.amount,
+                                   min));
+            }
         }
-        // TODO 4
-        
-        // Store the hash of this block as the expected previous hash for the
-        // next block (iteration of the for loop)
-        expected_prev_hash = get_hash(b);
-        
-        // TODO 5
-        
-        // If we have gotten here, all is in order.  Update the hash map to indicate
-        // that the from_address has lost a certain number of billcoins and the 
-        // to_address has gained an equivalent number of billcoins.
-        // No coins should ever be subtracted from the 0x0 address
-        // HINT: You may find .cloned() and .unwrap_or() helpful when dealing
-        // with the hashmap!
-
-        let old_balance_from = balances.get(&b.from_addr).cloned().unwrap_or(0);
-        let old_balance_to = balances.get(&b.to_addr).cloned().unwrap_or(0);
-        
-        if b.from_addr != 0 {
-            let new_from_amount = old_balance_from - b.amount;
-            balances.insert(b.from_addr, new_from_amount);
+        if let Some(max) = opts.max_amount {
+            if b.amount > max {
+                return Err(format!("Line {}: Amount {} is above the maximum of {}",
+                                   j,
+                                   b.amount,
+                                   max));
+            }
         }
-        let new_to_amount = old_balance_to + b.amount;
-        balances.insert(b.to_addr, new_to_amount);
-        
     }
 
-    // TODO 6
+    // If there is a maximum supply, check that this mint does not take
+    // the total number of billcoins ever minted over it.
+
+    let mut total_minted = state.total_minted;
+    if b.from_addr == 0 {
+        total_minted = total_minted.saturating_add(b.amount);
+        if let Some(max_supply) = opts.max_supply {
+            if total_minted > max_supply {
+                return Err(format!("Line {}: Minting {} billcoins would bring the total supply to {}, above the maximum supply of {}",
+                                   j,
+                                   b.amount,
+                                   total_minted,
+                                   max_supply));
+            }
+        }
+    }
+    
+    // TODO 3
+    // Check to see if the prev_hash matches the expected previous hash
+    // The first prev_hash should always be 0x0 (unless a different
+    // genesis hash was asked for).
+    // If not, return an error
+    if b.prev_hash != state.prev_hash {
+        return Err(format!("Line {}: Prev hash was expected to be {:#016x}, not {:#016x}",
+                           j,
+                           state.prev_hash,
+                           b.prev_hash));
+    }
+    // TODO 4
+    
+    // Store the hash of this block as the expected previous hash for the
+    // next block (iteration of the for loop)
+    state.prev_hash = get_hash_seeded(b, opts.hash_seed);
+    state.total_minted = total_minted;
+    state.blocks += 1;
+    
+    // TODO 5
+    
+    // If we have gotten here, all is in order.  Update the hash map to indicate
+    // that the from_address has lost a certain number of billcoins and the 
+    // to_address has gained an equivalent number of billcoins.
+    // No coins should ever be subtracted from the 0x0 address
+    // HINT: You may find .cloned() and .unwrap_or() helpful when dealing
+    // with the hashmap!
+
+    let old_balance_from = state.balances.get(&b.from_addr).cloned().unwrap_or(0);
+    let old_balance_to = state.balances.get(&b.to_addr).cloned().unwrap_or(0);
     
-    // Return hashmap of balances if all is correct
+    if b.from_addr != 0 {
+        let new_from_amount = old_balance_from - b.amount;
+        state.balances.insert(b.from_addr, new_from_amount);
+    }
+    let new_to_amount = old_balance_to + b.amount;
+    state.balances.insert(b.to_addr, new_to_amount);
+
+    // If asked to, keep a running total of everything sent and received.
+    // The magic address 0x0 is not counted as a sender - it creates
+    // billcoins rather than sending ones it has.
+    if opts.flows {
+        if b.from_addr != 0 {
+            state.flows.entry(b.from_addr).or_default().sent += b.amount as u128;
+        }
+        state.flows.entry(b.to_addr).or_default().received += b.amount as u128;
+    }
+
+    Ok(())
+}
+
+// Verify a blockchain as it is read in, one CSV line at a time from
+// `input`, printing "block N ok" as soon as each block is found to be
+// valid.  Stops at the first invalid block (or unreadable line), without
+// reading any further.  Nothing but the current state is kept, so this
+// works for a chain of any length - but for the same reason, checks which
+// need the whole chain up front (--canonical, --hash-only and --resume)
+// are not done.
+
+fn verify_stream(input: &mut impl BufRead, opts: &VerifyOptions,
+                 output: &mut impl Write) -> Result<Ledger, String> {
+    let mut state = Snapshot { prev_hash: opts.genesis, ..Default::default() };
+
+    for (j, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| format!("Line {}: Could not read line: {}", j, e))?;
+        if j == 0 && line.trim() == CSV_HEADER {
+            continue;
+        }
+        let b = parse_line(j, &line, state.blocks, opts)?;
+        verify_block(j, &b, &mut state, opts)?;
+        writeln!(output, "block {} ok", state.blocks - 1).unwrap();
+    }
+
+    if opts.reject_empty && state.blocks == 0 {
+        return Err("Empty chain (0 blocks)".to_string());
+    }
+    Ok((state.balances, state.flows))
+}
+
+// Verify the whole blockchain like verify_blockchain(), but instead of
+// stopping at the first invalid block, carry on and return every problem
+// found (an empty list means the chain is valid).  An invalid block is
+// skipped - its billcoins are not moved - but the next block is still
+// expected to point to its hash, so one bad block does not make every
+// block after it look bad too.
+// The hash-only, canonical and resume options work just as they do for
+// verify_blockchain().
+
+fn verify_all(blockchain: &[Block], opts: &VerifyOptions) -> Vec<String> {
+    let mut state = Snapshot { prev_hash: opts.genesis, ..Default::default() };
+    let mut errors = Vec::new();
+
+    if opts.reject_empty && blockchain.is_empty() {
+        errors.push("Empty chain (0 blocks)".to_string());
+    }
+
+    // In hash-only mode, the hash chain is all that is checked, and in
+    // canonical mode its problems are listed before anything else
+    if opts.hash_only || opts.canonical {
+        errors.extend(prev_hash_errors(blockchain, opts.genesis, opts.hash_seed));
+        if opts.hash_only {
+            return errors;
+        }
+    }
+
+    if opts.resume {
+        match resume_snapshot(blockchain, opts) {
+            Ok(snapshot) => state = snapshot,
+            Err(e) => {
+                errors.push(e);
+                return errors;
+            }
+        }
+    }
+
+    for (j, b) in blockchain.iter().enumerate().skip(state.blocks) {
+        if let Err(e) = verify_block(j, b, &mut state, opts) {
+            // In canonical mode, a bad prev_hash has already been listed
+            if !errors.contains(&e) {
+                errors.push(e);
+            }
+            state.prev_hash = get_hash_seeded(b, opts.hash_seed);
+            state.blocks = j + 1;
+        }
+    }
+    errors
+}
+
+// Write `snapshot` to `file` (replacing any older snapshot), as lines of
+// text:
+//   blocks <number of blocks verified>
+//   prev_hash <hash of the last block verified>
+//   minted <total billcoins minted>
+//   account <address> <balance> <received> <sent>   (one per account)
+// The received and sent totals are left off if flows were not kept.
+// Accounts are sorted by address, so the same state always gives the
+// same file.
+
+fn write_snapshot(file: &str, snapshot: &Snapshot) -> Result<(), String> {
+    let mut contents = format!("blocks {}\nprev_hash {:#016x}\nminted {}\n",
+                               snapshot.blocks, snapshot.prev_hash, snapshot.total_minted);
+    let mut addresses: Vec<&Address> = snapshot.balances.keys().collect();
+    addresses.sort();
+    for address in addresses {
+        contents.push_str(&format!("account {:#016x} {}", address, snapshot.balances[address]));
+        if let Some(flow) = snapshot.flows.get(address) {
+            contents.push_str(&format!(" {} {}", flow.received, flow.sent));
+        }
+        contents.push('\n');
+    }
+    fs::write(file, contents).map_err(|e| format!("Could not write snapshot {}: {}", file, e))
+}
+
+// Read a snapshot written by write_snapshot().
+
+fn read_snapshot(file: &str) -> Result<Snapshot, String> {
+    let contents = fs::read_to_string(file)
+        .map_err(|e| format!("Could not read snapshot {}: {}", file, e))?;
+    let mut snapshot = Snapshot::default();
+
+    for (j, line) in contents.lines().enumerate() {
+        let bad_line = || format!("Snapshot {} line {}: Could not understand {:?}", file, j, line);
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let decimal = |x: &str| x.parse::<u64>().map_err(|_| bad_line());
+        let total = |x: &str| x.parse::<u128>().map_err(|_| bad_line());
+        let hex = |x: &str| convert_hex(x.to_string()).map_err(|_| bad_line());
+        match fields.as_slice() {
+            ["blocks", n] => snapshot.blocks = decimal(n)? as usize,
+            ["prev_hash", h] => snapshot.prev_hash = hex(h)?,
+            ["minted", n] => snapshot.total_minted = decimal(n)?,
+            ["account", address, balance] => {
+                snapshot.balances.insert(hex(address)?, decimal(balance)?);
+            },
+            ["account", address, balance, received, sent] => {
+                let address = hex(address)?;
+                snapshot.balances.insert(address, decimal(balance)?);
+                snapshot.flows.insert(address, Flow { received: total(received)?, sent: total(sent)? });
+            },
+            _ => return Err(bad_line()),
+        }
+    }
+    Ok(snapshot)
+}
+
+// The starting value and multiplier of the 64-bit FNV-1a hash function.
+// See [here](https://en.wikipedia.org/wiki/Fowler%E2%80%93Noll%E2%80%93Vo_hash_function).
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+// Hash a list of 64-bit words with FNV-1a, one little-endian byte at a time
+// (after the seed, if one is given).  Unlike get_hash(), this never changes:
+// DefaultHasher's algorithm is not guaranteed to stay the same between
+// versions of Rust, but this gives the same value everywhere, forever.
+// It is nowhere near a cryptographic hash, but it is fine for fingerprinting.
+
+fn stable_hash(words: &[u64], seed: Option<u64>) -> Digest {
+    let mut h = FNV_OFFSET_BASIS;
+    for word in seed.iter().chain(words.iter()) {
+        for byte in word.to_le_bytes().iter() {
+            h ^= *byte as u64;
+            h = h.wrapping_mul(FNV_PRIME);
+        }
+    }
+    h
+}
+
+// Compute the Merkle root of a blockchain - hash every block, then keep
+// hashing pairs of hashes together until only one is left.  If a level
+// has an odd number of hashes, the last one is paired with itself (like
+// Bitcoin does).  Changing any block changes the root, so two chains can
+// be compared by just this one value.  An empty chain has a root of 0x0.
+// See [here](https://en.wikipedia.org/wiki/Merkle_tree).
+// The root is printed for people to write down and compare later, so it
+// uses stable_hash() rather than get_hash() - the same chain has the same
+// root whichever version of Rust this was built with.
+
+fn merkle_root(blockchain: &[Block], hash_seed: Option<u64>) -> Digest {
+    let mut level: Vec<Digest> = blockchain.iter()
+        .map(|b| stable_hash(&[b.to_addr, b.from_addr, b.amount, b.prev_hash], hash_seed))
+        .collect();
+    if level.is_empty() {
+        return 0;
+    }
+    while level.len() > 1 {
+        level = level.chunks(2)
+            .map(|pair| stable_hash(&[pair[0], *pair.last().unwrap()], hash_seed))
+            .collect();
+    }
+    level[0]
+}
+
+// Compare two blockchains block by block, returning a description of the
+// first difference (which block, and which field of it), or None if they
+// are identical.  If one chain is just the other with extra blocks on the
+// end, the difference is in their lengths.
+
+fn diff_chains(a: &[Block], b: &[Block]) -> Option<String> {
+    for (j, (block_a, block_b)) in a.iter().zip(b.iter()).enumerate() {
+        let fields = [("from", block_a.from_addr, block_b.from_addr),
+                      ("amount", block_a.amount, block_b.amount),
+                      ("to", block_a.to_addr, block_b.to_addr),
+                      ("prev_hash", block_a.prev_hash, block_b.prev_hash)];
+        if let Some((name, x, y)) = fields.iter().find(|(_, x, y)| x != y) {
+            return Some(if *name == "amount" {
+                format!("Block {}: {} differs ({} vs {})", j, name, x, y)
+            } else {
+                format!("Block {}: {} differs ({:#016x} vs {:#016x})", j, name, x, y)
+            });
+        }
+    }
+
+    if a.len() != b.len() {
+        return Some(format!("Chains differ in length ({} vs {} blocks); the first {} blocks are identical",
+                            a.len(), b.len(), a.len().min(b.len())));
+    }
+    None
+}
+
+// Find the first block which makes the blockchain invalid, i.e. the
+// shortest part of the chain which does not verify ends with it.  Returns
+// None if the problem is not with any one block (e.g. the chain is empty,
+// or the snapshot being resumed from does not match).
+// This goes through the chain once, checking each block against the state
+// left by the ones before it, just like verify_blockchain() does.
+
+fn first_invalid_block(blockchain: &[Block], opts: &VerifyOptions) -> Option<usize> {
+    let mut state = Snapshot { prev_hash: opts.genesis, ..Default::default() };
+    if opts.resume && !opts.hash_only {
+        state = resume_snapshot(blockchain, opts).ok()?;
+    }
 
-    Ok(balances)
+    (state.blocks..blockchain.len()).find(|&j| step_block(j, &blockchain[j], &mut state, opts).is_err())
+}
+
+// Apply block `b`, at index `j`, to `state` - like verify_block(), but in
+// hash-only mode just check that the block follows on from the one before
+// it.
 
+fn step_block(j: usize, b: &Block, state: &mut Snapshot, opts: &VerifyOptions) -> Result<(), String> {
+    if !opts.hash_only {
+        return verify_block(j, b, state, opts);
+    }
+    if b.prev_hash != state.prev_hash {
+        return Err(format!("Line {}: Prev hash was expected to be {:#016x}, not {:#016x}",
+                           j,
+                           state.prev_hash,
+                           b.prev_hash));
+    }
+    state.prev_hash = get_hash_seeded(b, opts.hash_seed);
+    state.blocks += 1;
+    Ok(())
 }
 
 // Read and verify blockchain.
 
-fn read_blockchain(f: String) -> Result<HashMap<Address, Amount>, String> {
-    let blockchain = read_file(&f);
-    pretty_print_blockchain(&blockchain);
-    verify_blockchain(&blockchain)
+// An empty file is a valid (empty) blockchain, but since that would print
+// nothing at all before "Blockchain valid!", say so explicitly.
+
+fn read_blockchain(f: String, opts: &VerifyOptions, human: bool,
+                   output: &mut impl Write) -> Result<Ledger, String> {
+    let blockchain = read_file(&f, opts)?.blocks;
+    if blockchain.is_empty() {
+        writeln!(output, "Empty chain (0 blocks)").unwrap();
+    }
+    pretty_print_blockchain(&blockchain, human, output);
+    verify_blockchain_with_flows(&blockchain, opts)
 }
 
+// Step through the blockchain one block at a time, waiting for the user to
+// press Enter (on `input`) before applying each block and then printing how
+// the balances changed.  Stops at the first invalid block and returns its
+// error, just like verify_blockchain().
+// Each step checks the new block against the state left by the ones before
+// it, using the same rules as when verifying normally.
+
+fn replay_blockchain(blockchain: &[Block],
+                     opts: &VerifyOptions,
+                     input: &mut impl BufRead,
+                     output: &mut impl Write) -> Result<HashMap<Address, Amount>, String> {
+    let mut state = Snapshot { prev_hash: opts.genesis, ..Default::default() };
+
+    for (j, b) in blockchain.iter().enumerate() {
+        write!(output, "Press Enter to apply block {}: {:#016x} sends {} to {:#016x} > ",
+               j, b.from_addr, b.amount, b.to_addr).unwrap();
+        let _ = output.flush();
+        // If the input has ended, just keep going without pausing
+        let _ = read_input_line(input);
+        writeln!(output).unwrap();
+
+        let balances = state.balances.clone();
+        if let Err(e) = step_block(j, b, &mut state, opts) {
+            writeln!(output, "Block {} is invalid!", j).unwrap();
+            return Err(e);
+        }
+
+        for address in [b.from_addr, b.to_addr].iter() {
+            // 0 is our "magic" source address - ignore it
+            if *address != 0 {
+                writeln!(output, "  {:#016x}: {} -> {}",
+                         address,
+                         balances.get(address).cloned().unwrap_or(0),
+                         state.balances.get(address).cloned().unwrap_or(0)).unwrap();
+            }
+        }
+    }
+
+    Ok(state.balances)
+}
 
 // Get block information from the user (from address, to address,
 // and amount.  Recall that every block has only a single transaction.
 // We also need the previous hash to generate a block, so it is
 // passed in as an argument.
 //
-// User can enter the block data from STDIN.  Type "x" for the "from"
-// address to stop generating blocks.
+// User can enter the block data from `input` (normally STDIN), and the
+// prompts are written to `output` (normally STDOUT).  Type "x" for the
+// "from" address to stop generating blocks.
 //
 // This will return either None (if the block could not be created,
 // probably because the user entered "x" because they did not want to
 // continue generating the blockchain) or Some(block).
 
-fn get_block_info(prev_hash: Digest) -> Option<Block> {
-    let mut to_addr: String = String::new();
-    let mut from_addr: String = String::new();
-    let mut amount: String = String::new();
-
-    print!("From address (hex) > ");
-    let _ = io::stdout().flush();
-    io::stdin().read_line(&mut from_addr).unwrap();
-    from_addr = from_addr.trim().to_string();
+fn get_block_info(prev_hash: Digest,
+                  hex_amounts: bool,
+                  input: &mut impl BufRead,
+                  output: &mut impl Write) -> Option<Block> {
+    write!(output, "From address (hex) > ").unwrap();
+    let _ = output.flush();
+    let from_addr = read_input_line(input)?;
     if from_addr == "x" {
         return None
     }
-    print!("To address (hex) > ");
-    let _ = io::stdout().flush();
-    io::stdin().read_line(&mut to_addr).expect("Error");
-    to_addr = to_addr.trim().to_string();
+    write!(output, "To address (hex) > ").unwrap();
+    let _ = output.flush();
+    let to_addr = read_input_line(input)?;
     
-    print!("Amount > ");
-    let _ = io::stdout().flush();
-    io::stdin().read_line(&mut amount).expect("Error");
-    amount = amount.trim().to_string();
+    if hex_amounts {
+        write!(output, "Amount (hex) > ").unwrap();
+    } else {
+        write!(output, "Amount > ").unwrap();
+    }
+    let _ = output.flush();
+    let amount = read_input_line(input)?;
+
+    // Generate block from input.  If an address is not valid hex, tell the
+    // user why and stop, rather than panicking.
+
+    let (to_addr, from_addr) = match (convert_hex(to_addr), convert_hex(from_addr)) {
+        (Ok(to), Ok(from)) => (to, from),
+        (Err(e), _) | (_, Err(e)) => {
+            writeln!(output, "{}", e).unwrap();
+            return None;
+        }
+    };
 
-    // Generate block from input
+    let amount = if hex_amounts {
+        convert_hex(amount)
+    } else {
+        convert_decimal(amount)
+    };
+    let amount = match amount {
+        Ok(a) => a,
+        Err(e) => {
+            writeln!(output, "{}", e).unwrap();
+            return None;
+        }
+    };
 
     let b = Block {
-        to_addr: convert_hex(to_addr),
-        from_addr: convert_hex(from_addr),
-        amount: convert_decimal(amount),
+        to_addr,
+        from_addr,
+        amount,
         prev_hash: prev_hash
     };
 
@@ -315,23 +1072,39 @@ fn get_block_info(prev_hash: Digest) -> Option<Block> {
         
 }
 
-// Generate a blockchain given input from the user (or really, STDIN)
-
-fn make_blockchain() -> Vec<Block> {
-    let mut prev_hash = 0;
-    
-    let mut blockchain: Vec<Block> = Vec::new();
+// Read a single line from `input`, with surrounding whitespace removed.
+// Returns None if the input has ended (e.g. STDIN was closed, or a piped
+// file ran out) - read_line() just returns 0 bytes in this case rather
+// than an error, so without this check we would try to parse an empty
+// string.  A read error is treated the same way.
+
+fn read_input_line(input: &mut impl BufRead) -> Option<String> {
+    let mut line = String::new();
+    match input.read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim().to_string()),
+    }
+}
+
+// Generate a blockchain given input from the user (or really, `input`)
+
+// Only the hash_seed and hex_amounts options affect generating blocks.
+
+fn make_blockchain(input: &mut impl BufRead, output: &mut impl Write, opts: &VerifyOptions) -> Vec<Block> {
+    let mut prev_hash = 0;
+    
+    let mut blockchain: Vec<Block> = Vec::new();
 
     let mut block_num = 0;
     loop {
-        println!("Block Number: {}", block_num);
+        writeln!(output, "Block Number: {}", block_num).unwrap();
         
-        let block_option = get_block_info(prev_hash);
+        let block_option = get_block_info(prev_hash, opts.hex_amounts, input, output);
         match block_option {
             Some(b) => {
                 // Get hash of this block to use as prev_hash for
                 // NEXT block
-                prev_hash = get_hash(&b);
+                prev_hash = get_hash_seeded(&b, opts.hash_seed);
 
                 // Add block to blockchain
                 blockchain.push(b);
@@ -350,50 +1123,1496 @@ fn make_blockchain() -> Vec<Block> {
         
 }
 
-fn print_usage_and_exit() {
-    println!("Usage:");
-    println!("No arguments: ");
-    println!("One argument: Read file specified by argument and display if blockchain is valid");
-    std::process::exit(1);
+fn print_usage(output: &mut impl Write) {
+    writeln!(output, "Usage:").unwrap();
+    writeln!(output, "No arguments: ").unwrap();
+    writeln!(output, "One argument: Read file specified by argument and display if blockchain is valid").unwrap();
+    writeln!(output, "--verify-stdin-stream: Verify a blockchain read from standard input, one block at a time").unwrap();
+    writeln!(output, "diff-chains <a> <b>: Show the first block where two blockchain files differ").unwrap();
+    writeln!(output, "<file> canonicalize <out>: Rewrite a blockchain file in canonical form").unwrap();
+    writeln!(output, "<file> root: Verify a blockchain file and print its Merkle root").unwrap();
+    writeln!(output, "Options (when generating):").unwrap();
+    writeln!(output, "  --header - print a header row before the blocks").unwrap();
+    writeln!(output, "Options (when generating or verifying):").unwrap();
+    writeln!(output, "  --hex-amounts - amounts are in hex instead of decimal").unwrap();
+    writeln!(output, "  --hash-seed <n> - hash blocks with this seed; the chain only verifies with the same seed").unwrap();
+    writeln!(output, "Options (when verifying):").unwrap();
+    writeln!(output, "  --min-amount <n> - reject any transaction sending less than n billcoins").unwrap();
+    writeln!(output, "  --max-amount <n> - reject any transaction sending more than n billcoins").unwrap();
+    writeln!(output, "  --strict-amount-zero - reject any transaction sending 0 billcoins").unwrap();
+    writeln!(output, "  --exempt-mints - do not apply the amount checks to transactions from 0x0").unwrap();
+    writeln!(output, "  --max-supply <n> - reject the chain if more than n billcoins are minted from 0x0 in total").unwrap();
+    writeln!(output, "  --canonical - check every prev_hash in the chain before checking balances").unwrap();
+    writeln!(output, "  --hash-only - only check the prev_hash of every block, not the balances").unwrap();
+    writeln!(output, "  --genesis <hex> - expect the first block's prev_hash to be this instead of 0x0").unwrap();
+    writeln!(output, "  --check-index - check that the index of every line matches its position").unwrap();
+    writeln!(output, "  --reject-empty - treat a chain with no blocks as invalid").unwrap();
+    writeln!(output, "  --snapshot-every <n> - save a snapshot of the balances every n blocks").unwrap();
+    writeln!(output, "  --resume - only verify the blocks after the last snapshot").unwrap();
+    writeln!(output, "  --snapshot-file <path> - where snapshots are kept (default <file>.snapshot)").unwrap();
+    writeln!(output, "  --all-errors - carry on past invalid blocks and report every problem").unwrap();
+    writeln!(output, "  --human - print amounts with thousands separators (e.g. 1,000,000)").unwrap();
+    writeln!(output, "  --only-errors - only print the blocks (up to the bad one) if the chain is invalid").unwrap();
+    writeln!(output, "  --compact - only print VALID <blocks> <accounts> or INVALID <reason>").unwrap();
+    writeln!(output, "  --replay - apply one block at a time, pressing Enter to step").unwrap();
+    writeln!(output, "  --flows - also print how much every account received and sent").unwrap();
 }
 
-// Execution starts here
+// Remove the flag `name` from `args` if it is present, returning whether
+// or not it was found.
 
-fn main() {
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().position(|a| a == name) {
+        Some(i) => {
+            args.remove(i);
+            true
+        },
+        None => false
+    }
+}
+
+// Remove the option `name` and the value following it from `args`,
+// returning the value if the option was given.  It is an error for the
+// option to be the last argument, since then it has no value.
 
-    let args_count = env::args().count();
-    if args_count <= 1 {
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>, String> {
+    match args.iter().position(|a| a == name) {
+        Some(i) => {
+            if i + 1 >= args.len() {
+                return Err(format!("{} requires a value", name));
+            }
+            let val = args.remove(i + 1);
+            args.remove(i);
+            Ok(Some(val))
+        },
+        None => Ok(None)
+    }
+}
+
+// Pull all of the verification options out of `args`, leaving only the
+// positional arguments behind.
+
+fn parse_verify_options(args: &mut Vec<String>) -> Result<VerifyOptions, String> {
+    let mut opts = VerifyOptions { flows: take_flag(args, "--flows"), ..Default::default() };
+
+    if let Some(min) = take_option(args, "--min-amount")? {
+        opts.min_amount = Some(min.parse::<Amount>()
+                               .map_err(|_| format!("Invalid minimum amount: {}", min))?);
+    }
+    if let Some(max) = take_option(args, "--max-amount")? {
+        opts.max_amount = Some(max.parse::<Amount>()
+                               .map_err(|_| format!("Invalid maximum amount: {}", max))?);
+    }
+    if let Some(supply) = take_option(args, "--max-supply")? {
+        opts.max_supply = Some(supply.parse::<Amount>()
+                               .map_err(|_| format!("Invalid maximum supply: {}", supply))?);
+    }
+    if let Some(seed) = take_option(args, "--hash-seed")? {
+        opts.hash_seed = Some(seed.parse::<u64>()
+                              .map_err(|_| format!("Invalid hash seed: {}", seed))?);
+    }
+    opts.reject_zero_amount = take_flag(args, "--strict-amount-zero");
+    opts.exempt_mints = take_flag(args, "--exempt-mints");
+    opts.canonical = take_flag(args, "--canonical");
+    opts.hash_only = take_flag(args, "--hash-only");
+    opts.reject_empty = take_flag(args, "--reject-empty");
+    opts.check_index = take_flag(args, "--check-index");
+    opts.hex_amounts = take_flag(args, "--hex-amounts");
+    if let Some(every) = take_option(args, "--snapshot-every")? {
+        opts.snapshot_every = match every.parse::<usize>() {
+            Ok(n) if n > 0 => Some(n),
+            _ => return Err(format!("Invalid snapshot interval: {}", every)),
+        };
+    }
+    opts.resume = take_flag(args, "--resume");
+    opts.snapshot_file = take_option(args, "--snapshot-file")?;
+    if let Some(genesis) = take_option(args, "--genesis")? {
+        opts.genesis = convert_hex(genesis.clone())
+            .map_err(|e| format!("Invalid genesis hash: {}", e))?;
+    }
+
+    Ok(opts)
+}
+
+// Run the program with the command-line arguments `args` (including the
+// program name), reading any user input from `input` and writing messages
+// to `output`.  Returns the exit code of the program - 0 on success, 1 if
+// the blockchain was invalid or the arguments were bad.
+// Keeping this separate from main() lets the tests drive the whole program
+// without having to spawn a new process.
+
+fn run(mut args: Vec<String>, mut input: impl BufRead, mut output: impl Write) -> i32 {
+
+    let mut opts = match parse_verify_options(&mut args) {
+        Ok(o) => o,
+        Err(e) => {
+            writeln!(output, "Error: {}", e).unwrap();
+            print_usage(&mut output);
+            return 1;
+        }
+    };
+    let compact = take_flag(&mut args, "--compact");
+    let header = take_flag(&mut args, "--header");
+    let replay = take_flag(&mut args, "--replay");
+    let only_errors = take_flag(&mut args, "--only-errors");
+    let human = take_flag(&mut args, "--human");
+    let all_errors = take_flag(&mut args, "--all-errors");
+    let stream = take_flag(&mut args, "--verify-stdin-stream");
+
+    let args_count = args.len();
+    if args_count <= 1 && stream {
+        // Verify a chain from standard input, block by block as it arrives
+        match verify_stream(&mut input, &opts, &mut output) {
+            Ok(_) => {
+                writeln!(output, "Blockchain valid!").unwrap();
+                0
+            },
+            Err(e) => {
+                writeln!(output, "Blockchain invalid: {}", e).unwrap();
+                1
+            }
+        }
+    } else if args_count <= 1 {
         // If no arguments are supplied, allow user to make a blockchain.
         // It will then be printed out in CSV, and you can copy/paste into a
         // file.
-        let blockchain = make_blockchain();
-        print_blockchain(&blockchain);
+        let blockchain = make_blockchain(&mut input, &mut output, &opts);
+        print_blockchain(&blockchain, header, opts.hex_amounts, &mut output);
+        0
     } else if args_count == 2 {
 
         // Otherwise, if exactly one argument is given, assume it is a
         // CSV file with blockchain data.
+
+        // Snapshots are kept next to the blockchain file unless asked
+        // otherwise
+        if opts.snapshot_file.is_none() {
+            opts.snapshot_file = Some(format!("{}.snapshot", args[1]));
+        }
         
+        // In replay mode, step through the blocks one at a time
+        if replay {
+            let result = read_file(&args[1], &opts)
+                .and_then(|chain| replay_blockchain(&chain.blocks, &opts, &mut input, &mut output));
+            return match result {
+                Ok(_) => {
+                    writeln!(output, "Blockchain valid!").unwrap();
+                    0
+                },
+                Err(e) => {
+                    writeln!(output, "Blockchain invalid: {}", e).unwrap();
+                    1
+                }
+            };
+        }
+
+        // List every problem with the chain, rather than just the first one
+        if all_errors {
+            let result = read_file(&args[1], &opts).map(|chain| verify_all(&chain.blocks, &opts));
+            return match result {
+                Ok(ref errors) if errors.is_empty() => {
+                    writeln!(output, "Blockchain valid!").unwrap();
+                    0
+                },
+                Ok(errors) => {
+                    for e in errors.iter() {
+                        writeln!(output, "{}", e).unwrap();
+                    }
+                    writeln!(output, "Blockchain invalid: {} problems found", errors.len()).unwrap();
+                    1
+                },
+                Err(e) => {
+                    writeln!(output, "Blockchain invalid: {}", e).unwrap();
+                    1
+                }
+            };
+        }
+
+        // When only showing errors, a valid chain just gets the result line,
+        // and an invalid one only shows the blocks up to the bad one.
+        if only_errors {
+            let result = read_file(&args[1], &opts).and_then(|chain| {
+                verify_blockchain(&chain.blocks, &opts).inspect_err(|_| {
+                    if let Some(j) = first_invalid_block(&chain.blocks, &opts) {
+                        pretty_print_blockchain(&chain.blocks[..=j], human, &mut output);
+                    }
+                })
+            });
+            return match result {
+                Ok(_) => {
+                    writeln!(output, "Blockchain valid!").unwrap();
+                    0
+                },
+                Err(e) => {
+                    writeln!(output, "Blockchain invalid: {}", e).unwrap();
+                    1
+                }
+            };
+        }
+
+        // In compact mode, print exactly one line saying whether the
+        // blockchain is valid, with no pretty-printing of the blocks.
+        if compact {
+            let result = read_file(&args[1], &opts)
+                .and_then(|chain| Ok((chain.blocks.len(), verify_blockchain(&chain.blocks, &opts)?)));
+            return match result {
+                Ok((num_blocks, balances)) => {
+                    writeln!(output, "VALID {} {}", num_blocks, balances.len()).unwrap();
+                    0
+                },
+                Err(e) => {
+                    writeln!(output, "INVALID {}", e).unwrap();
+                    1
+                }
+            };
+        }
+
         // Note: we know this element exists, otherwise we would
-        // have to worry about unwrap() panicking
-        let valid = read_blockchain(env::args().nth(1).unwrap());
+        // have to worry about indexing panicking
+        let valid = read_blockchain(args[1].clone(), &opts, human, &mut output);
         
         // If blockchain is valid, print out the final results - which
         // addresses exist and how many billcoins they own
         // Otherwise, say it is invalid (and hopefully why)
         match valid {
-            Ok(bc) => {
-                print_results(bc);
-                println!("Blockchain valid!");
+            Ok((balances, flows)) => {
+                print_results(balances, human, &mut output);
+                if opts.flows {
+                    print_flows(&flows, &mut output);
+                }
+                writeln!(output, "Blockchain valid!").unwrap();
+                0
             },
             Err(e) => {
-                println!("Blockchain invalid: {}", e);
+                writeln!(output, "Blockchain invalid: {}", e).unwrap();
+                1
             }
         } 
         
-        
+    } else if args_count == 4 && args[1] == "diff-chains" {
+        // Compare two blockchain files, block by block
+        let chains = read_file(&args[2], &opts)
+            .and_then(|a| Ok((a, read_file(&args[3], &opts)?)));
+        match chains {
+            Ok((a, b)) => match diff_chains(&a.blocks, &b.blocks) {
+                None => {
+                    writeln!(output, "identical").unwrap();
+                    0
+                },
+                Some(difference) => {
+                    writeln!(output, "{}", difference).unwrap();
+                    1
+                }
+            },
+            Err(e) => {
+                writeln!(output, "Error: {}", e).unwrap();
+                1
+            }
+        }
+    } else if args_count == 3 && args[2] == "root" {
+        // Only a valid chain gets a root, so that it can't be mistaken
+        // for the fingerprint of a good chain
+        let result = read_file(&args[1], &opts)
+            .and_then(|chain| verify_blockchain(&chain.blocks, &opts).map(|_| chain));
+        match result {
+            Ok(chain) => {
+                writeln!(output, "Merkle root: {:#016x}", merkle_root(&chain.blocks, opts.hash_seed)).unwrap();
+                0
+            },
+            Err(e) => {
+                writeln!(output, "Blockchain invalid: {}", e).unwrap();
+                1
+            }
+        }
+    } else if args_count == 4 && args[2] == "canonicalize" {
+        // Rewrite a blockchain file in canonical form, so that two files
+        // with the same blocks are byte-for-byte identical
+        let result = read_file(&args[1], &opts).and_then(|chain| {
+            let mut out = File::create(&args[3])
+                .map_err(|e| format!("Could not create {}: {}", args[3], e))?;
+            print_canonical_blockchain(&chain.blocks, &mut out);
+            Ok(chain.blocks.len())
+        });
+        match result {
+            Ok(num_blocks) => {
+                writeln!(output, "Wrote {} blocks to {}", num_blocks, args[3]).unwrap();
+                0
+            },
+            Err(e) => {
+                writeln!(output, "Error: {}", e).unwrap();
+                1
+            }
+        }
     } else {
         // If more than one argument is there, instruct user how to use
         // program and exit.
-        print_usage_and_exit();
+        print_usage(&mut output);
+        1
+    }
+}
+
+// Execution starts here
+
+fn main() {
+    let stdin = io::stdin();
+    let code = run(env::args().collect(), stdin.lock(), io::stdout());
+    std::process::exit(code);
+}
+
+// Tests start here
+// Run "cargo test" to run all of them
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Build a valid chain (in terms of prev hashes) out of a list of
+    // (from, to, amount) transactions.
+
+    fn make_chain(txs: &[(Address, Address, Amount)]) -> Vec<Block> {
+        make_chain_from(0, txs)
+    }
+
+    // Same as make_chain(), but with the given genesis hash as the first
+    // block's prev_hash.
+
+    fn make_chain_from(genesis: Digest, txs: &[(Address, Address, Amount)]) -> Vec<Block> {
+        let mut prev_hash = genesis;
+        let mut blockchain = Vec::new();
+        for &(from_addr, to_addr, amount) in txs {
+            let b = Block {
+                to_addr,
+                from_addr,
+                amount,
+                prev_hash
+            };
+            prev_hash = get_hash(&b);
+            blockchain.push(b);
+        }
+        blockchain
+    }
+
+    // Run the whole program with the given arguments (not including the
+    // program name) and input, returning the exit code and the output.
+
+    fn run_with(args: &[&str], input: &str) -> (i32, String) {
+        let mut full_args = vec!["billcoin".to_string()];
+        full_args.extend(args.iter().map(|a| a.to_string()));
+        let mut output = Vec::new();
+        let code = run(full_args, input.as_bytes(), &mut output);
+        (code, String::from_utf8(output).unwrap())
+    }
+
+    // ****************************************************************
+    // verify_blockchain_with_flows() function
+    // ****************************************************************
+
+    #[test]
+    fn test_flows_for_account_sending_and_receiving() {
+        let bc = make_chain(&[(0, 0xA, 100),
+                              (0xA, 0xB, 30),
+                              (0xB, 0xA, 10),
+                              (0xA, 0xC, 50),
+                              (0, 0xA, 5)]);
+        let opts = VerifyOptions { flows: true, ..Default::default() };
+        let (balances, flows) = verify_blockchain_with_flows(&bc, &opts).unwrap();
+        assert_eq!(flows[&0xA], Flow { received: 115, sent: 80 });
+        assert_eq!(flows[&0xA].net(), 35);
+        assert_eq!(balances[&0xA], 35);
+        assert_eq!(flows[&0xB], Flow { received: 30, sent: 10 });
+        assert_eq!(flows[&0xC].net(), 50);
+    }
+
+    #[test]
+    fn test_flows_exclude_magic_address_as_sender() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 30)]);
+        let opts = VerifyOptions { flows: true, ..Default::default() };
+        let (_, flows) = verify_blockchain_with_flows(&bc, &opts).unwrap();
+        assert!(!flows.contains_key(&0));
+    }
+
+    #[test]
+    fn test_flows_only_kept_when_asked_for() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 30)]);
+        let (_, flows) = verify_blockchain_with_flows(&bc, &VerifyOptions::default()).unwrap();
+        assert!(flows.is_empty());
+    }
+
+    #[test]
+    fn test_flows_can_total_more_than_u64() {
+        // Every balance fits in a u64, but 0xA receives u64::MAX twice
+        let bc = make_chain(&[(0, 0xA, u64::MAX), (0xA, 0xB, u64::MAX), (0xB, 0xA, u64::MAX)]);
+        let (balances, _) = verify_blockchain_with_flows(&bc, &VerifyOptions::default()).unwrap();
+        assert_eq!(balances[&0xA], u64::MAX);
+        let opts = VerifyOptions { flows: true, ..Default::default() };
+        let (_, flows) = verify_blockchain_with_flows(&bc, &opts).unwrap();
+        assert_eq!(flows[&0xA], Flow { received: 2 * u64::MAX as u128, sent: u64::MAX as u128 });
+        assert_eq!(flows[&0xA].net(), u64::MAX as i128);
+    }
+
+    #[test]
+    fn test_print_flows() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 30)]);
+        let opts = VerifyOptions { flows: true, ..Default::default() };
+        let (_, flows) = verify_blockchain_with_flows(&bc, &opts).unwrap();
+        let mut output = Vec::new();
+        print_flows(&flows, &mut output);
+        assert_eq!(String::from_utf8(output).unwrap(),
+                   "0x0000000000000a: received 100, sent 30, net 70\n\
+                    0x0000000000000b: received 30, sent 0, net 30\n");
+    }
+
+    // ****************************************************************
+    // verify_blockchain() function - amount bounds
+    // ****************************************************************
+
+    #[test]
+    fn test_verify_no_bounds_by_default() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 1)]);
+        assert!(verify_blockchain(&bc, &VerifyOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_verify_below_min_amount_rejected() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 1)]);
+        let opts = VerifyOptions { min_amount: Some(5), ..Default::default() };
+        let r = verify_blockchain(&bc, &opts);
+        assert_eq!(r.unwrap_err(), "Line 1: Amount 1 is below the minimum of 5");
+    }
+
+    #[test]
+    fn test_verify_above_max_amount_rejected() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 60)]);
+        let opts = VerifyOptions { max_amount: Some(50), exempt_mints: true, ..Default::default() };
+        let r = verify_blockchain(&bc, &opts);
+        assert_eq!(r.unwrap_err(), "Line 1: Amount 60 is above the maximum of 50");
+    }
+
+    #[test]
+    fn test_verify_max_amount_applies_to_mints() {
+        let bc = make_chain(&[(0, 0xA, 100)]);
+        let opts = VerifyOptions { max_amount: Some(50), ..Default::default() };
+        assert!(verify_blockchain(&bc, &opts).is_err());
+    }
+
+    #[test]
+    fn test_verify_within_bounds_accepted() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10)]);
+        let opts = VerifyOptions { min_amount: Some(10),
+                                   max_amount: Some(100),
+                                   ..Default::default() };
+        let balances = verify_blockchain(&bc, &opts).unwrap();
+        assert_eq!(balances[&0xA], 90);
+        assert_eq!(balances[&0xB], 10);
+    }
+
+    // ****************************************************************
+    // Chain struct
+    // ****************************************************************
+
+    #[test]
+    fn test_chain_push_links_blocks() {
+        let mut chain = Chain::new();
+        chain.push(0, 0xA, 100);
+        chain.push(0xA, 0xB, 10);
+        chain.push(0xB, 0xA, 5);
+        assert_eq!(chain.blocks, make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10), (0xB, 0xA, 5)]));
+        assert_eq!(chain.verify(), Ok(()));
+    }
+
+    #[test]
+    fn test_chain_balances() {
+        let mut chain = Chain::new();
+        chain.push(0, 0xA, 100);
+        chain.push(0xA, 0xB, 10);
+        let balances = chain.balances().unwrap();
+        assert_eq!(balances[&0xA], 90);
+        assert_eq!(balances[&0xB], 10);
+    }
+
+    #[test]
+    fn test_chain_with_overspend_does_not_verify() {
+        let mut chain = Chain::new();
+        chain.push(0, 0xA, 100);
+        chain.push(0xA, 0xB, 101);
+        assert!(chain.verify().unwrap_err().starts_with("Line 1:"));
+        assert!(Chain::new().verify().is_ok());
+    }
+
+    // ****************************************************************
+    // get_hash_seeded() function
+    // ****************************************************************
+
+    #[test]
+    fn test_different_seeds_give_different_hashes() {
+        let b = Block { to_addr: 0xA, from_addr: 0, amount: 100, prev_hash: 0 };
+        assert!(get_hash_seeded(&b, Some(1)) != get_hash_seeded(&b, Some(2)));
+        assert!(get_hash_seeded(&b, Some(1)) != get_hash(&b));
+        assert_eq!(get_hash_seeded(&b, Some(1)), get_hash_seeded(&b, Some(1)));
+    }
+
+    #[test]
+    fn test_no_seed_same_as_get_hash() {
+        let b = Block { to_addr: 0xA, from_addr: 0, amount: 100, prev_hash: 0 };
+        assert_eq!(get_hash_seeded(&b, None), get_hash(&b));
+    }
+
+    #[test]
+    fn test_seeded_chain_only_verifies_with_same_seed() {
+        let mut output = Vec::new();
+        let seeded = VerifyOptions { hash_seed: Some(42), ..Default::default() };
+        let bc = make_blockchain(&mut "0\nA\n100\nA\nB\n50\n".as_bytes(), &mut output, &seeded);
+        let other = VerifyOptions { hash_seed: Some(43), ..Default::default() };
+        assert!(verify_blockchain(&bc, &seeded).is_ok());
+        assert!(verify_blockchain(&bc, &other).is_err());
+        assert!(verify_blockchain(&bc, &VerifyOptions::default()).is_err());
+    }
+
+    // ****************************************************************
+    // verify_blockchain() function - snapshots
+    // ****************************************************************
+
+    fn snapshot_path(name: &str) -> String {
+        env::temp_dir().join(format!("billcoin_{}_{}.snapshot", name, std::process::id()))
+            .to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_resume_from_snapshot_gives_same_balances() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 30), (0xB, 0xC, 10),
+                              (0, 0xC, 5), (0xC, 0xA, 15), (0xA, 0xB, 1), (0, 0xD, 7)]);
+        let file = snapshot_path("resume");
+        let full = verify_blockchain_with_flows(&bc, &VerifyOptions::default()).unwrap();
+
+        let snapshotting = VerifyOptions {
+            snapshot_every: Some(3),
+            snapshot_file: Some(file.clone()),
+            ..Default::default()
+        };
+        assert_eq!(verify_blockchain_with_flows(&bc, &snapshotting).unwrap(), full);
+        // The latest snapshot is after block 6 (index 5)
+        assert_eq!(read_snapshot(&file).unwrap().blocks, 6);
+
+        let resuming = VerifyOptions {
+            resume: true,
+            snapshot_file: Some(file.clone()),
+            ..Default::default()
+        };
+        assert_eq!(verify_blockchain_with_flows(&bc, &resuming).unwrap(), full);
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_round_trips() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 30)]);
+        let opts = VerifyOptions { flows: true, ..Default::default() };
+        let (balances, flows) = verify_blockchain_with_flows(&bc, &opts).unwrap();
+        let snapshot = Snapshot {
+            blocks: 2,
+            prev_hash: get_hash(&bc[1]),
+            total_minted: 100,
+            balances,
+            flows,
+        };
+        let file = snapshot_path("round_trip");
+        write_snapshot(&file, &snapshot).unwrap();
+        assert_eq!(read_snapshot(&file).unwrap(), snapshot);
+        // Without flows, only the balances are written
+        let snapshot = Snapshot { flows: HashMap::new(), ..snapshot };
+        write_snapshot(&file, &snapshot).unwrap();
+        assert_eq!(read_snapshot(&file).unwrap(), snapshot);
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_resume_rejects_snapshot_of_other_chain() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 30)]);
+        let other = make_chain(&[(0, 0xA, 99), (0xA, 0xB, 30)]);
+        let file = snapshot_path("other_chain");
+        let snapshotting = VerifyOptions {
+            snapshot_every: Some(1),
+            snapshot_file: Some(file.clone()),
+            ..Default::default()
+        };
+        verify_blockchain(&other, &snapshotting).unwrap();
+
+        let resuming = VerifyOptions { resume: true, snapshot_file: Some(file.clone()), ..Default::default() };
+        assert_eq!(verify_blockchain(&bc, &resuming).unwrap_err(),
+                   "Snapshot does not match this blockchain");
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    // ****************************************************************
+    // verify_blockchain() function - zero amounts
+    // ****************************************************************
+
+    #[test]
+    fn test_zero_amount_accepted_by_default() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 0)]);
+        assert!(verify_blockchain(&bc, &VerifyOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_zero_amount_rejected_when_strict() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 0)]);
+        let opts = VerifyOptions { reject_zero_amount: true, ..Default::default() };
+        assert_eq!(verify_blockchain(&bc, &opts).unwrap_err(),
+                   "Line 1: Account 0x0000000000000a tried to send 0 billcoins");
+    }
+
+    #[test]
+    fn test_zero_amount_mint_exemption() {
+        let bc = make_chain(&[(0, 0xA, 0), (0, 0xA, 100)]);
+        let strict = VerifyOptions { reject_zero_amount: true, ..Default::default() };
+        assert!(verify_blockchain(&bc, &strict).is_err());
+        let exempt = VerifyOptions { reject_zero_amount: true, exempt_mints: true, ..Default::default() };
+        assert!(verify_blockchain(&bc, &exempt).is_ok());
+    }
+
+    // ****************************************************************
+    // verify_blockchain() function - maximum supply
+    // ****************************************************************
+
+    #[test]
+    fn test_mints_up_to_max_supply_accepted() {
+        let bc = make_chain(&[(0, 0xA, 60), (0xA, 0xB, 10), (0, 0xB, 40)]);
+        let opts = VerifyOptions { max_supply: Some(100), ..Default::default() };
+        assert!(verify_blockchain(&bc, &opts).is_ok());
+    }
+
+    #[test]
+    fn test_mint_over_max_supply_rejected() {
+        let bc = make_chain(&[(0, 0xA, 60), (0xA, 0xB, 10), (0, 0xB, 40), (0, 0xC, 1)]);
+        let opts = VerifyOptions { max_supply: Some(100), ..Default::default() };
+        assert_eq!(verify_blockchain(&bc, &opts).unwrap_err(),
+                   "Line 3: Minting 1 billcoins would bring the total supply to 101, above the maximum supply of 100");
+    }
+
+    #[test]
+    fn test_parse_max_supply_option() {
+        let mut args = vec!["billcoin".to_string(), "--max-supply".to_string(), "21".to_string()];
+        assert_eq!(parse_verify_options(&mut args).unwrap().max_supply, Some(21));
+        let mut args = vec!["billcoin".to_string(), "--max-supply".to_string(), "lots".to_string()];
+        assert!(parse_verify_options(&mut args).is_err());
+    }
+
+    // ****************************************************************
+    // verify_blockchain() function - canonical mode
+    // ****************************************************************
+
+    #[test]
+    fn test_canonical_accepts_valid_chain() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10), (0xB, 0xC, 5)]);
+        let opts = VerifyOptions { canonical: true, ..Default::default() };
+        assert!(verify_blockchain(&bc, &opts).is_ok());
+    }
+
+    // Block 1 overspends, and block 2 has been tampered with after the
+    // fact.  Normal verification gives up at the overspend without ever
+    // looking at the hash of block 2; canonical mode catches the tampering.
+    #[test]
+    fn test_canonical_catches_tampering_after_other_error() {
+        let mut bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 500), (0xB, 0xC, 5)]);
+        bc[2].prev_hash ^= 1;
+
+        let r = verify_blockchain(&bc, &VerifyOptions::default());
+        assert!(r.unwrap_err().starts_with("Line 1: Account"));
+
+        let opts = VerifyOptions { canonical: true, ..Default::default() };
+        let r = verify_blockchain(&bc, &opts);
+        assert!(r.unwrap_err().starts_with("Line 2: Prev hash was expected to be"));
+    }
+
+    // Changing the amount of a block without recomputing the next block's
+    // prev_hash breaks the link to the next block.
+    #[test]
+    fn test_canonical_catches_modified_block() {
+        let mut bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10), (0xB, 0xC, 5)]);
+        bc[1].amount = 20;
+        assert!(check_prev_hashes(&bc, 0, None).unwrap_err().starts_with("Line 2:"));
     }
+
+    // ****************************************************************
+    // verify_blockchain() function - genesis hash
+    // ****************************************************************
+
+    #[test]
+    fn test_custom_genesis_verifies_with_matching_genesis() {
+        let bc = make_chain_from(0xB111, &[(0, 0xA, 100), (0xA, 0xB, 10)]);
+        let opts = VerifyOptions { genesis: 0xB111, ..Default::default() };
+        assert!(verify_blockchain(&bc, &opts).is_ok());
+
+        let opts = VerifyOptions { genesis: 0xB111, canonical: true, ..Default::default() };
+        assert!(verify_blockchain(&bc, &opts).is_ok());
+    }
+
+    #[test]
+    fn test_custom_genesis_fails_with_default_genesis() {
+        let bc = make_chain_from(0xB111, &[(0, 0xA, 100), (0xA, 0xB, 10)]);
+        let r = verify_blockchain(&bc, &VerifyOptions::default());
+        assert_eq!(r.unwrap_err(),
+                   "Line 0: Prev hash was expected to be 0x00000000000000, not 0x0000000000b111");
+    }
+
+    #[test]
+    fn test_parse_genesis_option() {
+        let mut args = vec!["billcoin".to_string(), "--genesis".to_string(), "0xb111".to_string()];
+        assert_eq!(parse_verify_options(&mut args).unwrap().genesis, 0xB111);
+        assert_eq!(args, vec!["billcoin".to_string()]);
+    }
+
+    // ****************************************************************
+    // convert_hex() function
+    // ****************************************************************
+
+    #[test]
+    fn test_convert_hex_prefixes() {
+        assert_eq!(convert_hex("0x1F".to_string()), Ok(0x1F));
+        assert_eq!(convert_hex("0X1F".to_string()), Ok(0x1F));
+        assert_eq!(convert_hex("1F".to_string()), Ok(0x1F));
+    }
+
+    #[test]
+    fn test_convert_hex_doubled_prefix_rejected() {
+        assert_eq!(convert_hex("0x0x1".to_string()),
+                   Err("Invalid hex value \"0x0x1\": 0x prefix given more than once".to_string()));
+        assert!(convert_hex("0X0x1".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_convert_hex_empty_rejected() {
+        assert_eq!(convert_hex("0x".to_string()),
+                   Err("Invalid hex value \"0x\": no digits".to_string()));
+        assert!(convert_hex("".to_string()).is_err());
+        assert!(convert_hex("0xZZ".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_generate_stops_on_bad_address() {
+        let mut output = Vec::new();
+        let bc = make_blockchain(&mut "0\nA\n100\n0x0xA\nB\n5\n".as_bytes(), &mut output, &VerifyOptions::default());
+        assert_eq!(bc, make_chain(&[(0, 0xA, 100)]));
+        assert!(String::from_utf8(output).unwrap().contains("more than once"));
+    }
+
+    // ****************************************************************
+    // convert_decimal() function
+    // ****************************************************************
+
+    #[test]
+    fn test_convert_decimal() {
+        assert_eq!(convert_decimal("31".to_string()), Ok(31));
+        assert_eq!(convert_decimal("18446744073709551615".to_string()), Ok(u64::MAX));
+        assert_eq!(convert_decimal("abc".to_string()),
+                   Err("Invalid decimal value \"abc\"".to_string()));
+        assert!(convert_decimal("".to_string()).is_err());
+        assert!(convert_decimal("-1".to_string()).is_err());
+        assert!(convert_decimal("0x1F".to_string()).is_err());
+    }
+
+    // ****************************************************************
+    // pretty_print_blockchain(), print_blockchain() and print_results()
+    // functions
+    // ****************************************************************
+
+    #[test]
+    fn test_pretty_print_blockchain() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10)]);
+        let mut output = Vec::new();
+        pretty_print_blockchain(&bc, false, &mut output);
+        assert_eq!(String::from_utf8(output).unwrap(),
+                   format!("Block: 0, 0x00000000000000 sent 100 billcoins to 0x0000000000000a (Prev Hash: 0x00000000000000)\n\
+                            Block: 1, 0x0000000000000a sent 10 billcoins to 0x0000000000000b (Prev Hash: {:#016x})\n",
+                           bc[1].prev_hash));
+    }
+
+    #[test]
+    fn test_pretty_print_empty_blockchain() {
+        let mut output = Vec::new();
+        pretty_print_blockchain(&Vec::new(), false, &mut output);
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_print_blockchain_csv() {
+        let bc = make_chain(&[(0, 0xA, 100)]);
+        let mut output = Vec::new();
+        print_blockchain(&bc, true, false, &mut output);
+        assert_eq!(String::from_utf8(output).unwrap(),
+                   "index,from,amount,to,prev_hash\n0,0x00000000000000,100,0x0000000000000a,0x00000000000000\n");
+    }
+
+    #[test]
+    fn test_print_results_skips_magic_and_empty_accounts() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 100)]);
+        let mut output = Vec::new();
+        print_results(verify_blockchain(&bc, &VerifyOptions::default()).unwrap(), false, &mut output);
+        assert_eq!(String::from_utf8(output).unwrap(), "0x0000000000000b : 100 billcoins\n");
+    }
+
+    #[test]
+    fn test_format_amount() {
+        assert_eq!(format_amount(1000000, false), "1000000");
+        assert_eq!(format_amount(0, true), "0");
+        assert_eq!(format_amount(999, true), "999");
+        assert_eq!(format_amount(1000, true), "1,000");
+        assert_eq!(format_amount(1000000, true), "1,000,000");
+        assert_eq!(format_amount(12345678, true), "12,345,678");
+        assert_eq!(format_amount(u64::MAX, true), "18,446,744,073,709,551,615");
+    }
+
+    #[test]
+    fn test_human_amounts_in_printers() {
+        let bc = make_chain(&[(0, 0xA, 1234567)]);
+        let mut output = Vec::new();
+        pretty_print_blockchain(&bc, true, &mut output);
+        print_results(verify_blockchain(&bc, &VerifyOptions::default()).unwrap(), true, &mut output);
+        assert_eq!(String::from_utf8(output).unwrap(),
+                   "Block: 0, 0x00000000000000 sent 1,234,567 billcoins to 0x0000000000000a (Prev Hash: 0x00000000000000)\n\
+                    0x0000000000000a : 1,234,567 billcoins\n");
+    }
+
+    // ****************************************************************
+    // print_blockchain() and read_file() functions - CSV header
+    // ****************************************************************
+
+    // Write `contents` to a fresh file in the temp directory, returning
+    // its name.
+
+    fn write_temp_file(name: &str, contents: &str) -> String {
+        let path = env::temp_dir().join(format!("billcoin_{}_{}.csv", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_csv_with_header_round_trips() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10), (0xB, 0xC, 5)]);
+        let mut csv = vec![CSV_HEADER.to_string()];
+        for (j, b) in bc.iter().enumerate() {
+            csv.push(format_block_csv(j, b, false));
+        }
+        let f = write_temp_file("header", &csv.join("\n"));
+
+        assert_eq!(read_file(&f, &VerifyOptions::default()), Ok(Chain { blocks: bc }));
+        std::fs::remove_file(&f).unwrap();
+    }
+
+    #[test]
+    fn test_csv_without_header_still_reads() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10)]);
+        let csv: Vec<String> = bc.iter().enumerate().map(|(j, b)| format_block_csv(j, b, false)).collect();
+        let f = write_temp_file("no_header", &csv.join("\n"));
+
+        assert_eq!(read_file(&f, &VerifyOptions::default()), Ok(Chain { blocks: bc }));
+        std::fs::remove_file(&f).unwrap();
+    }
+
+    // ****************************************************************
+    // read_file() function - field validation
+    // ****************************************************************
+
+    #[test]
+    fn test_read_file_trims_fields() {
+        let f = write_temp_file("trimmed", " 0 , 0x0 ,\t100, 0xA ,0x0 \r");
+        let expected = Block { from_addr: 0, amount: 100, to_addr: 0xA, prev_hash: 0 };
+        assert_eq!(read_file(&f, &VerifyOptions::default()), Ok(Chain { blocks: vec![expected] }));
+        std::fs::remove_file(&f).unwrap();
+    }
+
+    #[test]
+    fn test_read_file_embedded_whitespace_rejected() {
+        let f = write_temp_file("embedded_tab", "0,0x0,100,0xA,0x0\n1,0xA,5,0x\tB,0x0");
+        assert_eq!(read_file(&f, &VerifyOptions::default()).unwrap_err(),
+                   "Line 1: Field to (\"0x\\tB\") contains unexpected character '\\t'");
+        std::fs::remove_file(&f).unwrap();
+
+        let f = write_temp_file("embedded_space", "0,0x0,1 00,0xA,0x0");
+        assert_eq!(read_file(&f, &VerifyOptions::default()).unwrap_err(),
+                   "Line 0: Field amount (\"1 00\") contains unexpected character ' '");
+        std::fs::remove_file(&f).unwrap();
+    }
+
+    #[test]
+    fn test_read_file_non_hex_rejected() {
+        let f = write_temp_file("non_hex", "0,0xG,100,0xA,0x0");
+        assert_eq!(read_file(&f, &VerifyOptions::default()).unwrap_err(),
+                   "Line 0: Field from (\"0xG\") contains unexpected character 'G'");
+        std::fs::remove_file(&f).unwrap();
+
+        let f = write_temp_file("non_ascii", "0,0x0,100,0xé,0x0");
+        assert!(read_file(&f, &VerifyOptions::default()).unwrap_err().starts_with("Line 0: Field to"));
+        std::fs::remove_file(&f).unwrap();
+
+        let f = write_temp_file("double_prefix", "0,0x0,100,0x0xA,0x0");
+        assert_eq!(read_file(&f, &VerifyOptions::default()).unwrap_err(),
+                   "Line 0: Field to: Invalid hex value \"0x0xA\": 0x prefix given more than once");
+        std::fs::remove_file(&f).unwrap();
+    }
+
+    #[test]
+    fn test_read_file_check_index_correct() {
+        let check_index = VerifyOptions { check_index: true, ..Default::default() };
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10), (0xB, 0xA, 5)]);
+        let mut output = Vec::new();
+        print_blockchain(&bc, true, false, &mut output);
+        let f = write_temp_file("good_index", &String::from_utf8(output).unwrap());
+        assert_eq!(read_file(&f, &check_index), Ok(Chain { blocks: bc }));
+        std::fs::remove_file(&f).unwrap();
+    }
+
+    #[test]
+    fn test_read_file_check_index_misnumbered() {
+        let check_index = VerifyOptions { check_index: true, ..Default::default() };
+        let f = write_temp_file("bad_index", "0,0x0,100,0xA,0x0\n1,0x0,100,0xA,0x0\n1,0x0,100,0xA,0x0\n5,0x0,1,0xA,0x0");
+        assert_eq!(read_file(&f, &check_index).unwrap_err(),
+                   "Line 2: Index 1 does not match the block's position 2");
+        // Without --check-index, the index is ignored
+        assert_eq!(read_file(&f, &VerifyOptions::default()).unwrap().blocks.len(), 4);
+        std::fs::remove_file(&f).unwrap();
+    }
+
+    #[test]
+    fn test_read_file_hex_amounts_same_as_decimal() {
+        let decimal = write_temp_file("decimal_amounts", "0,0x0,255,0xA,0x0\n1,0xA,16,0xB,0xba5eba11");
+        let hex = write_temp_file("hex_amounts", "0,0x0,0xff,0xA,0x0\n1,0xA,10,0xB,0xba5eba11");
+        let hex_opts = VerifyOptions { hex_amounts: true, ..Default::default() };
+        let bc = read_file(&hex, &hex_opts).unwrap().blocks;
+        assert_eq!(bc, read_file(&decimal, &VerifyOptions::default()).unwrap().blocks);
+        assert_eq!(bc[0].amount, 255);
+        assert_eq!(bc[1].amount, 16);
+        std::fs::remove_file(&decimal).unwrap();
+        std::fs::remove_file(&hex).unwrap();
+    }
+
+    #[test]
+    fn test_hex_amounts_chain_verifies_like_decimal() {
+        let bc = make_chain(&[(0, 0xA, 255), (0xA, 0xB, 16)]);
+        let decimal: Vec<String> = bc.iter().enumerate().map(|(j, b)| format_block_csv(j, b, false)).collect();
+        let hex: Vec<String> = bc.iter().enumerate()
+            .map(|(j, b)| format!("{},{:#x},{:#x},{:#x},{:#x}", j, b.from_addr, b.amount, b.to_addr, b.prev_hash))
+            .collect();
+        let decimal = write_temp_file("decimal_chain", &decimal.join("\n"));
+        let hex = write_temp_file("hex_chain", &hex.join("\n"));
+
+        let (code, decimal_output) = run_with(&[&decimal], "");
+        assert_eq!(code, 0);
+        let (code, hex_output) = run_with(&["--hex-amounts", &hex], "");
+        assert_eq!(code, 0);
+        assert_eq!(hex_output.lines().last(), decimal_output.lines().last());
+
+        // Without --hex-amounts, "0xff" is not a valid amount
+        let (code, _) = run_with(&[&hex], "");
+        assert_eq!(code, 1);
+        std::fs::remove_file(&decimal).unwrap();
+        std::fs::remove_file(&hex).unwrap();
+    }
+
+    #[test]
+    fn test_generate_hex_amounts() {
+        let mut output = Vec::new();
+        let opts = VerifyOptions { hex_amounts: true, ..Default::default() };
+        let bc = make_blockchain(&mut "0\nA\nff\nx\n".as_bytes(), &mut output, &opts);
+        assert_eq!(bc, make_chain(&[(0, 0xA, 255)]));
+    }
+
+    #[test]
+    fn test_generate_bad_decimal_amount() {
+        let mut output = Vec::new();
+        let bc = make_blockchain(&mut "0\nA\nabc\n".as_bytes(), &mut output, &VerifyOptions::default());
+        assert!(bc.is_empty());
+        assert!(String::from_utf8(output).unwrap().ends_with("Invalid decimal value \"abc\"\n"));
+    }
+
+    #[test]
+    fn test_generate_hex_amounts_reads_back() {
+        let opts = VerifyOptions { hex_amounts: true, ..Default::default() };
+        let bc = make_blockchain(&mut "0\nA\n64\nA\nB\nff\nx\n".as_bytes(), &mut Vec::new(), &opts);
+        let mut csv = Vec::new();
+        print_blockchain(&bc, false, true, &mut csv);
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("0,0x00000000000000,0x64,"));
+
+        // 0x64 is read back as 100, not as 0x100
+        let f = write_temp_file("generated_hex_amounts", &csv);
+        let read_back = read_file(&f, &opts).unwrap();
+        assert_eq!(read_back.blocks, bc);
+        assert_eq!(read_back.blocks[0].amount, 100);
+        std::fs::remove_file(&f).unwrap();
+    }
+
+    #[test]
+    fn test_read_file_wrong_number_of_fields() {
+        let f = write_temp_file("short_line", "0,0x0,100,0xA");
+        assert_eq!(read_file(&f, &VerifyOptions::default()).unwrap_err(), "Line 0: Expected 5 fields, found 4");
+        std::fs::remove_file(&f).unwrap();
+    }
+
+    // ****************************************************************
+    // diff_chains() function
+    // ****************************************************************
+
+    #[test]
+    fn test_diff_chains_identical() {
+        let a = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10)]);
+        let b = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10)]);
+        assert_eq!(diff_chains(&a, &b), None);
+        assert_eq!(diff_chains(&[], &[]), None);
+    }
+
+    #[test]
+    fn test_diff_chains_one_field() {
+        let a = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10)]);
+        let mut b = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10)]);
+        b[1].amount = 11;
+        assert_eq!(diff_chains(&a, &b), Some("Block 1: amount differs (10 vs 11)".to_string()));
+
+        let b = make_chain(&[(0, 0xA, 100), (0xA, 0xC, 10)]);
+        assert_eq!(diff_chains(&a, &b),
+                   Some("Block 1: to differs (0x0000000000000b vs 0x0000000000000c)".to_string()));
+    }
+
+    #[test]
+    fn test_diff_chains_different_lengths() {
+        let a = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10)]);
+        let b = make_chain(&[(0, 0xA, 100)]);
+        assert_eq!(diff_chains(&a, &b),
+                   Some("Chains differ in length (2 vs 1 blocks); the first 1 blocks are identical".to_string()));
+        assert_eq!(diff_chains(&b, &a),
+                   Some("Chains differ in length (1 vs 2 blocks); the first 1 blocks are identical".to_string()));
+    }
+
+    // ****************************************************************
+    // run() function
+    // ****************************************************************
+
+    #[test]
+    fn test_run_verify_valid_chain() {
+        let (code, output) = run_with(&["5_small_works.csv"], "");
+        assert_eq!(code, 0);
+        assert!(output.ends_with("Blockchain valid!\n"));
+    }
+
+    #[test]
+    fn test_run_verify_invalid_chain() {
+        let (code, output) = run_with(&["4_bad_prev_hash.csv"], "");
+        assert_eq!(code, 1);
+        // The blocks are pretty-printed before the result
+        assert!(output.starts_with("Block: 0, "));
+        assert!(output.ends_with("\nBlockchain invalid: Line 4: Prev hash was expected to be 0x7bbb03eab47979c8, not 0x123456789abcdef\n"));
+    }
+
+    #[test]
+    fn test_run_too_many_arguments() {
+        let (code, output) = run_with(&["a.csv", "b.csv"], "");
+        assert_eq!(code, 1);
+        assert!(output.starts_with("Usage:"));
+    }
+
+    #[test]
+    fn test_run_bad_option_value() {
+        let (code, output) = run_with(&["5_small_works.csv", "--min-amount", "lots"], "");
+        assert_eq!(code, 1);
+        assert!(output.starts_with("Error: Invalid minimum amount: lots"));
+    }
+
+    #[test]
+    fn test_run_generate() {
+        let (code, output) = run_with(&[], "0\nA\n100\nA\nB\n50\nx\n");
+        assert_eq!(code, 0);
+        assert!(output.contains("Block Number: 0"));
+        assert!(output.contains("Block Number: 1"));
+        assert!(output.contains("Block Number: 2"));
+        assert!(!output.contains("Block Number: 3"));
+    }
+
+    #[test]
+    fn test_generate_stops_at_eof() {
+        let mut output = Vec::new();
+        let bc = make_blockchain(&mut "0\nA\n100\n".as_bytes(), &mut output, &VerifyOptions::default());
+        assert_eq!(bc, make_chain(&[(0, 0xA, 100)]));
+    }
+
+    #[test]
+    fn test_generate_stops_at_eof_mid_block() {
+        let mut output = Vec::new();
+        let bc = make_blockchain(&mut "0\nA\n100\nA\nB".as_bytes(), &mut output, &VerifyOptions::default());
+        assert_eq!(bc, make_chain(&[(0, 0xA, 100)]));
+        let bc = make_blockchain(&mut "".as_bytes(), &mut output, &VerifyOptions::default());
+        assert!(bc.is_empty());
+    }
+
+    #[test]
+    fn test_run_empty_chain() {
+        let f = write_temp_file("empty", "");
+        let (code, output) = run_with(&[&f], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, "Empty chain (0 blocks)\nBlockchain valid!\n");
+        std::fs::remove_file(&f).unwrap();
+    }
+
+    #[test]
+    fn test_run_empty_chain_rejected() {
+        let f = write_temp_file("empty_strict", "");
+        let (code, output) = run_with(&["--reject-empty", &f], "");
+        assert_eq!(code, 1);
+        assert!(output.ends_with("Blockchain invalid: Empty chain (0 blocks)\n"));
+
+        let (code, output) = run_with(&["--reject-empty", "--compact", &f], "");
+        assert_eq!(code, 1);
+        assert_eq!(output, "INVALID Empty chain (0 blocks)\n");
+        std::fs::remove_file(&f).unwrap();
+    }
+
+    #[test]
+    fn test_replay_steps_block_by_block() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 30), (0xB, 0xA, 10)]);
+        let mut output = Vec::new();
+        let r = replay_blockchain(&bc, &VerifyOptions::default(), &mut "\n\n\n".as_bytes(), &mut output);
+        assert_eq!(r.unwrap(), verify_blockchain(&bc, &VerifyOptions::default()).unwrap());
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines,
+                   vec!["Press Enter to apply block 0: 0x00000000000000 sends 100 to 0x0000000000000a > ",
+                        "  0x0000000000000a: 0 -> 100",
+                        "Press Enter to apply block 1: 0x0000000000000a sends 30 to 0x0000000000000b > ",
+                        "  0x0000000000000a: 100 -> 70",
+                        "  0x0000000000000b: 0 -> 30",
+                        "Press Enter to apply block 2: 0x0000000000000b sends 10 to 0x0000000000000a > ",
+                        "  0x0000000000000b: 30 -> 20",
+                        "  0x0000000000000a: 70 -> 80"]);
+    }
+
+    #[test]
+    fn test_replay_stops_at_invalid_block() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 300), (0xB, 0xA, 10)]);
+        let mut output = Vec::new();
+        let r = replay_blockchain(&bc, &VerifyOptions::default(), &mut "\n\n\n".as_bytes(), &mut output);
+        assert!(r.unwrap_err().starts_with("Line 1:"));
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.ends_with("Block 1 is invalid!\n"));
+        assert!(!output.contains("block 2"));
+    }
+
+    #[test]
+    fn test_replay_hash_only() {
+        // The overspend is not checked, only the hash chain
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 300)]);
+        let opts = VerifyOptions { hash_only: true, ..Default::default() };
+        let mut output = Vec::new();
+        let r = replay_blockchain(&bc, &opts, &mut "\n\n".as_bytes(), &mut output);
+        assert_eq!(r.unwrap(), HashMap::new());
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.ends_with("  0x0000000000000b: 0 -> 0\n"), "{}", output);
+    }
+
+    #[test]
+    fn test_run_replay() {
+        let (code, output) = run_with(&["--replay", "5_small_works.csv"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output.matches("Press Enter").count(), 6);
+        assert!(output.ends_with("Blockchain valid!\n"));
+    }
+
+    #[test]
+    fn test_run_diff_chains() {
+        let (code, output) = run_with(&["diff-chains", "5_small_works.csv", "5_small_works.csv"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, "identical\n");
+
+        let (code, output) = run_with(&["diff-chains", "5_small_works.csv", "4_bad_prev_hash.csv"], "");
+        assert_eq!(code, 1);
+        assert_eq!(output, "Block 0: amount differs (100 vs 10000)\n");
+    }
+
+    #[test]
+    fn test_format_block_canonical() {
+        let b = Block { from_addr: 0xAB, to_addr: 0xFFFF_FFFF_FFFF_FFFF, amount: 42, prev_hash: 0 };
+        assert_eq!(format_block_canonical(3, &b),
+                   "3,0x00000000000000ab,42,0xffffffffffffffff,0x0000000000000000");
+    }
+
+    #[test]
+    fn test_run_canonicalize_is_idempotent() {
+        let once = write_temp_file("canonical_once", "");
+        let twice = write_temp_file("canonical_twice", "");
+        let (code, output) = run_with(&["5_small_works.csv", "canonicalize", &once], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, format!("Wrote 6 blocks to {}\n", once));
+        let (code, _) = run_with(&[&once, "canonicalize", &twice], "");
+        assert_eq!(code, 0);
+        let first = fs::read_to_string(&once).unwrap();
+        assert_eq!(first, fs::read_to_string(&twice).unwrap());
+        // Still the same blocks as the original file
+        let opts = VerifyOptions::default();
+        assert_eq!(read_file(&once, &opts), read_file(&"5_small_works.csv".to_string(), &opts));
+        std::fs::remove_file(&once).unwrap();
+        std::fs::remove_file(&twice).unwrap();
+    }
+
+    #[test]
+    fn test_verify_all_reports_every_error() {
+        // Block 2 overspends, and block 4 has a bad prev_hash.  Block 3 is
+        // fine, since block 2 is skipped and 0xB still has 10.
+        let mut bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10), (0xB, 0xC, 11),
+                                  (0xB, 0xC, 10), (0xA, 0xD, 5)]);
+        bc[4].prev_hash ^= 1;
+        let errors = verify_all(&bc, &VerifyOptions::default());
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0], "Line 2: Account 0x0000000000000b only has 10 billcoins; it cannot send 11");
+        assert!(errors[1].starts_with("Line 4: Prev hash was expected to be "));
+        assert_eq!(verify_blockchain(&bc, &VerifyOptions::default()).unwrap_err(), errors[0]);
+    }
+
+    #[test]
+    fn test_verify_all_valid_chain() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10)]);
+        assert!(verify_all(&bc, &VerifyOptions::default()).is_empty());
+        let opts = VerifyOptions { reject_empty: true, ..Default::default() };
+        assert_eq!(verify_all(&[], &opts), vec!["Empty chain (0 blocks)".to_string()]);
+    }
+
+    #[test]
+    fn test_verify_all_honours_hash_only_and_canonical() {
+        let mut bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10), (0xB, 0xC, 11),
+                                  (0xB, 0xC, 10), (0xA, 0xD, 5)]);
+        bc[4].prev_hash ^= 1;
+        let opts = VerifyOptions { hash_only: true, ..Default::default() };
+        let errors = verify_all(&bc, &opts);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("Line 4: Prev hash was expected to be "));
+        // The bad prev_hash comes first, and is only listed once
+        let opts = VerifyOptions { canonical: true, ..Default::default() };
+        let errors = verify_all(&bc, &opts);
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("Line 4: Prev hash was expected to be "));
+        assert!(errors[1].starts_with("Line 2: "));
+        assert_eq!(verify_blockchain(&bc, &opts).unwrap_err(), errors[0]);
+    }
+
+    #[test]
+    fn test_verify_all_honours_resume() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10), (0xB, 0xC, 11)]);
+        let file = snapshot_path("verify_all");
+        let opts = VerifyOptions { resume: true, snapshot_file: Some(file.clone()), ..Default::default() };
+        // Resuming after block 1 skips the blocks already verified
+        let snapshot = Snapshot {
+            blocks: 2,
+            prev_hash: get_hash(&bc[1]),
+            balances: [(0xA, 90), (0xB, 10)].iter().cloned().collect(),
+            ..Default::default()
+        };
+        write_snapshot(&file, &snapshot).unwrap();
+        let errors = verify_all(&bc, &opts);
+        assert_eq!(errors, vec![verify_blockchain(&bc, &opts).unwrap_err()]);
+        assert!(errors[0].starts_with("Line 2: "));
+        // A snapshot for a longer chain does not match
+        write_snapshot(&file, &Snapshot { blocks: 4, ..snapshot }).unwrap();
+        assert_eq!(verify_all(&bc, &opts), vec!["Snapshot does not match this blockchain".to_string()]);
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_run_all_errors() {
+        let (code, output) = run_with(&["--all-errors", "5_small_works.csv"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, "Blockchain valid!\n");
+        let (code, output) = run_with(&["--all-errors", "4_bad_prev_hash.csv"], "");
+        assert_eq!(code, 1);
+        // Block 4 is skipped, so the account it sent to has nothing to
+        // send in block 5
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].starts_with("Line 4: Prev hash"));
+        assert_eq!(lines[1], "Line 5: Account 0x000006639ad72e has 0 billcoins; it cannot send 8");
+        assert_eq!(lines[2], "Blockchain invalid: 2 problems found");
+    }
+
+    // Format blocks as the lines of a CSV file
+    fn chain_csv(bc: &[Block]) -> String {
+        bc.iter().enumerate().map(|(j, b)| format_block_csv(j, b, false) + "\n").collect()
+    }
+
+    #[test]
+    fn test_verify_stream_valid_chain() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10), (0xB, 0xC, 5)]);
+        let csv = format!("{}\n{}", CSV_HEADER, chain_csv(&bc));
+        let mut output = Vec::new();
+        let (balances, _) = verify_stream(&mut csv.as_bytes(), &VerifyOptions::default(), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "block 0 ok\nblock 1 ok\nblock 2 ok\n");
+        assert_eq!(balances, verify_blockchain(&bc, &VerifyOptions::default()).unwrap());
+    }
+
+    #[test]
+    fn test_verify_stream_stops_at_first_error() {
+        // Block 2 overspends, and the line after it would not even parse -
+        // but it is never read
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10), (0xB, 0xC, 11)]);
+        let csv = chain_csv(&bc) + "not a block\n";
+        let mut input = std::io::BufReader::with_capacity(1, csv.as_bytes());
+        let mut output = Vec::new();
+        let result = verify_stream(&mut input, &VerifyOptions::default(), &mut output);
+        assert_eq!(result.unwrap_err(), "Line 2: Account 0x0000000000000b only has 10 billcoins; it cannot send 11");
+        assert_eq!(String::from_utf8(output).unwrap(), "block 0 ok\nblock 1 ok\n");
+        assert_eq!(input.lines().next().unwrap().unwrap(), "not a block");
+    }
+
+    #[test]
+    fn test_verify_stream_errors_give_line_number() {
+        // With a header, block 2 is on line 3
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10), (0xB, 0xC, 11)]);
+        let csv = format!("{}\n{}", CSV_HEADER, chain_csv(&bc));
+        let mut output = Vec::new();
+        let result = verify_stream(&mut csv.as_bytes(), &VerifyOptions::default(), &mut output);
+        assert_eq!(result.unwrap_err(), "Line 3: Account 0x0000000000000b only has 10 billcoins; it cannot send 11");
+        assert_eq!(String::from_utf8(output).unwrap(), "block 0 ok\nblock 1 ok\n");
+    }
+
+    #[test]
+    fn test_run_verify_stdin_stream() {
+        let csv = fs::read_to_string("4_bad_prev_hash.csv").unwrap();
+        let (code, output) = run_with(&["--verify-stdin-stream"], &csv);
+        assert_eq!(code, 1);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[..4], ["block 0 ok", "block 1 ok", "block 2 ok", "block 3 ok"]);
+        assert!(lines[4].starts_with("Blockchain invalid: Line 4: Prev hash"));
+
+        let csv = fs::read_to_string("5_small_works.csv").unwrap();
+        let (code, output) = run_with(&["--verify-stdin-stream"], &csv);
+        assert_eq!(code, 0);
+        assert!(output.ends_with("block 5 ok\nBlockchain valid!\n"));
+    }
+
+    #[test]
+    fn test_hash_only_skips_balance_checks() {
+        // 0xB sends more than it has, but every prev_hash is right
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10), (0xB, 0xC, 11)]);
+        assert!(verify_blockchain(&bc, &VerifyOptions::default()).is_err());
+        let opts = VerifyOptions { hash_only: true, ..Default::default() };
+        assert_eq!(verify_blockchain(&bc, &opts), Ok(HashMap::new()));
+    }
+
+    #[test]
+    fn test_hash_only_catches_bad_prev_hash() {
+        let opts = VerifyOptions { hash_only: true, ..Default::default() };
+        let mut bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10)]);
+        bc[1].prev_hash ^= 1;
+        assert!(verify_blockchain(&bc, &opts).unwrap_err().starts_with("Line 1: Prev hash"));
+    }
+
+    #[test]
+    fn test_run_hash_only() {
+        let (code, _) = run_with(&["1_sending_more_than_has.csv"], "");
+        assert_eq!(code, 1);
+        let (code, output) = run_with(&["--hash-only", "1_sending_more_than_has.csv"], "");
+        assert_eq!(code, 0);
+        assert!(output.ends_with("Blockchain valid!\n"));
+    }
+
+    #[test]
+    fn test_merkle_root_is_stable() {
+        let txs = [(0, 0xA, 100), (0xA, 0xB, 10), (0xB, 0xC, 5)];
+        let bc = make_chain(&txs);
+        assert_eq!(merkle_root(&bc, None), merkle_root(&make_chain(&txs), None));
+        assert_eq!(merkle_root(&[], None), 0);
+        let b = &bc[0];
+        assert_eq!(merkle_root(&bc[..1], None),
+                   stable_hash(&[b.to_addr, b.from_addr, b.amount, b.prev_hash], None));
+    }
+
+    #[test]
+    fn test_stable_hash_known_values() {
+        // FNV-1a of nothing is just the offset basis
+        assert_eq!(stable_hash(&[], None), FNV_OFFSET_BASIS);
+        assert_eq!(stable_hash(&[0], None), 0xa8c7f832281a39c5);
+        assert_eq!(stable_hash(&[1], Some(2)), 0x072184407c3a4ac6);
+        assert_ne!(stable_hash(&[1, 2], None), stable_hash(&[2, 1], None));
+    }
+
+    #[test]
+    fn test_merkle_root_known_value() {
+        // These blocks have fixed prev_hashes (rather than ones from
+        // get_hash()), so the root must never change
+        let bc = [Block { to_addr: 0xA, from_addr: 0, amount: 100, prev_hash: 0 },
+                  Block { to_addr: 0xB, from_addr: 0xA, amount: 10, prev_hash: 0x1234 },
+                  Block { to_addr: 0xC, from_addr: 0xB, amount: 5, prev_hash: 0x5678 }];
+        assert_eq!(merkle_root(&bc, None), 0xd2ed22f46c4b5cb9);
+    }
+
+    #[test]
+    fn test_merkle_root_changes_with_any_block() {
+        let txs = [(0, 0xA, 100), (0xA, 0xB, 10), (0xB, 0xC, 5)];
+        let bc = make_chain(&txs);
+        let root = merkle_root(&bc, None);
+        for j in 0..bc.len() {
+            let mut changed = make_chain(&txs);
+            changed[j].amount += 1;
+            assert_ne!(merkle_root(&changed, None), root, "block {}", j);
+        }
+        assert_ne!(merkle_root(&bc, Some(1)), root);
+    }
+
+    #[test]
+    fn test_run_root() {
+        let (code, first) = run_with(&["5_small_works.csv", "root"], "");
+        assert_eq!(code, 0);
+        assert!(first.starts_with("Merkle root: 0x"));
+        let (_, second) = run_with(&["5_small_works.csv", "root"], "");
+        assert_eq!(first, second);
+
+        let (code, output) = run_with(&["4_bad_prev_hash.csv", "root"], "");
+        assert_eq!(code, 1);
+        assert!(output.starts_with("Blockchain invalid: "));
+    }
+
+    #[test]
+    fn test_run_only_errors_valid_chain() {
+        let (code, output) = run_with(&["--only-errors", "5_small_works.csv"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, "Blockchain valid!\n");
+    }
+
+    #[test]
+    fn test_run_only_errors_invalid_chain() {
+        let (code, output) = run_with(&["--only-errors", "4_bad_prev_hash.csv"], "");
+        assert_eq!(code, 1);
+        let lines: Vec<&str> = output.lines().collect();
+        // Blocks 0 to 4 are printed, and then the error
+        assert_eq!(lines.len(), 6);
+        assert!(lines[0].starts_with("Block: 0, "));
+        assert!(lines[4].starts_with("Block: 4, "));
+        assert!(lines[5].starts_with("Blockchain invalid: Line 4: "));
+    }
+
+    #[test]
+    fn test_first_invalid_block() {
+        let bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 10), (0xB, 0xC, 11), (0, 0xD, 1)]);
+        assert_eq!(first_invalid_block(&bc, &VerifyOptions::default()), Some(2));
+        let bc = make_chain(&[(0, 0xA, 100)]);
+        assert_eq!(first_invalid_block(&bc, &VerifyOptions::default()), None);
+    }
+
+    #[test]
+    fn test_first_invalid_block_hash_only() {
+        let mut bc = make_chain(&[(0, 0xA, 100), (0xA, 0xB, 500), (0, 0xC, 1), (0, 0xD, 1)]);
+        let opts = VerifyOptions { hash_only: true, ..Default::default() };
+        assert_eq!(first_invalid_block(&bc, &opts), None);
+        bc[2].amount = 2;
+        assert_eq!(first_invalid_block(&bc, &opts), Some(3));
+        assert_eq!(first_invalid_block(&bc, &VerifyOptions::default()), Some(1));
+    }
+
+    #[test]
+    fn test_run_compact_valid_chain() {
+        let (code, output) = run_with(&["--compact", "5_small_works.csv"], "");
+        assert_eq!(code, 0);
+        assert_eq!(output, "VALID 6 6\n");
+    }
+
+    #[test]
+    fn test_run_compact_invalid_chain() {
+        let (code, output) = run_with(&["4_bad_prev_hash.csv", "--compact"], "");
+        assert_eq!(code, 1);
+        assert_eq!(output, "INVALID Line 4: Prev hash was expected to be 0x7bbb03eab47979c8, not 0x123456789abcdef\n");
+    }
+
 }